@@ -0,0 +1,177 @@
+//! Render a sequence of traffic count snapshots as PNG frames and an animated GIF.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::bounding_box::BoundingBox;
+use crate::network::Network;
+
+
+/// Collects snapshots of the network's traffic counts that are rendered as frames later on.
+///
+/// Rendering is deferred until all snapshots are known, so that every frame can be normalized
+/// with the same maximum count. Only the counts that changed since the previous frame are kept.
+pub struct FrameRecorder {
+    /// Traffic counts of the previous frame
+    previous: Vec<usize>,
+    /// Edge indices and new counts of the edges that changed in each frame
+    changes: Vec<Vec<(usize, usize)>>,
+    /// Highest count of all frames
+    max_number: usize,
+}
+
+impl FrameRecorder {
+    pub fn new() -> Self {
+        FrameRecorder {
+            previous: vec![],
+            changes: vec![],
+            max_number: 0,
+        }
+    }
+
+    /// Store the current traffic counts of the network as a new frame.
+    pub fn record(&mut self, net: &Network) {
+        let counts = net.edge_counts();
+        self.previous.resize(counts.len(), 0);
+        let changes = counts.iter()
+            .zip(&self.previous)
+            .enumerate()
+            .filter(|(_, (current, previous))| current != previous)
+            .map(|(index, (&current, _))| (index, current))
+            .collect();
+        self.changes.push(changes);
+        self.max_number = self.max_number.max(counts.iter().copied().max().unwrap_or(0));
+        self.previous = counts;
+    }
+
+    /// Traffic counts of the last recorded frame
+    pub fn last_counts(&self) -> &[usize] {
+        &self.previous
+    }
+
+    pub fn number_of_frames(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Reconstruct the traffic counts of all frames in order and pass them to `frame` with the
+    /// index of the frame. `number_of_edges` is the number of edges of the recorded network.
+    fn replay<F>(&self, number_of_edges: usize, mut frame: F) -> anyhow::Result<()>
+        where F: FnMut(usize, &[usize]) -> anyhow::Result<()>
+    {
+        let mut counts = vec![0; number_of_edges];
+        for (i, changes) in self.changes.iter().enumerate() {
+            for &(index, number) in changes {
+                counts[index] = number;
+            }
+            frame(i, &counts)?;
+        }
+        Ok(())
+    }
+
+    /// Render all recorded frames. PNG files are written to `frames_dir` (if given) and an
+    /// animated GIF to `gif_path` (if given). `delay` is the duration of each frame in
+    /// hundredths of a second.
+    ///
+    /// The traffic counts of `net` are restored afterwards.
+    pub fn render(
+        self,
+        net: &mut Network,
+        bounds: BoundingBox,
+        size: u32,
+        frames_dir: Option<&Path>,
+        gif_path: Option<&Path>,
+        delay: u16,
+    ) -> anyhow::Result<()>
+    {
+        if let Some(dir) = frames_dir {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let mut gif_encoder = match gif_path {
+            Some(path) => {
+                let writer = BufWriter::new(File::create(path)?);
+                let mut encoder = gif::Encoder::new(writer, size as u16, size as u16, &[])?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                Some(encoder)
+            },
+            None => None,
+        };
+
+        let current = net.edge_counts();
+        self.replay(current.len(), |i, counts| {
+            println!("Render frame {}/{}", i + 1, self.changes.len());
+            net.set_edge_counts(counts);
+            // Use the same color scale for every frame
+            let canvas = net.render_image_with_max(bounds, size, size, self.max_number);
+
+            if let Some(dir) = frames_dir {
+                canvas.pixmap.save_png(frame_path(dir, i))?;
+            }
+
+            if let Some(encoder) = gif_encoder.as_mut() {
+                // All pixels are opaque, so premultiplied RGBA equals straight RGBA.
+                let mut rgba = canvas.pixmap.take();
+                let mut frame = gif::Frame::from_rgba_speed(size as u16, size as u16, &mut rgba, 10);
+                frame.delay = delay;
+                encoder.write_frame(&frame)?;
+            }
+            Ok(())
+        })?;
+        net.set_edge_counts(&current);
+
+        Ok(())
+    }
+}
+
+fn frame_path(dir: &Path, index: usize) -> PathBuf {
+    dir.join(format!("frame_{:05}.png", index))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::OsmNodeId;
+    use crate::test_util::TempFile;
+
+    #[test]
+    fn test_record_and_replay() {
+        let id = OsmNodeId::new;
+        let mut net = Network::from_nodes(
+            &[(1, 52.5, 13.4), (2, 52.5, 13.5), (3, 52.6, 13.5)],
+            &[(1, 2), (2, 3)],
+        );
+        let mut recorder = FrameRecorder::new();
+        net.bump_edges(&[id(1), id(2)]);
+        recorder.record(&net);
+        net.bump_edges(&[id(1), id(2), id(3)]);
+        recorder.record(&net);
+        // No changes
+        recorder.record(&net);
+        net.bump_edges(&[id(3), id(2)]);
+        recorder.record(&net);
+
+        assert_eq!(recorder.number_of_frames(), 4);
+        assert_eq!(recorder.last_counts(), &[2, 2][..]);
+        assert_eq!(recorder.max_number, 2);
+        // Only the changed counts are stored
+        assert_eq!(recorder.changes, vec![vec![(0, 1)], vec![(0, 2), (1, 1)], vec![], vec![(1, 2)]]);
+
+        let mut frames = vec![];
+        recorder.replay(2, |i, counts| {
+            frames.push((i, counts.to_vec()));
+            Ok(())
+        }).unwrap();
+        assert_eq!(frames, vec![(0, vec![1, 0]), (1, vec![2, 1]), (2, vec![2, 1]), (3, vec![2, 2])]);
+
+        // Rendering restores the counts of the network.
+        net.reset_edge_counts();
+        net.bump_edges(&[id(1), id(2)]);
+        let gif_path = TempFile::new("frames.gif");
+        let bounds = net.get_bounds();
+        recorder.render(&mut net, bounds, 32, None, Some(gif_path.as_ref()), 10).unwrap();
+        assert_eq!(net.edge_counts(), vec![1, 0]);
+        assert!(std::fs::read(&gif_path).unwrap().starts_with(b"GIF89a"));
+    }
+}
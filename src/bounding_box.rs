@@ -18,6 +18,25 @@ impl BoundingBox {
         BoundingBox { sw, ne }
     }
 
+    /// Return the smallest bounding box that contains all given points or None if there are no
+    /// points.
+    pub fn from_points<I: IntoIterator<Item=Point4326>>(points: I) -> Option<Self> {
+        let mut iter = points.into_iter();
+        let first = iter.next()?;
+        let (mut min_lat, mut min_lon) = (first.lat(), first.lon());
+        let (mut max_lat, mut max_lon) = (first.lat(), first.lon());
+        for p in iter {
+            min_lat = min_lat.min(p.lat());
+            min_lon = min_lon.min(p.lon());
+            max_lat = max_lat.max(p.lat());
+            max_lon = max_lon.max(p.lon());
+        }
+        Some(BoundingBox {
+            sw: Point4326::new(min_lat, min_lon),
+            ne: Point4326::new(max_lat, max_lon),
+        })
+    }
+
     ///FIXME This is a naive implementation that does not handle the 180th meridian.
     pub fn is_inside(&self, point: Point4326) -> bool {
         point.coords.0 >= self.sw.coords.0 &&
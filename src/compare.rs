@@ -1,19 +1,20 @@
 //! Compare generated traffic numbers with empirical data.
 
-use std::fs::File;
-use std::io::Read;
 use std::path::Path;
 
 use anyhow::Context;
-use geojson::GeoJson;
 use geomatic::{laea, Point3035, Point4326};
 use rstar::primitives::Line;
 use rstar::{AABB, PointDistance, RTree, RTreeObject};
 use serde::Serialize;
 
-use crate::geojson_writer::GeoJsonWriter;
+use crate::bounding_box::BoundingBox;
+use crate::geojson_reader::FeatureReader;
+use crate::geometry::Geometry;
 use crate::network;
 use crate::network::{OsmNodeId, UNDEF_OSM_EDGE};
+use crate::output::{self, Feature};
+use crate::render::{ColorScale, Projection};
 
 
 /// A line segment that can be inserted into an RTree.
@@ -109,8 +110,24 @@ struct CsvRecord {
     num_sim_segments: usize,
 }
 
-pub fn compare<P: AsRef<Path>>(net: &network::Network, geojson_path: P, number_property: &str)
-    -> anyhow::Result<()>
+/// A location where reference traffic could be matched to simulated traffic.
+#[derive(Clone, Debug)]
+pub struct ComparisonPoint {
+    pub point: Point4326,
+    pub number_ref: f64,
+    pub number_sim: f64,
+}
+
+/// Compare the simulated traffic of the network with the reference traffic from the given GeoJSON
+/// file. Writes the files `polylines.geojson` and `comparison.csv`, writes the matched
+/// connections to `connections_path` (GeoJSON or FlatGeobuf, see `output::create`) and returns
+/// all matched locations.
+pub fn compare<P: AsRef<Path>, Q: AsRef<Path>>(
+    net: &network::Network,
+    geojson_path: P,
+    number_property: &str,
+    connections_path: Q,
+) -> anyhow::Result<Vec<ComparisonPoint>>
 {
     // Build R-Trees for efficient spatial lookups
     let reference_traffic = geojson_to_rtree(&geojson_path, number_property)
@@ -126,8 +143,9 @@ pub fn compare<P: AsRef<Path>>(net: &network::Network, geojson_path: P, number_p
     println!("Built {} polylines", polylines.polylines.len());
     polylines.write_to_geojson("polylines.geojson")?;
 
-    let mut writer = GeoJsonWriter::from_path("connections.geojson")?;
+    let mut writer = output::create(connections_path)?;
     let mut csv_writer = csv::Writer::from_path("comparison.csv")?;
+    let mut comparison_points = vec![];
 
     for ref_segment in &reference_traffic {
         let matches = find_matching_segments(&ref_segment, None, &simulated_traffic, 20_f64.powi(2));
@@ -172,24 +190,26 @@ pub fn compare<P: AsRef<Path>>(net: &network::Network, geojson_path: P, number_p
                 sim_number += m.to_segment.number;
                 let from = point_to_4326(m.from_point);
                 let to = point_to_4326(m.to_point);
-                {
-                    let mut feat = writer.add_line_string(&[from, to])?;
-                    feat.add_property("number_ref", m.from_segment.number)?;
-                    feat.add_property("number_sim", m.to_segment.number)?;
-                    feat.add_property("length", m.distance)?;
-                    feat.finish()?;
-                }
+                writer.write_feature(&Feature::new(Geometry::LineString(vec![from, to]))
+                    .with_property("number_ref", m.from_segment.number)
+                    .with_property("number_sim", m.to_segment.number)
+                    .with_property("length", m.distance)
+                )?;
             }
 
-            {
-                let mut feat = writer.add_point(point_to_4326(ref_segment.center()))?;
-                feat.add_property("number_ref", ref_segment.number)?;
-                feat.add_property("number_sim", sim_number)?;
-                feat.add_property("diff", sim_number - ref_segment.number)?;
-                feat.add_property("number_connections", matches.len())?;
-                feat.add_property("poly_ok", poly_ok)?;
-                feat.finish()?;
-            }
+            writer.write_feature(&Feature::new(Geometry::Point(point_to_4326(ref_segment.center())))
+                .with_property("number_ref", ref_segment.number)
+                .with_property("number_sim", sim_number)
+                .with_property("diff", sim_number - ref_segment.number)
+                .with_property("number_connections", matches.len())
+                .with_property("poly_ok", poly_ok)
+            )?;
+
+            comparison_points.push(ComparisonPoint {
+                point: point_to_4326(ref_segment.center()),
+                number_ref: ref_segment.number,
+                number_sim: sim_number,
+            });
 
             if ref_segment.number > 0.0 && sim_number > 0.0 {
                 csv_writer.serialize(CsvRecord {
@@ -204,7 +224,62 @@ pub fn compare<P: AsRef<Path>>(net: &network::Network, geojson_path: P, number_p
     writer.finish()?;
     csv_writer.flush()?;
 
-    Ok(())
+    Ok(comparison_points)
+}
+
+/// Draw the comparison points as circles on top of a rendering of the network (see
+/// `Network::render_image`).
+///
+/// Simulated and reference traffic are on different scales, so the ratio sim/ref of each point is
+/// divided by the ratio of the sums over all points. Points where the model underestimates traffic
+/// are blue, points where it overestimates are red. The color scale saturates at a factor of four.
+pub fn draw_comparison(
+    canvas: &mut tiny_skia::Canvas,
+    bounds: BoundingBox,
+    points: &[ComparisonPoint],
+) {
+    let valid = || points.iter().filter(|p| p.number_ref > 0.0 && p.number_sim > 0.0);
+    let sum_ref: f64 = valid().map(|p| p.number_ref).sum();
+    let sum_sim: f64 = valid().map(|p| p.number_sim).sum();
+    if sum_ref <= 0.0 || sum_sim <= 0.0 {
+        return;
+    }
+    let global_ratio = sum_sim / sum_ref;
+
+    let projection = Projection::new(bounds, canvas.pixmap.width(), canvas.pixmap.height());
+    let color_scale = ColorScale::bu_rd();
+
+    let mut paint = tiny_skia::Paint {
+        anti_alias: true,
+        ..Default::default()
+    };
+    let stroke = tiny_skia::Stroke {
+        width: 2.0,
+        ..Default::default()
+    };
+
+    // Draw points without any simulated traffic in gray.
+    for p in points.iter().filter(|p| p.number_ref > 0.0 && p.number_sim <= 0.0) {
+        let (x, y) = projection.project(laea::forward(p.point));
+        if let Some(circle) = tiny_skia::PathBuilder::from_circle(x, y, 8.0) {
+            paint.set_color(tiny_skia::Color::from_rgba8(160, 160, 160, 255));
+            canvas.fill_path(&circle, &paint, tiny_skia::FillRule::Winding);
+            paint.set_color(tiny_skia::Color::BLACK);
+            canvas.stroke_path(&circle, &paint, &stroke);
+        }
+    }
+
+    for p in valid() {
+        let (x, y) = projection.project(laea::forward(p.point));
+        // log2 of the normalized ratio, clamped to [-2, 2]
+        let log_ratio = ((p.number_sim / p.number_ref) / global_ratio).log2().clamp(-2.0, 2.0);
+        if let Some(circle) = tiny_skia::PathBuilder::from_circle(x, y, 8.0) {
+            paint.set_color(color_scale.color((0.5 + 0.25 * log_ratio) as f32));
+            canvas.fill_path(&circle, &paint, tiny_skia::FillRule::Winding);
+            paint.set_color(tiny_skia::Color::BLACK);
+            canvas.stroke_path(&circle, &paint, &stroke);
+        }
+    }
 }
 
 struct SegmentMatch {
@@ -264,39 +339,27 @@ pub fn network_to_rtree(network: &network::Network) -> RTree<Segment> {
 pub fn geojson_to_rtree<P: AsRef<Path>>(geojson_path: P, number_property: &str)
     -> anyhow::Result<RTree<Segment>>
 {
-    // Parse GeoJSON
-    let geojson: GeoJson = {
-        let mut f = File::open(&geojson_path)?;
-        let mut geojson_str = String::new();
-        f.read_to_string(&mut geojson_str)?;
-        geojson_str.parse::<GeoJson>()?
-    };
-
     // line segments of geojson file
     let mut segments: Vec<Segment> = vec![];
 
     // Gather segments of all line strings
-    match &geojson {
-        &GeoJson::FeatureCollection(ref fc) => {
-            for feature in &fc.features {
-                let line_string = feature.geometry.as_ref().map(|g| &g.value);
-                let number = feature.properties.as_ref().and_then(|p| p.get(number_property)).and_then(|n| n.as_f64());
-
-                if let (Some(geojson::Value::LineString(line_string)), Some(number)) = (line_string, number) {
-                    let mut last_point = None;
-                    for point in line_string {
-                        if point.len() >= 2 {
-                            let current_point = laea::forward(Point4326::new(point[1], point[0]));
-                            if let Some(last_point) = last_point {
-                                segments.push(Segment::new(last_point, current_point, UNDEF_OSM_EDGE, number));
-                            }
-                            last_point = Some(current_point);
-                        }
+    for feature in FeatureReader::from_path(&geojson_path)? {
+        let feature = feature?;
+        let line_string = feature.geometry.as_ref().map(|g| &g.value);
+        let number = feature.properties.as_ref().and_then(|p| p.get(number_property)).and_then(|n| n.as_f64());
+
+        if let (Some(geojson::Value::LineString(line_string)), Some(number)) = (line_string, number) {
+            let mut last_point = None;
+            for point in line_string {
+                if point.len() >= 2 {
+                    let current_point = laea::forward(Point4326::new(point[1], point[0]));
+                    if let Some(last_point) = last_point {
+                        segments.push(Segment::new(last_point, current_point, UNDEF_OSM_EDGE, number));
                     }
+                    last_point = Some(current_point);
                 }
             }
-        },
-        _ => anyhow::bail!("GeoJSON file is no FeatureCollection: {}", geojson_path.as_ref().display()),
+        }
     }
 
     if segments.is_empty() {
@@ -330,4 +393,57 @@ mod tests {
         assert!(approx_eq(orientation_diff(&a2, &b), PI * 0.5));
         assert!(approx_eq(orientation_diff(&b, &a2), PI * 0.5));
     }
+
+    #[test]
+    fn test_draw_comparison() {
+        let point = |lon: f64, number_ref: f64, number_sim: f64| ComparisonPoint {
+            point: Point4326::new(52.5, lon),
+            number_ref,
+            number_sim,
+        };
+        let bounds = BoundingBox::new(Point4326::new(52.4, 13.0), Point4326::new(52.6, 14.0));
+        // Colors at the centers of the circles
+        let colors = |points: &[ComparisonPoint]| -> Vec<(u8, u8, u8)> {
+            let mut canvas = crate::render::new_canvas(200, 200);
+            draw_comparison(&mut canvas, bounds, points);
+            let projection = Projection::new(bounds, 200, 200);
+            points.iter()
+                .map(|p| {
+                    let (x, y) = projection.project(laea::forward(p.point));
+                    let c = canvas.pixmap.pixel(x as u32, y as u32).unwrap();
+                    (c.red(), c.green(), c.blue())
+                })
+                .collect()
+        };
+        let assert_close = |actual: (u8, u8, u8), expected: (u8, u8, u8)| {
+            let close = |a: u8, b: u8| (a as i32 - b as i32).abs() <= 2;
+            assert!(
+                close(actual.0, expected.0) && close(actual.1, expected.1) && close(actual.2, expected.2),
+                "{:?} != {:?}", actual, expected,
+            );
+        };
+
+        // The simulation counts twice as much traffic overall, which is normalized.
+        let points = vec![
+            point(13.2, 10.0, 20.0),
+            // Underestimated by a factor of four
+            point(13.4, 40.0, 20.0),
+            // Overestimated by a factor of four
+            point(13.6, 10.0, 80.0),
+            // Not matched
+            point(13.8, 10.0, 0.0),
+        ];
+        let colors = colors(&points);
+        let scale = ColorScale::bu_rd();
+        let rgb = |t: f32| {
+            let c = scale.color(t).to_color_u8();
+            (c.red(), c.green(), c.blue())
+        };
+        assert_eq!(rgb(0.0), (33, 102, 172));
+        // Sum of the valid points: sim 120, ref 60
+        assert_close(colors[0], rgb(0.5));
+        assert_close(colors[1], rgb(0.0));
+        assert_close(colors[2], rgb(1.0));
+        assert_close(colors[3], (160, 160, 160));
+    }
 }
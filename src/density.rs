@@ -57,15 +57,25 @@ impl DensityClusters {
         })
     }
 
+    /// Locations of all clusters.
+    pub fn points(&self) -> &[Point4326] {
+        &self.points
+    }
+
+    /// Weights of all clusters, in the same order as `points`.
+    pub fn weights(&self) -> &[u32] {
+        &self.weights
+    }
+
     /// Return a random point from the distribution.
-    pub fn sample_point(&self, rng: &mut ThreadRng) -> Point4326 {
+    pub fn sample_point<R: Rng + ?Sized>(&self, rng: &mut R) -> Point4326 {
         self.points[self.dist.sample(rng)]
     }
 
     /// Sample a point within a radius from a given point.
     /// Returns None if no destination point can be created because there aren't any or the sum of
     /// weights is zero.
-    pub fn sample_point_within(&self, rng: &mut ThreadRng, from: Point4326, within_radius: f64)
+    pub fn sample_point_within<R: Rng + ?Sized>(&self, rng: &mut R, from: Point4326, within_radius: f64)
         -> Option<Point4326>
     {
         let from = laea::forward(from);
@@ -87,4 +97,34 @@ impl DensityClusters {
             Err(_) => None,
         }
     }
+
+    /// Sample a point within a radius from a given point. The weight of each cluster is
+    /// multiplied with `distance_weight` of its distance in meters, e.g. to approximate a
+    /// distribution of trip lengths (see `TripLength::area_weight`).
+    /// Returns None if there are no points with a positive weight.
+    pub fn sample_point_within_weighted<R, F>(
+        &self,
+        rng: &mut R,
+        from: Point4326,
+        within_radius: f64,
+        distance_weight: F,
+    ) -> Option<Point4326>
+        where
+            R: Rng + ?Sized,
+            F: Fn(f64) -> f64,
+    {
+        let from = laea::forward(from);
+        let indices = self.kdtree.within(
+            &[from.coords.0, from.coords.1],
+            within_radius.powi(2),
+            &squared_euclidean,
+        ).unwrap();
+
+        let weights = indices.iter()
+            .map(|&(dist_sq, &i)| self.weights[i] as f64 * distance_weight(dist_sq.sqrt()));
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => Some(self.points[*indices[distribution.sample(rng)].1]),
+            Err(_) => None,
+        }
+    }
 }
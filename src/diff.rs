@@ -0,0 +1,389 @@
+//! Compare the traffic counts of two runs on the same road network.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use geomatic::{laea, Point4326};
+
+use crate::bounding_box::BoundingBox;
+use crate::geojson_reader::FeatureReader;
+use crate::geometry::Geometry;
+use crate::network::Network;
+use crate::output::{self, Feature};
+use crate::render::{self, ColorScale, Projection};
+use crate::route::RouteCollectionReader;
+
+
+/// Number of decimal places of coordinates that are used to match edges, as written to GeoJSON
+/// files by default.
+const MAX_PRECISION: u32 = 6;
+
+/// Coordinates (lat, lon) in units of 10^-precision degrees.
+type CoordKey = (i64, i64);
+
+/// An undirected edge, the smaller coordinate always comes first.
+type EdgeKey = (CoordKey, CoordKey);
+
+fn coord_key(point: Point4326, precision: u32) -> CoordKey {
+    let scale = 10f64.powi(precision as i32);
+    ((point.lat() * scale).round() as i64, (point.lon() * scale).round() as i64)
+}
+
+fn edge_key(a: Point4326, b: Point4326, precision: u32) -> EdgeKey {
+    let a = coord_key(a, precision);
+    let b = coord_key(b, precision);
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+fn key_to_point(key: CoordKey, precision: u32) -> Point4326 {
+    let scale = 10f64.powi(precision as i32);
+    Point4326::new(key.0 as f64 / scale, key.1 as f64 / scale)
+}
+
+/// The number of decimal places of a coordinate, at most `MAX_PRECISION`.
+fn decimal_places(value: f64) -> u32 {
+    (0..MAX_PRECISION)
+        .find(|&d| {
+            let scaled = value * 10f64.powi(d as i32);
+            (scaled - scaled.round()).abs() < 1e-3
+        })
+        .unwrap_or(MAX_PRECISION)
+}
+
+/// Traffic counts of the edges of a road network, identified by their coordinates.
+pub struct EdgeCounts {
+    /// Both ends and the count of each edge
+    edges: Vec<(Point4326, Point4326, f64)>,
+    /// Number of decimal places of the coordinates, at most `MAX_PRECISION`
+    precision: u32,
+}
+
+impl EdgeCounts {
+    /// Read counts from a `*.routes` file or a GeoJSON file (selected by file extension).
+    ///
+    /// `*.routes` files are replayed on the given network which has to be the network that
+    /// was used to create the routes.
+    pub fn from_path<P: AsRef<Path>>(path: P, number_property: &str, net: Option<&mut Network>)
+        -> anyhow::Result<Self>
+    {
+        let path = path.as_ref();
+        if is_routes_path(path) {
+            let net = net.ok_or_else(|| anyhow::anyhow!("Can't replay routes without a network"))?;
+            Self::from_routes(path, net)
+        } else {
+            Self::from_geojson(path, number_property)
+        }
+    }
+
+    /// Read the number property of all LineString features of a GeoJSON file or GeoJSON text
+    /// sequence. The precision of the coordinates is detected, so that files written with fewer
+    /// decimal places can still be matched.
+    pub fn from_geojson<P: AsRef<Path>>(path: P, number_property: &str) -> anyhow::Result<Self> {
+        let mut edges = vec![];
+        let mut precision = 0;
+
+        for feature in FeatureReader::from_path(&path)? {
+            let feature = feature?;
+            let line_string = feature.geometry.as_ref().map(|g| &g.value);
+            let number = feature.properties.as_ref()
+                .and_then(|p| p.get(number_property))
+                .and_then(|n| n.as_f64());
+
+            if let (Some(geojson::Value::LineString(line_string)), Some(number)) = (line_string, number) {
+                let points: Vec<Point4326> = line_string.iter()
+                    .filter(|p| p.len() >= 2)
+                    .map(|p| Point4326::new(p[1], p[0]))
+                    .collect();
+                for p in &points {
+                    precision = precision.max(decimal_places(p.lat())).max(decimal_places(p.lon()));
+                }
+                edges.extend(points.windows(2).map(|w| (w[0], w[1], number)));
+            }
+        }
+
+        Ok(EdgeCounts {
+            edges,
+            precision: if precision == 0 { MAX_PRECISION } else { precision },
+        })
+    }
+
+    /// Replay all routes of a `*.routes` file on the given network and take the resulting counts.
+    pub fn from_routes<P: AsRef<Path>>(path: P, net: &mut Network) -> anyhow::Result<Self> {
+        let reader = RouteCollectionReader::new(&path)
+            .with_context(|| format!("Failed to read the routes file {:?}", path.as_ref()))?;
+        net.reset_edge_counts();
+        for route in reader {
+            net.bump_edges(&route?.node_ids);
+        }
+        Ok(Self::from_network(net))
+    }
+
+    pub fn from_network(net: &Network) -> Self {
+        EdgeCounts {
+            edges: net.edges()
+                .filter(|e| e.number > 0)
+                .map(|e| (e.a.as_point4326(), e.b.as_point4326(), e.number as f64))
+                .collect(),
+            precision: MAX_PRECISION,
+        }
+    }
+
+    /// Counts of the edges with coordinates rounded to the given number of decimal places. Edges
+    /// that shrink to a single point are left out.
+    fn keyed(&self, precision: u32) -> HashMap<EdgeKey, f64> {
+        let mut counts = HashMap::new();
+        for &(a, b, number) in &self.edges {
+            let key = edge_key(a, b, precision);
+            if key.0 != key.1 {
+                *counts.entry(key).or_insert(0.0) += number;
+            }
+        }
+        counts
+    }
+}
+
+pub fn is_routes_path(path: &Path) -> bool {
+    path.extension() == Some("routes".as_ref())
+}
+
+/// The change of traffic on one edge.
+pub struct EdgeDiff {
+    pub a: Point4326,
+    pub b: Point4326,
+    pub number_before: f64,
+    pub number_after: f64,
+}
+
+impl EdgeDiff {
+    /// Absolute difference
+    pub fn diff(&self) -> f64 {
+        self.number_after - self.number_before
+    }
+
+    /// Difference relative to the count before. Returns None if there was no traffic before.
+    pub fn relative_diff(&self) -> Option<f64> {
+        if self.number_before > 0.0 {
+            Some(self.diff() / self.number_before)
+        } else {
+            None
+        }
+    }
+}
+
+/// Differences between two sets of traffic counts.
+pub struct NetworkDiff {
+    pub edges: Vec<EdgeDiff>,
+}
+
+impl NetworkDiff {
+    /// Match the edges of both counts by their coordinates, rounded to the lower precision of
+    /// both.
+    pub fn new(before: &EdgeCounts, after: &EdgeCounts) -> Self {
+        let precision = before.precision.min(after.precision);
+        if before.precision != after.precision {
+            println!("Match edges by coordinates with {} decimal places", precision);
+        }
+        let before = before.keyed(precision);
+        let after = after.keyed(precision);
+
+        let mut edges: Vec<EdgeDiff> = before.iter()
+            .map(|(key, &number)| EdgeDiff {
+                a: key_to_point(key.0, precision),
+                b: key_to_point(key.1, precision),
+                number_before: number,
+                number_after: after.get(key).copied().unwrap_or(0.0),
+            })
+            .collect();
+
+        // Add edges that only have traffic afterwards
+        edges.extend(after.iter()
+            .filter(|(key, _)| !before.contains_key(key))
+            .map(|(key, &number)| EdgeDiff {
+                a: key_to_point(key.0, precision),
+                b: key_to_point(key.1, precision),
+                number_before: 0.0,
+                number_after: number,
+            })
+        );
+
+        NetworkDiff { edges }
+    }
+
+    pub fn get_bounds(&self) -> Option<BoundingBox> {
+        BoundingBox::from_points(self.edges.iter().flat_map(|e| vec![e.a, e.b]))
+    }
+
+    /// Write both counts and the differences of all edges to a GeoJSON or FlatGeobuf file (see
+    /// `output::create`).
+    pub fn write_to_file<P: AsRef<Path>>(&self, output_path: P) -> anyhow::Result<()> {
+        let mut writer = output::create(output_path)?;
+
+        for edge in &self.edges {
+            let mut feature = Feature::new(Geometry::LineString(vec![edge.a, edge.b]))
+                .with_property("number_before", edge.number_before)
+                .with_property("number_after", edge.number_after)
+                .with_property("diff", edge.diff());
+            if let Some(rel) = edge.relative_diff() {
+                feature = feature.with_property("relative_diff", rel);
+            }
+            writer.write_feature(&feature)?;
+        }
+
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Render the differences with a diverging color scale. Blue edges lost traffic, red edges
+    /// gained traffic. If `relative` is true, colors show the relative difference in the range of
+    /// -100% to +100%, otherwise the absolute difference is normalized with the largest absolute
+    /// difference.
+    pub fn render_image(&self, bounds: BoundingBox, width: u32, height: u32, relative: bool)
+        -> tiny_skia::Canvas
+    {
+        let mut canvas = render::new_canvas(width, height);
+        let projection = Projection::new(bounds, width, height);
+        let color_scale = ColorScale::bu_rd();
+
+        let max_abs_diff = self.edges.iter().map(|e| e.diff().abs()).fold(0.0, f64::max);
+
+        // Position on the color scale in [-1, 1]
+        let value = |edge: &EdgeDiff| -> f64 {
+            if relative {
+                edge.relative_diff().unwrap_or(1.0).min(1.0)
+            } else if max_abs_diff > 0.0 {
+                edge.diff() / max_abs_diff
+            } else {
+                0.0
+            }
+        };
+
+        let mut paint = tiny_skia::Paint {
+            anti_alias: true,
+            ..Default::default()
+        };
+        let mut stroke = tiny_skia::Stroke {
+            line_cap: tiny_skia::LineCap::Round,
+            ..Default::default()
+        };
+
+        // Draw all edges in gray to provide some context.
+        stroke.width = 2.0;
+        paint.set_color(tiny_skia::Color::from_rgba8(210, 210, 210, 255));
+        for edge in &self.edges {
+            if let Some(path) = projection.line_path(laea::forward(edge.a), laea::forward(edge.b)) {
+                canvas.stroke_path(&path, &paint, &stroke);
+            }
+        }
+
+        // Draw largest differences on top.
+        let mut edges: Vec<(&EdgeDiff, f64)> = self.edges.iter()
+            .map(|e| (e, value(e)))
+            .filter(|(_, v)| *v != 0.0)
+            .collect();
+        edges.sort_by(|a, b| a.1.abs().total_cmp(&b.1.abs()));
+
+        stroke.width = 4.0;
+        for (edge, v) in edges {
+            if let Some(path) = projection.line_path(laea::forward(edge.a), laea::forward(edge.b)) {
+                paint.set_color(color_scale.color((0.5 + 0.5 * v) as f32));
+                canvas.stroke_path(&path, &paint, &stroke);
+            }
+        }
+
+        canvas
+    }
+
+    pub fn write_png<P: AsRef<Path>>(&self, path: P, bounds: BoundingBox, width: u32, height: u32, relative: bool)
+        -> anyhow::Result<()>
+    {
+        let canvas = self.render_image(bounds, width, height, relative);
+        canvas.pixmap.save_png(&path)
+            .with_context(|| format!("Failed to write PNG file {:?}", path.as_ref()))?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(lat: f64, lon: f64) -> Point4326 {
+        Point4326::new(lat, lon)
+    }
+
+    fn counts(edges: &[(Point4326, Point4326, f64)], precision: u32) -> EdgeCounts {
+        EdgeCounts { edges: edges.to_vec(), precision }
+    }
+
+    /// (before, after) of the edge between a and b
+    fn numbers(diff: &NetworkDiff, a: Point4326, b: Point4326) -> Option<(f64, f64)> {
+        let key = edge_key(a, b, MAX_PRECISION);
+        diff.edges.iter()
+            .find(|e| edge_key(e.a, e.b, MAX_PRECISION) == key)
+            .map(|e| (e.number_before, e.number_after))
+    }
+
+    #[test]
+    fn test_edge_key() {
+        let a = p(52.5, 13.4);
+        let b = p(52.6, 13.5);
+        assert_eq!(edge_key(a, b, 6), edge_key(b, a, 6));
+        assert_eq!(edge_key(a, p(52.6000004, 13.5), 6), edge_key(a, b, 6));
+        assert_ne!(edge_key(a, p(52.600001, 13.5), 6), edge_key(a, b, 6));
+        assert_eq!(coord_key(p(52.51234, 13.4), 3), (52512, 13400));
+        let point = key_to_point((52512, 13400), 3);
+        assert_eq!((point.lat(), point.lon()), (52.512, 13.4));
+
+        assert_eq!(decimal_places(13.0), 0);
+        assert_eq!(decimal_places(13.41), 2);
+        assert_eq!(decimal_places(-52.512345), 6);
+        assert_eq!(decimal_places(52.51234567), 6);
+    }
+
+    #[test]
+    fn test_network_diff() {
+        let (a, b, c, d) = (p(52.5, 13.4), p(52.5, 13.5), p(52.6, 13.5), p(52.6, 13.6));
+        let before = counts(&[(a, b, 10.0), (b, c, 4.0)], 6);
+        // Reversed direction of the same edge
+        let after = counts(&[(b, a, 15.0), (c, d, 3.0)], 6);
+        let diff = NetworkDiff::new(&before, &after);
+
+        assert_eq!(diff.edges.len(), 3);
+        // Changed
+        assert_eq!(numbers(&diff, a, b), Some((10.0, 15.0)));
+        // Removed
+        assert_eq!(numbers(&diff, b, c), Some((4.0, 0.0)));
+        // Added
+        assert_eq!(numbers(&diff, c, d), Some((0.0, 3.0)));
+
+        let relative = |a, b| {
+            let key = edge_key(a, b, MAX_PRECISION);
+            diff.edges.iter().find(|e| edge_key(e.a, e.b, MAX_PRECISION) == key).unwrap().relative_diff()
+        };
+        assert_eq!(relative(a, b), Some(0.5));
+        assert_eq!(relative(b, c), Some(-1.0));
+        // No traffic before
+        assert_eq!(relative(c, d), None);
+    }
+
+    #[test]
+    fn test_lower_precision() {
+        let a = p(52.512345, 13.412345);
+        let b = p(52.523456, 13.423456);
+        let c = p(52.523458, 13.423456);
+        let before = counts(&[(a, b, 10.0), (b, c, 1.0)], 6);
+        // The same edge written with three decimal places
+        let after = counts(&[(p(52.512, 13.412), p(52.523, 13.423), 12.0)], 3);
+        let diff = NetworkDiff::new(&before, &after);
+        // The edge from b to c is a single point with three decimal places.
+        assert_eq!(diff.edges.len(), 1);
+        assert_eq!((diff.edges[0].number_before, diff.edges[0].number_after), (10.0, 12.0));
+    }
+}
@@ -0,0 +1,597 @@
+//! Write FlatGeobuf files with a packed Hilbert R-tree index (https://flatgeobuf.org).
+//!
+//! The index precedes the features in the file and the features have to be sorted along a
+//! Hilbert curve, so all features are kept in memory until the writer is finished.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use flatbuffers::{field_index_to_field_offset as slot, FlatBufferBuilder, TableFinishedWIPOffset, WIPOffset};
+use geomatic::Point4326;
+use serde_json::Value;
+
+use crate::geometry::Geometry;
+use crate::output::{Feature, FeatureSink};
+
+
+const MAGIC_BYTES: [u8; 8] = [b'f', b'g', b'b', 3, b'f', b'g', b'b', 0];
+
+/// Number of children of each node of the spatial index.
+const INDEX_NODE_SIZE: u16 = 16;
+
+/// Largest coordinate on the Hilbert curve that is used to sort the features.
+const HILBERT_MAX: f64 = ((1 << 16) - 1) as f64;
+
+/// Values of the GeometryType enum of the FlatGeobuf schema.
+mod geometry_type {
+    pub const UNKNOWN: u8 = 0;
+    pub const POINT: u8 = 1;
+    pub const LINE_STRING: u8 = 2;
+    pub const POLYGON: u8 = 3;
+    pub const MULTI_POINT: u8 = 4;
+    pub const MULTI_LINE_STRING: u8 = 5;
+    pub const MULTI_POLYGON: u8 = 6;
+}
+
+/// The subset of the ColumnType enum of the FlatGeobuf schema that is needed to store JSON values.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ColumnType {
+    Bool = 2,
+    Long = 7,
+    ULong = 8,
+    Double = 10,
+    String = 11,
+    Json = 12,
+}
+
+impl ColumnType {
+    /// Returns None for null values, which are simply left out.
+    fn of(value: &Value) -> Option<ColumnType> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(ColumnType::Bool),
+            Value::Number(n) if n.is_u64() => Some(ColumnType::ULong),
+            Value::Number(n) if n.is_i64() => Some(ColumnType::Long),
+            Value::Number(_) => Some(ColumnType::Double),
+            Value::String(_) => Some(ColumnType::String),
+            _ => Some(ColumnType::Json),
+        }
+    }
+
+    /// Find a type that can hold values of both types.
+    fn merge(self, other: ColumnType) -> ColumnType {
+        use ColumnType::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Long, ULong) | (ULong, Long) => Long,
+            (Long, Double) | (ULong, Double) | (Double, Long) | (Double, ULong) => Double,
+            _ => Json,
+        }
+    }
+}
+
+/// A node of the packed R-tree. For leaf nodes `offset` is the byte offset of the feature in the
+/// feature section, for all other nodes it is the index of the first child node.
+#[derive(Copy, Clone, Debug)]
+struct NodeItem {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+    offset: u64,
+}
+
+impl NodeItem {
+    fn empty(offset: u64) -> Self {
+        NodeItem {
+            min_x: f64::INFINITY,
+            min_y: f64::INFINITY,
+            max_x: f64::NEG_INFINITY,
+            max_y: f64::NEG_INFINITY,
+            offset,
+        }
+    }
+
+    fn from_geometry(geometry: &Geometry) -> Self {
+        let mut node = NodeItem::empty(0);
+        let mut expand = |points: &[Point4326]| {
+            for p in points {
+                node.expand(&NodeItem {
+                    min_x: p.lon(),
+                    min_y: p.lat(),
+                    max_x: p.lon(),
+                    max_y: p.lat(),
+                    offset: 0,
+                });
+            }
+        };
+        match geometry {
+            Geometry::Point(p) => expand(&[*p]),
+            Geometry::MultiPoint(points) | Geometry::LineString(points) => expand(points),
+            Geometry::MultiLineString(parts) | Geometry::Polygon(parts) => {
+                parts.iter().for_each(|p| expand(p))
+            },
+            Geometry::MultiPolygon(polygons) => {
+                polygons.iter().flatten().for_each(|p| expand(p))
+            },
+        }
+        node
+    }
+
+    /// Grow to include the other node. Non-finite coordinates are ignored.
+    fn expand(&mut self, other: &NodeItem) {
+        self.min_x = self.min_x.min(other.min_x);
+        self.min_y = self.min_y.min(other.min_y);
+        self.max_x = self.max_x.max(other.max_x);
+        self.max_y = self.max_y.max(other.max_y);
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        for value in &[self.min_x, self.min_y, self.max_x, self.max_y] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.write_all(&self.offset.to_le_bytes())
+    }
+}
+
+/// Write FlatGeobuf files
+pub struct FlatGeobufWriter<W: Write> {
+    writer: BufWriter<W>,
+    /// Name of the layer
+    name: String,
+    features: Vec<(NodeItem, Feature)>,
+    finished: bool,
+}
+
+impl FlatGeobufWriter<File> {
+    /// Create a file. The file stem is used as the layer name.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<FlatGeobufWriter<File>> {
+        let name = path.as_ref().file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file = File::create(path)?;
+        Ok(Self::new(file, &name))
+    }
+}
+
+impl<W: Write> FlatGeobufWriter<W> {
+    pub fn new(writer: W, name: &str) -> FlatGeobufWriter<W> {
+        FlatGeobufWriter {
+            writer: BufWriter::new(writer),
+            name: name.to_string(),
+            features: vec![],
+            finished: false,
+        }
+    }
+
+    /// A private method that does not move self so Drop can call it.
+    fn mut_finish(&mut self) -> anyhow::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let mut extent = NodeItem::empty(0);
+        for (node, _) in &self.features {
+            extent.expand(node);
+        }
+
+        // Sort along a Hilbert curve so that nearby features end up in the same index nodes.
+        self.features.sort_by_cached_key(|(node, _)| hilbert_value(node, &extent));
+
+        // Collect all columns in the order of their first appearance.
+        let mut columns: Vec<(&str, ColumnType)> = vec![];
+        let mut column_index: HashMap<&str, usize> = HashMap::new();
+        for (_, feature) in &self.features {
+            for (key, value) in feature.properties_with_id() {
+                let column_type = match ColumnType::of(value) {
+                    Some(t) => t,
+                    None => continue,
+                };
+                match column_index.get(key) {
+                    Some(&i) => columns[i].1 = columns[i].1.merge(column_type),
+                    None => {
+                        column_index.insert(key, columns.len());
+                        columns.push((key, column_type));
+                    },
+                }
+            }
+        }
+
+        // Use a common geometry type if possible, otherwise each feature stores its type.
+        let types: Vec<u8> = self.features.iter().map(|(_, f)| type_of(&f.geometry)).collect();
+        let header_type = match types.first() {
+            Some(&t) if types.iter().all(|&u| u == t) => t,
+            _ => geometry_type::UNKNOWN,
+        };
+
+        // Serialize features and remember their offsets for the index.
+        let mut feature_data: Vec<u8> = vec![];
+        let mut leaves: Vec<NodeItem> = vec![];
+        let mut fbb = FlatBufferBuilder::new();
+        for (node, feature) in &self.features {
+            leaves.push(NodeItem { offset: feature_data.len() as u64, ..*node });
+            let props = encode_properties(feature, &columns, &column_index);
+            fbb.reset();
+            let geometry = build_geometry(&mut fbb, &feature.geometry, header_type == geometry_type::UNKNOWN);
+            let properties = fbb.create_vector(&props);
+            let start = fbb.start_table();
+            fbb.push_slot_always(slot(0), geometry);
+            if !props.is_empty() {
+                fbb.push_slot_always(slot(1), properties);
+            }
+            let root = fbb.end_table(start);
+            fbb.finish_size_prefixed(root, None);
+            feature_data.extend_from_slice(fbb.finished_data());
+        }
+
+        let index_node_size = if self.features.is_empty() { 0 } else { INDEX_NODE_SIZE };
+
+        // Header
+        fbb.reset();
+        let name = fbb.create_string(&self.name);
+        let envelope = fbb.create_vector(&[extent.min_x, extent.min_y, extent.max_x, extent.max_y]);
+        let mut column_offsets = vec![];
+        for &(column_name, column_type) in &columns {
+            let column_name = fbb.create_string(column_name);
+            let start = fbb.start_table();
+            fbb.push_slot_always(slot(0), column_name);
+            fbb.push_slot::<u8>(slot(1), column_type as u8, 0);
+            column_offsets.push(fbb.end_table(start));
+        }
+        let column_offsets = fbb.create_vector(&column_offsets);
+        let crs = {
+            let start = fbb.start_table();
+            fbb.push_slot::<i32>(slot(1), 4326, 0);
+            fbb.end_table(start)
+        };
+        let start = fbb.start_table();
+        fbb.push_slot_always(slot(0), name);
+        if !self.features.is_empty() {
+            fbb.push_slot_always(slot(1), envelope);
+        }
+        fbb.push_slot::<u8>(slot(2), header_type, geometry_type::UNKNOWN);
+        fbb.push_slot_always(slot(7), column_offsets);
+        fbb.push_slot::<u64>(slot(8), self.features.len() as u64, 0);
+        fbb.push_slot::<u16>(slot(9), index_node_size, INDEX_NODE_SIZE);
+        fbb.push_slot_always(slot(10), crs);
+        let root = fbb.end_table(start);
+        fbb.finish_size_prefixed(root, None);
+
+        self.writer.write_all(&MAGIC_BYTES)?;
+        self.writer.write_all(fbb.finished_data())?;
+        if index_node_size > 0 {
+            for node in build_index(&leaves, index_node_size as usize) {
+                node.write(&mut self.writer)?;
+            }
+        }
+        self.writer.write_all(&feature_data)?;
+        self.writer.flush()?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> FeatureSink for FlatGeobufWriter<W> {
+    fn write_feature(&mut self, feature: &Feature) -> anyhow::Result<()> {
+        self.features.push((NodeItem::from_geometry(&feature.geometry), feature.clone()));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.mut_finish()
+    }
+}
+
+impl<W: Write> Drop for FlatGeobufWriter<W> {
+    fn drop(&mut self) {
+        // drop can't return errors :(
+        let _ = self.mut_finish();
+    }
+}
+
+/// Encode properties as pairs of column index and value in little endian byte order.
+fn encode_properties(feature: &Feature, columns: &[(&str, ColumnType)], column_index: &HashMap<&str, usize>)
+    -> Vec<u8>
+{
+    let mut buf = vec![];
+    for (key, value) in feature.properties_with_id() {
+        if value.is_null() {
+            continue;
+        }
+        let i = column_index[key];
+        buf.extend_from_slice(&(i as u16).to_le_bytes());
+        match columns[i].1 {
+            ColumnType::Bool => buf.push(value.as_bool().unwrap_or(false) as u8),
+            ColumnType::Long => {
+                let v = value.as_i64().or_else(|| value.as_u64().map(|v| v as i64)).unwrap_or(0);
+                buf.extend_from_slice(&v.to_le_bytes());
+            },
+            ColumnType::ULong => buf.extend_from_slice(&value.as_u64().unwrap_or(0).to_le_bytes()),
+            ColumnType::Double => {
+                buf.extend_from_slice(&value.as_f64().unwrap_or(f64::NAN).to_le_bytes());
+            },
+            ColumnType::String | ColumnType::Json => {
+                let s = match value {
+                    Value::String(s) if columns[i].1 == ColumnType::String => s.clone(),
+                    _ => value.to_string(),
+                };
+                buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                buf.extend_from_slice(s.as_bytes());
+            },
+        }
+    }
+    buf
+}
+
+fn type_of(geometry: &Geometry) -> u8 {
+    match geometry {
+        Geometry::Point(_) => geometry_type::POINT,
+        Geometry::MultiPoint(_) => geometry_type::MULTI_POINT,
+        Geometry::LineString(_) => geometry_type::LINE_STRING,
+        Geometry::MultiLineString(_) => geometry_type::MULTI_LINE_STRING,
+        Geometry::Polygon(_) => geometry_type::POLYGON,
+        Geometry::MultiPolygon(_) => geometry_type::MULTI_POLYGON,
+    }
+}
+
+/// Build a Geometry table. Multi part geometries store the end index of each part in `ends`,
+/// polygons of a MultiPolygon are stored as separate `parts`.
+fn build_geometry<'a>(fbb: &mut FlatBufferBuilder<'a>, geometry: &Geometry, write_type: bool)
+    -> WIPOffset<TableFinishedWIPOffset>
+{
+    let geom_type = type_of(geometry);
+
+    if let Geometry::MultiPolygon(polygons) = geometry {
+        let parts: Vec<_> = polygons.iter()
+            .map(|rings| build_geometry(fbb, &Geometry::Polygon(rings.clone()), true))
+            .collect();
+        let parts = fbb.create_vector(&parts);
+        let start = fbb.start_table();
+        fbb.push_slot_always(slot(7), parts);
+        if write_type {
+            fbb.push_slot::<u8>(slot(6), geom_type, geometry_type::UNKNOWN);
+        }
+        return fbb.end_table(start);
+    }
+
+    let parts: Vec<Vec<Point4326>> = match geometry {
+        Geometry::Point(p) => vec![vec![*p]],
+        Geometry::MultiPoint(points) | Geometry::LineString(points) => vec![points.clone()],
+        Geometry::MultiLineString(lines) => lines.clone(),
+        Geometry::Polygon(rings) => rings.iter().map(|r| close_ring(r)).collect(),
+        Geometry::MultiPolygon(_) => unreachable!(),
+    };
+
+    let mut xy = vec![];
+    let mut ends = vec![];
+    for part in &parts {
+        for p in part {
+            xy.push(p.lon());
+            xy.push(p.lat());
+        }
+        ends.push((xy.len() / 2) as u32);
+    }
+
+    let xy = fbb.create_vector(&xy);
+    let ends = if ends.len() > 1 { Some(fbb.create_vector(&ends)) } else { None };
+    let start = fbb.start_table();
+    if let Some(ends) = ends {
+        fbb.push_slot_always(slot(0), ends);
+    }
+    fbb.push_slot_always(slot(1), xy);
+    if write_type {
+        fbb.push_slot::<u8>(slot(6), geom_type, geometry_type::UNKNOWN);
+    }
+    fbb.end_table(start)
+}
+
+/// Repeat the first position at the end if the ring is not closed yet.
+fn close_ring(ring: &[Point4326]) -> Vec<Point4326> {
+    let mut ring = ring.to_vec();
+    if let (Some(first), Some(last)) = (ring.first(), ring.last()) {
+        if first.coords != last.coords {
+            ring.push(*first);
+        }
+    }
+    ring
+}
+
+/// Position of the center of the node on a Hilbert curve that covers the extent.
+fn hilbert_value(node: &NodeItem, extent: &NodeItem) -> u32 {
+    let scale = |center: f64, min: f64, max: f64| -> u32 {
+        if max > min {
+            (HILBERT_MAX * (center - min) / (max - min)).floor() as u32
+        } else {
+            0
+        }
+    };
+    let x = scale(0.5 * (node.min_x + node.max_x), extent.min_x, extent.max_x);
+    let y = scale(0.5 * (node.min_y + node.max_y), extent.min_y, extent.max_y);
+    hilbert(x, y)
+}
+
+/// Map 16-bit coordinates to the index on a Hilbert curve.
+/// Based on public domain code from https://github.com/rawrunprotected/hilbert_curves
+fn hilbert(x: u32, y: u32) -> u32 {
+    let mut a = x ^ y;
+    let mut b = 0xFFFF ^ a;
+    let mut c = 0xFFFF ^ (x | y);
+    let mut d = x & (y ^ 0xFFFF);
+
+    let mut aa = a | (b >> 1);
+    let mut bb = (a >> 1) ^ a;
+    let mut cc = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+    let mut dd = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+    a = aa; b = bb; c = cc; d = dd;
+    aa = (a & (a >> 2)) ^ (b & (b >> 2));
+    bb = (a & (b >> 2)) ^ (b & ((a ^ b) >> 2));
+    cc ^= (a & (c >> 2)) ^ (b & (d >> 2));
+    dd ^= (b & (c >> 2)) ^ ((a ^ b) & (d >> 2));
+
+    a = aa; b = bb; c = cc; d = dd;
+    aa = (a & (a >> 4)) ^ (b & (b >> 4));
+    bb = (a & (b >> 4)) ^ (b & ((a ^ b) >> 4));
+    cc ^= (a & (c >> 4)) ^ (b & (d >> 4));
+    dd ^= (b & (c >> 4)) ^ ((a ^ b) & (d >> 4));
+
+    a = aa; b = bb; c = cc; d = dd;
+    cc ^= (a & (c >> 8)) ^ (b & (d >> 8));
+    dd ^= (b & (c >> 8)) ^ ((a ^ b) & (d >> 8));
+
+    a = cc ^ (cc >> 1);
+    b = dd ^ (dd >> 1);
+
+    let mut i0 = x ^ y;
+    let mut i1 = b | (0xFFFF ^ (i0 | a));
+
+    i0 = (i0 | (i0 << 8)) & 0x00FF00FF;
+    i0 = (i0 | (i0 << 4)) & 0x0F0F0F0F;
+    i0 = (i0 | (i0 << 2)) & 0x33333333;
+    i0 = (i0 | (i0 << 1)) & 0x55555555;
+
+    i1 = (i1 | (i1 << 8)) & 0x00FF00FF;
+    i1 = (i1 | (i1 << 4)) & 0x0F0F0F0F;
+    i1 = (i1 | (i1 << 2)) & 0x33333333;
+    i1 = (i1 | (i1 << 1)) & 0x55555555;
+
+    (i1 << 1) | i0
+}
+
+/// Number of nodes on each level of the tree, starting with the leaves.
+fn level_sizes(num_items: usize, node_size: usize) -> Vec<usize> {
+    let mut sizes = vec![num_items];
+    let mut n = num_items;
+    loop {
+        n = n.div_ceil(node_size);
+        sizes.push(n);
+        if n == 1 {
+            break;
+        }
+    }
+    sizes
+}
+
+/// Build the packed R-tree. The root node comes first and the leaves come last.
+fn build_index(leaves: &[NodeItem], node_size: usize) -> Vec<NodeItem> {
+    let sizes = level_sizes(leaves.len(), node_size);
+    let num_nodes: usize = sizes.iter().sum();
+
+    // Start index of each level, starting with the leaves.
+    let mut level_starts = vec![];
+    let mut end = num_nodes;
+    for size in &sizes {
+        level_starts.push(end - size);
+        end -= size;
+    }
+
+    let mut nodes = vec![NodeItem::empty(0); num_nodes];
+    nodes[level_starts[0]..].copy_from_slice(leaves);
+
+    for level in 0..sizes.len() - 1 {
+        let children = level_starts[level]..level_starts[level] + sizes[level];
+        let mut parent_index = level_starts[level + 1];
+        let mut child_index = children.start;
+        while child_index < children.end {
+            let mut parent = NodeItem::empty(child_index as u64);
+            for _ in 0..node_size {
+                if child_index >= children.end {
+                    break;
+                }
+                parent.expand(&nodes[child_index]);
+                child_index += 1;
+            }
+            nodes[parent_index] = parent;
+            parent_index += 1;
+        }
+    }
+
+    nodes
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flatbuffers::{ForwardsUOffset, Table, Vector};
+
+    fn read_u32(data: &[u8], pos: usize) -> usize {
+        u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize
+    }
+
+    #[test]
+    fn test_index_layout() {
+        assert_eq!(level_sizes(1, 16), vec![1, 1]);
+        assert_eq!(level_sizes(16, 16), vec![16, 1]);
+        assert_eq!(level_sizes(17, 16), vec![17, 2, 1]);
+
+        let leaves: Vec<NodeItem> = (0..20).map(|i| NodeItem {
+            min_x: i as f64,
+            min_y: 0.0,
+            max_x: i as f64 + 1.0,
+            max_y: 1.0,
+            offset: i,
+        }).collect();
+        let nodes = build_index(&leaves, 16);
+        assert_eq!(nodes.len(), 23);
+        // root
+        assert_eq!((nodes[0].min_x, nodes[0].max_x, nodes[0].offset), (0.0, 20.0, 1));
+        // second level points to the leaves
+        assert_eq!((nodes[1].max_x, nodes[1].offset), (16.0, 3));
+        assert_eq!((nodes[2].min_x, nodes[2].offset), (16.0, 19));
+        assert_eq!(nodes[22].offset, 19);
+    }
+
+    #[test]
+    fn test_write() {
+        let mut buffer: Vec<u8> = vec![];
+        {
+            let mut writer = FlatGeobufWriter::new(&mut buffer, "test");
+            for i in 0..3 {
+                let coords = vec![Point4326::new(52.5, 13.4), Point4326::new(52.6, 13.5 + i as f64)];
+                let feature = Feature::new(Geometry::LineString(coords))
+                    .with_property("number", i)
+                    .with_property("name", "a");
+                writer.write_feature(&feature).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        assert_eq!(buffer[..8], MAGIC_BYTES);
+        let header_size = read_u32(&buffer, 8);
+        let header = unsafe {
+            flatbuffers::size_prefixed_root_unchecked::<Table>(&buffer[8..])
+        };
+        unsafe {
+            assert_eq!(header.get::<ForwardsUOffset<&str>>(slot(0), None), Some("test"));
+            assert_eq!(header.get::<u8>(slot(2), None), Some(geometry_type::LINE_STRING));
+            assert_eq!(header.get::<u64>(slot(8), None), Some(3));
+            let envelope = header.get::<ForwardsUOffset<Vector<f64>>>(slot(1), None).unwrap();
+            assert_eq!(envelope.iter().collect::<Vec<_>>(), vec![13.4, 52.5, 15.5, 52.6]);
+            let columns = header.get::<ForwardsUOffset<Vector<ForwardsUOffset<Table>>>>(slot(7), None).unwrap();
+            assert_eq!(columns.len(), 2);
+            assert_eq!(columns.get(0).get::<ForwardsUOffset<&str>>(slot(0), None), Some("number"));
+            assert_eq!(columns.get(0).get::<u8>(slot(1), None), Some(ColumnType::ULong as u8));
+        }
+
+        // root node and three leaves
+        let index_start = 12 + header_size;
+        let features_start = index_start + 4 * 40;
+        let feature_size = read_u32(&buffer, features_start);
+        let feature = unsafe {
+            flatbuffers::size_prefixed_root_unchecked::<Table>(&buffer[features_start..])
+        };
+        unsafe {
+            let geometry = feature.get::<ForwardsUOffset<Table>>(slot(0), None).unwrap();
+            let xy = geometry.get::<ForwardsUOffset<Vector<f64>>>(slot(1), None).unwrap();
+            assert_eq!(xy.len(), 4);
+            let props = feature.get::<ForwardsUOffset<Vector<u8>>>(slot(1), None).unwrap();
+            // column 0 (u16) + u64, column 1 (u16) + length (u32) + "a"
+            assert_eq!(props.len(), 2 + 8 + 2 + 4 + 1);
+        }
+        assert!(features_start + 4 + feature_size < buffer.len());
+    }
+}
@@ -0,0 +1,156 @@
+//! Read features from GeoJSON files and GeoJSON text sequences
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use geojson::{Feature, GeoJson};
+
+use crate::geojson_writer::{GeoJsonFormat, RECORD_SEPARATOR};
+
+
+/// Iterates over all features of a GeoJSON file.
+///
+/// GeoJSON text sequences (RFC 8142) and newline-delimited files are read one feature at a time.
+/// A FeatureCollection has to be parsed completely before the first feature can be returned.
+pub struct FeatureReader<R: Read> {
+    inner: Inner<R>,
+}
+
+enum Inner<R: Read> {
+    Collection(std::vec::IntoIter<Feature>),
+    Sequence {
+        reader: BufReader<R>,
+        /// The byte that terminates each record, either a record separator or a line feed.
+        delimiter: u8,
+        buffer: Vec<u8>,
+    },
+}
+
+impl FeatureReader<File> {
+    /// Open a file and select the format by its extension (see `GeoJsonFormat::from_path`).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<FeatureReader<File>> {
+        let format = GeoJsonFormat::from_path(&path);
+        let file = File::open(path)?;
+        Self::with_format(file, format)
+    }
+}
+
+impl<R: Read> FeatureReader<R> {
+    pub fn with_format(reader: R, format: GeoJsonFormat) -> anyhow::Result<FeatureReader<R>> {
+        let mut reader = BufReader::new(reader);
+        let inner = match format {
+            GeoJsonFormat::FeatureCollection => {
+                let mut geojson_str = String::new();
+                reader.read_to_string(&mut geojson_str)?;
+                match geojson_str.parse::<GeoJson>()? {
+                    GeoJson::FeatureCollection(fc) => Inner::Collection(fc.features.into_iter()),
+                    GeoJson::Feature(feature) => Inner::Collection(vec![feature].into_iter()),
+                    GeoJson::Geometry(_) => anyhow::bail!("GeoJSON file is no FeatureCollection"),
+                }
+            },
+            GeoJsonFormat::Sequence | GeoJsonFormat::NewlineDelimited => {
+                // Files with record separators may also contain pretty-printed features that span
+                // several lines, so split at record separators whenever they are present.
+                let has_separators = reader.fill_buf()?
+                    .iter()
+                    .find(|b| !b.is_ascii_whitespace())
+                    == Some(&RECORD_SEPARATOR);
+                Inner::Sequence {
+                    reader,
+                    delimiter: if has_separators { RECORD_SEPARATOR } else { b'\n' },
+                    buffer: vec![],
+                }
+            },
+        };
+        Ok(FeatureReader { inner })
+    }
+}
+
+impl<R: Read> Iterator for FeatureReader<R> {
+    type Item = anyhow::Result<Feature>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            Inner::Collection(iter) => iter.next().map(Ok),
+            Inner::Sequence { reader, delimiter, buffer } => {
+                loop {
+                    buffer.clear();
+                    match reader.read_until(*delimiter, buffer) {
+                        Ok(0) => return None,
+                        Ok(_) => {},
+                        Err(err) => return Some(Err(err.into())),
+                    }
+
+                    let record: &[u8] = trim_record(buffer);
+                    if record.is_empty() {
+                        // Skip empty lines and the empty record before the first separator.
+                        continue;
+                    }
+                    return Some(serde_json::from_slice::<Feature>(record).map_err(|e| e.into()));
+                }
+            },
+        }
+    }
+}
+
+/// Remove whitespace and record separators from both ends.
+fn trim_record(record: &[u8]) -> &[u8] {
+    let is_junk = |b: &u8| b.is_ascii_whitespace() || *b == RECORD_SEPARATOR;
+    let start = record.iter().position(|b| !is_junk(b)).unwrap_or(record.len());
+    let end = record.iter().rposition(|b| !is_junk(b)).map_or(start, |i| i + 1);
+    &record[start..end]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geojson_writer::GeoJsonWriter;
+    use geomatic::Point4326;
+
+    fn write_features(format: GeoJsonFormat) -> Vec<u8> {
+        let mut buffer: Vec<u8> = vec![];
+        {
+            let mut writer = GeoJsonWriter::with_format(&mut buffer, format).unwrap();
+            for i in 0..3 {
+                let coords = [Point4326::new(52.5, 13.4), Point4326::new(52.6, 13.5 + i as f64)];
+                let mut feat = writer.add_line_string(&coords).unwrap();
+                feat.add_property("number", i).unwrap();
+                feat.finish().unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        buffer
+    }
+
+    fn read_numbers(buffer: &[u8], format: GeoJsonFormat) -> Vec<i64> {
+        FeatureReader::with_format(buffer, format).unwrap()
+            .map(|f| f.unwrap().properties.unwrap()["number"].as_i64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for &format in &[
+            GeoJsonFormat::FeatureCollection,
+            GeoJsonFormat::Sequence,
+            GeoJsonFormat::NewlineDelimited,
+        ] {
+            let buffer = write_features(format);
+            assert_eq!(read_numbers(&buffer, format), vec![0, 1, 2]);
+        }
+
+        let buffer = write_features(GeoJsonFormat::Sequence);
+        assert_eq!(buffer[0], RECORD_SEPARATOR);
+        assert_eq!(buffer.last(), Some(&b'\n'));
+        assert_eq!(buffer.iter().filter(|&&b| b == RECORD_SEPARATOR).count(), 3);
+    }
+
+    #[test]
+    fn test_multi_line_records() {
+        let text = "\x1e{\"type\": \"Feature\",\n \"geometry\": null,\n \"properties\": {\"number\": 5}}\n\
+                    \x1e{\"type\": \"Feature\", \"geometry\": null, \"properties\": {\"number\": 6}}\n";
+        assert_eq!(read_numbers(text.as_bytes(), GeoJsonFormat::Sequence), vec![5, 6]);
+    }
+}
@@ -1,18 +1,62 @@
-//! A simple interface for writing GeoJSON feature collections
+//! A simple interface for writing GeoJSON feature collections and GeoJSON text sequences
 
-use std::fmt::Debug;
 use std::fs::File;
 use std::io::{BufWriter, Write};
 use std::path::Path;
 
 use geomatic::Point4326;
+use serde::Serialize;
 
+use crate::geometry::Geometry;
+use crate::output::{Feature, FeatureSink};
+
+
+/// Number of decimal places of coordinates if not set otherwise. Six decimal places are about
+/// 10 cm.
+pub const DEFAULT_PRECISION: usize = 6;
+
+/// Record separator that starts each feature of a GeoJSON text sequence (RFC 8142).
+pub const RECORD_SEPARATOR: u8 = 0x1e;
+
+/// How features are laid out in a file.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GeoJsonFormat {
+    /// A single FeatureCollection object (RFC 7946).
+    FeatureCollection,
+    /// A GeoJSON text sequence, each feature is preceded by a record separator and followed by a
+    /// line feed (RFC 8142).
+    Sequence,
+    /// One feature per line without record separators (also known as GeoJSONL or ndjson).
+    NewlineDelimited,
+}
+
+impl GeoJsonFormat {
+    /// Select the format by file extension: `*.geojsons` and `*.geojsonseq` are GeoJSON text
+    /// sequences, `*.geojsonl` and `*.ndjson` are newline-delimited. Everything else is written as
+    /// a FeatureCollection.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        let ext = path.as_ref().extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        match ext.as_deref() {
+            Some("geojsons") | Some("geojsonseq") => GeoJsonFormat::Sequence,
+            Some("geojsonl") | Some("ndjson") => GeoJsonFormat::NewlineDelimited,
+            _ => GeoJsonFormat::FeatureCollection,
+        }
+    }
+}
 
 /// Write feature collections
 pub struct GeoJsonWriter<W: Write> {
     writer: BufWriter<W>,
+    format: GeoJsonFormat,
     is_first_feature: bool,
     finished: bool,
+    /// Number of decimal places of coordinates
+    precision: usize,
+    /// Write a bbox member for the whole collection?
+    write_bbox: bool,
+    /// Bounds of all positions written so far (min lon, min lat, max lon, max lat).
+    /// Only written for FeatureCollections.
+    bbox: Option<[f64; 4]>,
 }
 
 
@@ -20,85 +64,190 @@ impl GeoJsonWriter<File> {
     pub fn from_path<P: AsRef<Path>>(path: P)
         -> anyhow::Result<GeoJsonWriter<File>>
     {
+        let format = GeoJsonFormat::from_path(&path);
         let file = File::create(path)?;
-        Self::new(file)
+        Self::with_format(file, format)
     }
 }
 
 impl<W: Write> GeoJsonWriter<W> {
+    /// Write a FeatureCollection, e.g. to an in-memory buffer in tests.
+    #[cfg(test)]
     pub fn new(writer: W)
         -> anyhow::Result<GeoJsonWriter<W>>
+    {
+        Self::with_format(writer, GeoJsonFormat::FeatureCollection)
+    }
+
+    pub fn with_format(writer: W, format: GeoJsonFormat)
+        -> anyhow::Result<GeoJsonWriter<W>>
     {
         let mut writer = BufWriter::new(writer);
-        writer.write(b"{\"type\": \"FeatureCollection\", \"features\": [")?;
+        if format == GeoJsonFormat::FeatureCollection {
+            writer.write_all(b"{\"type\": \"FeatureCollection\", \"features\": [")?;
+        }
         Ok(GeoJsonWriter {
             writer,
+            format,
             is_first_feature: true,
             finished: false,
+            precision: DEFAULT_PRECISION,
+            write_bbox: false,
+            bbox: None,
         })
     }
 
-    pub fn add_line_string(&mut self, coords: &[Point4326]) -> anyhow::Result<FeatureWriter<W>> {
-        if self.is_first_feature {
-            self.is_first_feature = false;
-        } else {
-            write!(self.writer, ",")?;
-        }
+    /// Set the number of decimal places of coordinates for all following features.
+    pub fn set_precision(&mut self, precision: usize) {
+        self.precision = precision;
+    }
 
-        write!(
-            self.writer,
-            "\n{{\"type\": \"Feature\", \
-               \"geometry\": {{\
-                 \"type\": \"LineString\", \
-                 \"coordinates\": [\
-             ",
-        )?;
+    /// Enable or disable writing a bbox member for the whole collection. The bbox is computed
+    /// from all written features and is appended after the features array.
+    pub fn set_write_bbox(&mut self, write_bbox: bool) {
+        self.write_bbox = write_bbox;
+    }
 
-        for (i, point) in coords.iter().enumerate() {
-            if i > 0 {
-                write!(self.writer, ",")?;
-            }
-            write!(
-                self.writer,
-                "[{lon:.6}, {lat:.6}]",
-                lon = point.lon(),
-                lat = point.lat(),
-            )?;
-        }
+    pub fn add_point(&mut self, coord: Point4326) -> anyhow::Result<FeatureWriter<'_, W>> {
+        self.begin_feature("Point")?;
+        self.write_position(coord)?;
+        self.end_geometry()
+    }
 
-        write!(self.writer, "]}}, \"properties\": {{")?;
+    pub fn add_multi_point(&mut self, coords: &[Point4326]) -> anyhow::Result<FeatureWriter<'_, W>> {
+        self.begin_feature("MultiPoint")?;
+        self.write_positions(coords)?;
+        self.end_geometry()
+    }
 
-        Ok(FeatureWriter {
-            gjwriter: self,
-            is_first: true,
-            finished: false,
-        })
+    pub fn add_line_string(&mut self, coords: &[Point4326]) -> anyhow::Result<FeatureWriter<'_, W>> {
+        self.begin_feature("LineString")?;
+        self.write_positions(coords)?;
+        self.end_geometry()
     }
 
-    pub fn add_point(&mut self, coord: Point4326) -> anyhow::Result<FeatureWriter<W>> {
-        if self.is_first_feature {
-            self.is_first_feature = false;
-        } else {
-            write!(self.writer, ",")?;
+    pub fn add_multi_line_string(&mut self, lines: &[Vec<Point4326>]) -> anyhow::Result<FeatureWriter<'_, W>> {
+        self.begin_feature("MultiLineString")?;
+        self.write_list(lines, |w, line| w.write_positions(line))?;
+        self.end_geometry()
+    }
+
+    /// Add a polygon. The first ring is the exterior ring, all following rings are holes. Rings
+    /// are closed automatically if the last position does not equal the first one.
+    pub fn add_polygon(&mut self, rings: &[Vec<Point4326>]) -> anyhow::Result<FeatureWriter<'_, W>> {
+        self.begin_feature("Polygon")?;
+        self.write_list(rings, |w, ring| w.write_ring(ring))?;
+        self.end_geometry()
+    }
+
+    pub fn add_multi_polygon(&mut self, polygons: &[Vec<Vec<Point4326>>]) -> anyhow::Result<FeatureWriter<'_, W>> {
+        self.begin_feature("MultiPolygon")?;
+        self.write_list(polygons, |w, rings| w.write_list(rings, |w, ring| w.write_ring(ring)))?;
+        self.end_geometry()
+    }
+
+    pub fn add_geometry(&mut self, geometry: &Geometry) -> anyhow::Result<FeatureWriter<'_, W>> {
+        match geometry {
+            Geometry::Point(p) => self.add_point(*p),
+            Geometry::MultiPoint(points) => self.add_multi_point(points),
+            Geometry::LineString(line) => self.add_line_string(line),
+            Geometry::MultiLineString(lines) => self.add_multi_line_string(lines),
+            Geometry::Polygon(rings) => self.add_polygon(rings),
+            Geometry::MultiPolygon(polygons) => self.add_multi_polygon(polygons),
+        }
+    }
+
+    /// Write everything up to the coordinates of a new feature.
+    fn begin_feature(&mut self, geometry_type: &str) -> anyhow::Result<()> {
+        match self.format {
+            GeoJsonFormat::FeatureCollection => {
+                if self.is_first_feature {
+                    self.is_first_feature = false;
+                } else {
+                    write!(self.writer, ",")?;
+                }
+                self.writer.write_all(b"\n")?;
+            },
+            GeoJsonFormat::Sequence => self.writer.write_all(&[RECORD_SEPARATOR])?,
+            GeoJsonFormat::NewlineDelimited => {},
         }
         write!(
             self.writer,
-            "\n{{\"type\": \"Feature\", \
+            "{{\"type\": \"Feature\", \
                \"geometry\": {{\
-                 \"type\": \"Point\", \
-                 \"coordinates\": [{lon:.6}, {lat:.6}]}}, \
-               \"properties\": {{\
+                 \"type\": \"{}\", \
+                 \"coordinates\": \
              ",
-            lon = coord.lon(),
-            lat = coord.lat(),
+            geometry_type,
         )?;
+        Ok(())
+    }
+
+    /// Close the geometry and open the properties object.
+    fn end_geometry(&mut self) -> anyhow::Result<FeatureWriter<'_, W>> {
+        write!(self.writer, "}}, \"properties\": {{")?;
         Ok(FeatureWriter {
             gjwriter: self,
             is_first: true,
+            id: None,
             finished: false,
         })
     }
 
+    /// Write a JSON array by calling `write_item` for each item.
+    fn write_list<T, F>(&mut self, items: &[T], mut write_item: F) -> anyhow::Result<()>
+        where F: FnMut(&mut Self, &T) -> anyhow::Result<()>
+    {
+        write!(self.writer, "[")?;
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                write!(self.writer, ",")?;
+            }
+            write_item(self, item)?;
+        }
+        write!(self.writer, "]")?;
+        Ok(())
+    }
+
+    fn write_positions(&mut self, coords: &[Point4326]) -> anyhow::Result<()> {
+        self.write_list(coords, |w, p| w.write_position(*p))
+    }
+
+    /// Write a linear ring and make sure it is closed.
+    fn write_ring(&mut self, coords: &[Point4326]) -> anyhow::Result<()> {
+        match (coords.first(), coords.last()) {
+            (Some(&first), Some(&last)) if first.coords != last.coords => {
+                let mut closed = coords.to_vec();
+                closed.push(first);
+                self.write_positions(&closed)
+            },
+            _ => self.write_positions(coords),
+        }
+    }
+
+    /// Write a single position. Non-finite coordinates are written as `null` to keep the output
+    /// valid JSON.
+    fn write_position(&mut self, point: Point4326) -> anyhow::Result<()> {
+        write!(self.writer, "[")?;
+        write_number(&mut self.writer, point.lon(), self.precision)?;
+        write!(self.writer, ", ")?;
+        write_number(&mut self.writer, point.lat(), self.precision)?;
+        write!(self.writer, "]")?;
+
+        if point.lon().is_finite() && point.lat().is_finite() {
+            self.bbox = Some(match self.bbox {
+                None => [point.lon(), point.lat(), point.lon(), point.lat()],
+                Some(b) => [
+                    b[0].min(point.lon()),
+                    b[1].min(point.lat()),
+                    b[2].max(point.lon()),
+                    b[3].max(point.lat()),
+                ],
+            });
+        }
+        Ok(())
+    }
+
     pub fn finish(mut self) -> anyhow::Result<()> {
         self.mut_finish()
     }
@@ -106,7 +255,20 @@ impl<W: Write> GeoJsonWriter<W> {
     /// A private method that does not move self so Drop can call it.
     fn mut_finish(&mut self) -> anyhow::Result<()> {
         if !self.finished {
-            self.writer.write_all(b"\n]}")?;
+            if self.format == GeoJsonFormat::FeatureCollection {
+                self.writer.write_all(b"\n]")?;
+                if let (true, Some(bbox)) = (self.write_bbox, self.bbox) {
+                    write!(self.writer, ", \"bbox\": [")?;
+                    for (i, &value) in bbox.iter().enumerate() {
+                        if i > 0 {
+                            write!(self.writer, ", ")?;
+                        }
+                        write_number(&mut self.writer, value, self.precision)?;
+                    }
+                    write!(self.writer, "]")?;
+                }
+                self.writer.write_all(b"}")?;
+            }
             self.writer.flush()?;
             self.finished = true;
         }
@@ -114,6 +276,31 @@ impl<W: Write> GeoJsonWriter<W> {
     }
 }
 
+impl<W: Write> FeatureSink for GeoJsonWriter<W> {
+    fn write_feature(&mut self, feature: &Feature) -> anyhow::Result<()> {
+        let mut feat = self.add_geometry(&feature.geometry)?;
+        for (key, value) in &feature.properties {
+            feat.add_property(key, value)?;
+        }
+        if let Some(id) = &feature.id {
+            feat.set_id(id)?;
+        }
+        feat.finish()
+    }
+
+    fn set_precision(&mut self, precision: usize) {
+        GeoJsonWriter::set_precision(self, precision);
+    }
+
+    fn set_write_bbox(&mut self, write_bbox: bool) {
+        GeoJsonWriter::set_write_bbox(self, write_bbox);
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.mut_finish()
+    }
+}
+
 impl<W: Write> Drop for GeoJsonWriter<W> {
     fn drop(&mut self) {
         // drop can't return errors :(
@@ -124,22 +311,29 @@ impl<W: Write> Drop for GeoJsonWriter<W> {
 pub struct FeatureWriter<'a, W: Write> {
     gjwriter: &'a mut GeoJsonWriter<W>,
     is_first: bool,
+    /// Serialized id member
+    id: Option<String>,
     finished: bool,
 }
 
 impl<'a, W: Write> FeatureWriter<'a, W> {
-    pub fn add_property<D: Debug>(&mut self, key: &str, value: D) -> anyhow::Result<()> {
+    /// Add a property with any value that can be serialized to JSON. Keys and strings are
+    /// escaped, non-finite numbers are written as `null`.
+    pub fn add_property<S: Serialize>(&mut self, key: &str, value: S) -> anyhow::Result<()> {
         if self.is_first {
             self.is_first = false;
         } else {
             write!(self.gjwriter.writer, ",")?;
         }
-        write!(
-            self.gjwriter.writer,
-            "\"{}\": {:?}",
-            key,
-            value,
-        )?;
+        serde_json::to_writer(&mut self.gjwriter.writer, key)?;
+        write!(self.gjwriter.writer, ": ")?;
+        serde_json::to_writer(&mut self.gjwriter.writer, &value)?;
+        Ok(())
+    }
+
+    /// Set the id member of the feature, which should be a string or a number.
+    pub fn set_id<S: Serialize>(&mut self, id: S) -> anyhow::Result<()> {
+        self.id = Some(serde_json::to_string(&id)?);
         Ok(())
     }
 
@@ -150,7 +344,14 @@ impl<'a, W: Write> FeatureWriter<'a, W> {
     /// A private method that does not move self so Drop can call it.
     fn mut_finish(&mut self) -> anyhow::Result<()> {
         if !self.finished {
-            self.gjwriter.writer.write_all(b"}}")?;
+            self.gjwriter.writer.write_all(b"}")?;
+            if let Some(id) = &self.id {
+                write!(self.gjwriter.writer, ", \"id\": {}", id)?;
+            }
+            self.gjwriter.writer.write_all(b"}")?;
+            if self.gjwriter.format != GeoJsonFormat::FeatureCollection {
+                self.gjwriter.writer.write_all(b"\n")?;
+            }
             self.finished = true;
         }
         Ok(())
@@ -163,3 +364,173 @@ impl<'a, W: Write> Drop for FeatureWriter<'a, W> {
         let _ = self.mut_finish();
     }
 }
+
+/// Write a number with the given number of decimal places or `null` if it is not finite.
+fn write_number<W: Write>(writer: &mut W, value: f64, precision: usize) -> std::io::Result<()> {
+    if value.is_finite() {
+        write!(writer, "{:.*}", precision, value)
+    } else {
+        write!(writer, "null")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geojson::GeoJson;
+
+    fn parse(buffer: &[u8]) -> geojson::FeatureCollection {
+        let text = std::str::from_utf8(buffer).unwrap();
+        match text.parse::<GeoJson>().unwrap() {
+            GeoJson::FeatureCollection(fc) => fc,
+            _ => panic!("not a FeatureCollection"),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut buffer: Vec<u8> = vec![];
+        {
+            let mut writer = GeoJsonWriter::new(&mut buffer).unwrap();
+            let mut feat = writer.add_point(Point4326::new(52.5, 13.4)).unwrap();
+            feat.add_property("number", 42).unwrap();
+            feat.add_property("ratio", 0.25).unwrap();
+            feat.add_property("flag", true).unwrap();
+            feat.finish().unwrap();
+
+            let coords = [Point4326::new(52.5, 13.4), Point4326::new(52.6, 13.5)];
+            let mut feat = writer.add_line_string(&coords).unwrap();
+            feat.add_property("list", vec![1, 2, 3]).unwrap();
+            feat.add_property("missing", Option::<u32>::None).unwrap();
+            feat.finish().unwrap();
+            writer.finish().unwrap();
+        }
+
+        let fc = parse(&buffer);
+        assert_eq!(fc.features.len(), 2);
+
+        let props = fc.features[0].properties.as_ref().unwrap();
+        assert_eq!(props["number"], 42);
+        assert_eq!(props["ratio"], 0.25);
+        assert_eq!(props["flag"], true);
+        match &fc.features[0].geometry.as_ref().unwrap().value {
+            geojson::Value::Point(p) => assert_eq!(p, &vec![13.4, 52.5]),
+            _ => panic!("not a Point"),
+        }
+
+        let props = fc.features[1].properties.as_ref().unwrap();
+        assert_eq!(props["list"], serde_json::json!([1, 2, 3]));
+        assert!(props["missing"].is_null());
+        match &fc.features[1].geometry.as_ref().unwrap().value {
+            geojson::Value::LineString(ls) => assert_eq!(ls.len(), 2),
+            _ => panic!("not a LineString"),
+        }
+    }
+
+    #[test]
+    fn test_escaping() {
+        let nasty = "quote \" backslash \\ newline \n tab \t umlaut \u{e4} control \u{1}";
+        let mut buffer: Vec<u8> = vec![];
+        {
+            let mut writer = GeoJsonWriter::new(&mut buffer).unwrap();
+            let mut feat = writer.add_point(Point4326::new(52.5, 13.4)).unwrap();
+            feat.add_property("name", nasty).unwrap();
+            feat.add_property("key \"with\" quotes", "value").unwrap();
+            feat.finish().unwrap();
+            writer.finish().unwrap();
+        }
+
+        let fc = parse(&buffer);
+        let props = fc.features[0].properties.as_ref().unwrap();
+        assert_eq!(props["name"], nasty);
+        assert_eq!(props["key \"with\" quotes"], "value");
+    }
+
+    #[test]
+    fn test_non_finite_numbers() {
+        let mut buffer: Vec<u8> = vec![];
+        {
+            let mut writer = GeoJsonWriter::new(&mut buffer).unwrap();
+            let mut feat = writer.add_point(Point4326::new(52.5, 13.4)).unwrap();
+            feat.add_property("nan", f64::NAN).unwrap();
+            feat.add_property("inf", f64::INFINITY).unwrap();
+            feat.add_property("neg_inf", f32::NEG_INFINITY).unwrap();
+            feat.add_property("nested", vec![1.0, f64::NAN]).unwrap();
+            feat.finish().unwrap();
+            writer.finish().unwrap();
+        }
+
+        // Has to be valid JSON
+        let value: serde_json::Value = serde_json::from_slice(&buffer).unwrap();
+        let props = &value["features"][0]["properties"];
+        assert!(props["nan"].is_null());
+        assert!(props["inf"].is_null());
+        assert!(props["neg_inf"].is_null());
+        assert_eq!(props["nested"], serde_json::json!([1.0, null]));
+    }
+
+    #[test]
+    fn test_empty_collection() {
+        let mut buffer: Vec<u8> = vec![];
+        GeoJsonWriter::new(&mut buffer).unwrap().finish().unwrap();
+        assert!(parse(&buffer).features.is_empty());
+    }
+
+    #[test]
+    fn test_geometries() {
+        let a = Point4326::new(52.0, 13.0);
+        let b = Point4326::new(52.0, 14.0);
+        let c = Point4326::new(53.0, 14.0);
+        let d = Point4326::new(51.5, 12.5);
+        let mut buffer: Vec<u8> = vec![];
+        {
+            let mut writer = GeoJsonWriter::new(&mut buffer).unwrap();
+            writer.set_write_bbox(true);
+            writer.set_precision(2);
+            writer.add_multi_point(&[a, b]).unwrap().finish().unwrap();
+            writer.add_multi_line_string(&[vec![a, b], vec![b, c]]).unwrap().finish().unwrap();
+            // open ring that has to be closed by the writer
+            writer.add_polygon(&[vec![a, b, c]]).unwrap().finish().unwrap();
+            let mut feat = writer.add_geometry(&Geometry::MultiPolygon(vec![
+                vec![vec![a, b, c, a]],
+                vec![vec![d, a, b, d]],
+            ])).unwrap();
+            feat.set_id(7).unwrap();
+            feat.add_property("name", "zone").unwrap();
+            feat.finish().unwrap();
+            writer.finish().unwrap();
+        }
+
+        let fc = parse(&buffer);
+        assert_eq!(fc.features.len(), 4);
+        let values: Vec<_> = fc.features.iter()
+            .map(|f| f.geometry.as_ref().unwrap().value.clone())
+            .collect();
+        match &values[0] {
+            geojson::Value::MultiPoint(points) => assert_eq!(points.len(), 2),
+            _ => panic!("not a MultiPoint"),
+        }
+        match &values[1] {
+            geojson::Value::MultiLineString(lines) => assert_eq!(lines.len(), 2),
+            _ => panic!("not a MultiLineString"),
+        }
+        match &values[2] {
+            geojson::Value::Polygon(rings) => {
+                assert_eq!(rings[0].len(), 4);
+                assert_eq!(rings[0][0], rings[0][3]);
+            },
+            _ => panic!("not a Polygon"),
+        }
+        match &values[3] {
+            geojson::Value::MultiPolygon(polygons) => assert_eq!(polygons.len(), 2),
+            _ => panic!("not a MultiPolygon"),
+        }
+        assert_eq!(fc.features[3].id, Some(geojson::feature::Id::Number(7.into())));
+        assert_eq!(fc.bbox, Some(vec![12.5, 51.5, 14.0, 53.0]));
+
+        // Two decimal places
+        let text = std::str::from_utf8(&buffer).unwrap();
+        assert!(text.contains("[13.00, 52.00]"));
+    }
+}
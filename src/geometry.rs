@@ -0,0 +1,114 @@
+//! Simple feature geometries with WGS 84 coordinates.
+
+use geomatic::Point4326;
+
+
+#[derive(Clone, Debug)]
+pub enum Geometry {
+    Point(Point4326),
+    MultiPoint(Vec<Point4326>),
+    LineString(Vec<Point4326>),
+    MultiLineString(Vec<Vec<Point4326>>),
+    /// The first ring is the exterior ring, all following rings are holes.
+    Polygon(Vec<Vec<Point4326>>),
+    MultiPolygon(Vec<Vec<Vec<Point4326>>>),
+}
+
+impl Geometry {
+    /// Convert a GeoJSON geometry. Positions with less than two values are skipped.
+    /// Returns None for a GeometryCollection.
+    pub fn from_geojson(value: &geojson::Value) -> Option<Self> {
+        fn points(positions: &[Vec<f64>]) -> Vec<Point4326> {
+            positions.iter()
+                .filter(|p| p.len() >= 2)
+                .map(|p| Point4326::new(p[1], p[0]))
+                .collect()
+        }
+        fn parts(lines: &[Vec<Vec<f64>>]) -> Vec<Vec<Point4326>> {
+            lines.iter().map(|line| points(line)).collect()
+        }
+        Some(match value {
+            geojson::Value::Point(p) => Geometry::Point(*points(std::slice::from_ref(p)).first()?),
+            geojson::Value::MultiPoint(positions) => Geometry::MultiPoint(points(positions)),
+            geojson::Value::LineString(line) => Geometry::LineString(points(line)),
+            geojson::Value::MultiLineString(lines) => Geometry::MultiLineString(parts(lines)),
+            geojson::Value::Polygon(rings) => Geometry::Polygon(parts(rings)),
+            geojson::Value::MultiPolygon(polygons) => {
+                Geometry::MultiPolygon(polygons.iter().map(|rings| parts(rings)).collect())
+            },
+            geojson::Value::GeometryCollection(_) => return None,
+        })
+    }
+
+    /// Format as Well-known text with the given number of decimal places, e.g.
+    /// `LINESTRING (13.4 52.5, 13.5 52.6)`.
+    pub fn to_wkt(&self, precision: usize) -> String {
+        let position = |p: &Point4326| format!("{:.*} {:.*}", precision, p.lon(), precision, p.lat());
+        let list = |points: &[Point4326]| {
+            format!("({})", points.iter().map(position).collect::<Vec<_>>().join(", "))
+        };
+        let nested = |parts: &[Vec<Point4326>]| {
+            format!("({})", parts.iter().map(|p| list(p)).collect::<Vec<_>>().join(", "))
+        };
+        let (name, body) = match self {
+            Geometry::Point(p) => ("POINT", format!("({})", position(p))),
+            Geometry::MultiPoint(points) => ("MULTIPOINT", list(points)),
+            Geometry::LineString(points) => ("LINESTRING", list(points)),
+            Geometry::MultiLineString(lines) => ("MULTILINESTRING", nested(lines)),
+            Geometry::Polygon(rings) => ("POLYGON", nested(rings)),
+            Geometry::MultiPolygon(polygons) => {
+                let polygons: Vec<String> = polygons.iter().map(|p| nested(p)).collect();
+                ("MULTIPOLYGON", format!("({})", polygons.join(", ")))
+            },
+        };
+        if body == "()" {
+            format!("{} EMPTY", name)
+        } else {
+            format!("{} {}", name, body)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wkt() {
+        let a = Point4326::new(52.5, 13.4);
+        let b = Point4326::new(52.6, 13.5);
+        assert_eq!(Geometry::Point(a).to_wkt(1), "POINT (13.4 52.5)");
+        assert_eq!(Geometry::LineString(vec![a, b]).to_wkt(2), "LINESTRING (13.40 52.50, 13.50 52.60)");
+        assert_eq!(Geometry::LineString(vec![]).to_wkt(2), "LINESTRING EMPTY");
+        assert_eq!(
+            Geometry::MultiPolygon(vec![vec![vec![a, b, a]]]).to_wkt(1),
+            "MULTIPOLYGON (((13.4 52.5, 13.5 52.6, 13.4 52.5)))",
+        );
+    }
+
+    #[test]
+    fn test_from_geojson() {
+        let wkt = |value: geojson::Value| Geometry::from_geojson(&value).map(|g| g.to_wkt(1));
+        assert_eq!(wkt(geojson::Value::Point(vec![13.4, 52.5])), Some("POINT (13.4 52.5)".into()));
+        assert_eq!(wkt(geojson::Value::Point(vec![13.4])), None);
+        assert_eq!(
+            wkt(geojson::Value::MultiPoint(vec![vec![13.4, 52.5], vec![13.5, 52.6, 30.0]])),
+            Some("MULTIPOINT (13.4 52.5, 13.5 52.6)".into()),
+        );
+        assert_eq!(
+            wkt(geojson::Value::MultiLineString(vec![
+                vec![vec![13.4, 52.5], vec![13.5, 52.6]],
+                vec![vec![13.6, 52.7], vec![13.7, 52.8]],
+            ])),
+            Some("MULTILINESTRING ((13.4 52.5, 13.5 52.6), (13.6 52.7, 13.7 52.8))".into()),
+        );
+        assert_eq!(
+            wkt(geojson::Value::MultiPolygon(vec![vec![vec![
+                vec![13.4, 52.5], vec![13.5, 52.6], vec![13.4, 52.6], vec![13.4, 52.5],
+            ]]])),
+            Some("MULTIPOLYGON (((13.4 52.5, 13.5 52.6, 13.4 52.6, 13.4 52.5)))".into()),
+        );
+        assert!(wkt(geojson::Value::GeometryCollection(vec![])).is_none());
+    }
+}
@@ -0,0 +1,266 @@
+//! Render spatial densities as heatmaps to check the inputs and outputs of sampling.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+use geomatic::{laea, Point3035, Point4326};
+
+use crate::bounding_box::BoundingBox;
+use crate::density::DensityClusters;
+use crate::geojson_writer::GeoJsonWriter;
+use crate::geometry::Geometry;
+use crate::network::Network;
+use crate::render::{self, ColorScale, Projection};
+use crate::route::RouteCollectionReader;
+
+
+/// Which end points of routes contribute to a heatmap.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Endpoints {
+    Sources,
+    Destinations,
+    Both,
+}
+
+/// Weights aggregated on a regular grid in EPSG:3035.
+pub struct Heatmap {
+    /// Edge length of the square grid cells in meters.
+    cell_size: f64,
+    /// Maps the cell index (column, row) to the sum of weights.
+    cells: HashMap<(i64, i64), f64>,
+}
+
+impl Heatmap {
+    pub fn new(cell_size: f64) -> Self {
+        assert!(cell_size.is_finite() && cell_size > 0.0, "invalid cell size {}", cell_size);
+        Heatmap {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Add a weight to the cell that contains the given point.
+    pub fn add(&mut self, point: Point4326, weight: f64) {
+        let p: Point3035 = laea::forward(point);
+        let index = (
+            (p.coords.0 / self.cell_size).floor() as i64,
+            (p.coords.1 / self.cell_size).floor() as i64,
+        );
+        *self.cells.entry(index).or_insert(0.0) += weight;
+    }
+
+    /// Add all weighted clusters of the given density.
+    pub fn add_density(&mut self, density: &DensityClusters) {
+        for (&point, &weight) in density.points().iter().zip(density.weights()) {
+            self.add(point, weight as f64);
+        }
+    }
+
+    /// Add the start and/or end points of all routes from the given `*.routes` file.
+    pub fn add_routes<P: AsRef<Path>>(&mut self, path: P, endpoints: Endpoints) -> anyhow::Result<()> {
+        let reader = RouteCollectionReader::new(&path)
+            .with_context(|| format!("Failed to read the routes file {:?}", path.as_ref()))?;
+        for route in reader {
+            let route = route?;
+            if endpoints != Endpoints::Destinations {
+                self.add(route.start_coord.as_point4326(), 1.0);
+            }
+            if endpoints != Endpoints::Sources {
+                self.add(route.end_coord.as_point4326(), 1.0);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn number_of_cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Return the bounds of all non-empty cells.
+    pub fn get_bounds(&self) -> Option<BoundingBox> {
+        let cell_size = self.cell_size;
+        BoundingBox::from_points(self.cells.keys().flat_map(|&(col, row)| {
+            vec![
+                laea::backward(Point3035::new(col as f64 * cell_size, row as f64 * cell_size)),
+                laea::backward(Point3035::new((col + 1) as f64 * cell_size, (row + 1) as f64 * cell_size)),
+            ]
+        }))
+    }
+
+    /// Write all non-empty cells as polygons with their weight.
+    pub fn write_to_geojson<P: AsRef<Path>>(&self, output_path: P) -> anyhow::Result<()> {
+        let mut writer = GeoJsonWriter::from_path(output_path)?;
+        writer.set_write_bbox(true);
+
+        let corner = |col: i64, row: i64| -> Point4326 {
+            laea::backward(Point3035::new(col as f64 * self.cell_size, row as f64 * self.cell_size))
+        };
+
+        for (&(col, row), &weight) in &self.cells {
+            let ring = vec![
+                corner(col, row),
+                corner(col + 1, row),
+                corner(col + 1, row + 1),
+                corner(col, row + 1),
+            ];
+            let mut feat = writer.add_geometry(&Geometry::Polygon(vec![ring]))?;
+            feat.add_property("weight", weight)?;
+            feat.finish()?;
+        }
+
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Render the heatmap with the same projection as `Network::render_image`. Weights are
+    /// mapped logarithmically to colors, because densities like population are heavily skewed.
+    ///
+    /// If a network is given, its edges are drawn on top in gray to reveal misaligned inputs.
+    pub fn render_image(&self, bounds: BoundingBox, width: u32, height: u32, net: Option<&Network>)
+        -> tiny_skia::Canvas
+    {
+        let mut canvas = render::new_canvas(width, height);
+        let projection = Projection::new(bounds, width, height);
+        let color_scale = ColorScale::yl_or_rd();
+
+        let max_weight = self.cells.values().copied().fold(0.0, f64::max);
+        let mut paint = tiny_skia::Paint::default();
+
+        if max_weight > 0.0 {
+            let log_max = max_weight.ln_1p();
+            for (&(col, row), &weight) in &self.cells {
+                if weight <= 0.0 {
+                    continue;
+                }
+                let top_left = projection.project(Point3035::new(
+                    col as f64 * self.cell_size,
+                    (row + 1) as f64 * self.cell_size,
+                ));
+                let bottom_right = projection.project(Point3035::new(
+                    (col + 1) as f64 * self.cell_size,
+                    row as f64 * self.cell_size,
+                ));
+                // Make sure that tiny cells are still visible.
+                let rect = tiny_skia::Rect::from_ltrb(
+                    top_left.0,
+                    top_left.1,
+                    bottom_right.0.max(top_left.0 + 1.0),
+                    bottom_right.1.max(top_left.1 + 1.0),
+                );
+                if let Some(rect) = rect {
+                    paint.set_color(color_scale.color((weight.ln_1p() / log_max) as f32));
+                    canvas.fill_rect(rect, &paint);
+                }
+            }
+        }
+
+        if let Some(net) = net {
+            let stroke = tiny_skia::Stroke {
+                width: 1.0,
+                ..Default::default()
+            };
+            let mut paint = tiny_skia::Paint {
+                anti_alias: true,
+                ..Default::default()
+            };
+            paint.set_color(tiny_skia::Color::from_rgba8(80, 80, 80, 160));
+            for edge in net.edges() {
+                if let Some(path) = projection.line_path(edge.a.as_point3035(), edge.b.as_point3035()) {
+                    canvas.stroke_path(&path, &paint, &stroke);
+                }
+            }
+        }
+
+        canvas
+    }
+
+    pub fn write_png<P: AsRef<Path>>(&self, path: P, bounds: BoundingBox, width: u32, height: u32, net: Option<&Network>)
+        -> anyhow::Result<()>
+    {
+        let canvas = self.render_image(bounds, width, height, net);
+        canvas.pixmap.save_png(&path)
+            .with_context(|| format!("Failed to write PNG file {:?}", path.as_ref()))?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geojson::GeoJson;
+    use crate::test_util::TempFile;
+
+    /// A point in EPSG:3035 near Berlin
+    fn p(x: f64, y: f64) -> Point4326 {
+        laea::backward(Point3035::new(4_550_000.0 + x, 3_270_000.0 + y))
+    }
+
+    fn test_heatmap() -> Heatmap {
+        let mut heatmap = Heatmap::new(1000.0);
+        heatmap.add(p(100.0, 100.0), 1.0);
+        heatmap.add(p(900.0, 900.0), 2.5);
+        heatmap.add(p(1500.0, 200.0), 4.0);
+        heatmap.add(p(-10.0, 500.0), 1.0);
+        heatmap
+    }
+
+    #[test]
+    fn test_add() {
+        let heatmap = test_heatmap();
+        assert_eq!(heatmap.number_of_cells(), 3);
+        let weight = |col: i64, row: i64| heatmap.cells.get(&(4550 + col, 3270 + row)).copied();
+        assert_eq!(weight(0, 0), Some(3.5));
+        assert_eq!(weight(1, 0), Some(4.0));
+        // Cells left of zero are not rounded towards zero
+        assert_eq!(weight(-1, 0), Some(1.0));
+        assert_eq!(weight(0, 1), None);
+    }
+
+    #[test]
+    fn test_bounds() {
+        assert!(Heatmap::new(1000.0).get_bounds().is_none());
+
+        let bounds = test_heatmap().get_bounds().unwrap();
+        // The outer corners of the three cells
+        for corner in &[p(-1000.0, 0.0), p(2000.0, 1000.0)] {
+            assert!((bounds.sw.lat()..=bounds.ne.lat()).contains(&corner.lat()));
+            assert!((bounds.sw.lon()..=bounds.ne.lon()).contains(&corner.lon()));
+        }
+        // Three cells wide and one cell high, with some slack for the projection
+        let sw = laea::forward(bounds.sw);
+        let ne = laea::forward(bounds.ne);
+        assert!((ne.coords.0 - sw.coords.0 - 3000.0).abs() < 200.0);
+        assert!((ne.coords.1 - sw.coords.1 - 1000.0).abs() < 200.0);
+    }
+
+    #[test]
+    fn test_write_to_geojson() {
+        let path = TempFile::new("heatmap.geojson");
+        test_heatmap().write_to_geojson(&path).unwrap();
+
+        let fc = match std::fs::read_to_string(&path).unwrap().parse::<GeoJson>().unwrap() {
+            GeoJson::FeatureCollection(fc) => fc,
+            _ => panic!("not a FeatureCollection"),
+        };
+        assert!(fc.bbox.is_some());
+        assert_eq!(fc.features.len(), 3);
+        let mut weights = vec![];
+        for feature in &fc.features {
+            match &feature.geometry.as_ref().unwrap().value {
+                // Closed square
+                geojson::Value::Polygon(rings) => {
+                    assert_eq!(rings.len(), 1);
+                    assert_eq!(rings[0].len(), 5);
+                    assert_eq!(rings[0][0], rings[0][4]);
+                },
+                _ => panic!("not a Polygon"),
+            }
+            weights.push(feature.properties.as_ref().unwrap()["weight"].as_f64().unwrap());
+        }
+        weights.sort_by(f64::total_cmp);
+        assert_eq!(weights, vec![1.0, 3.5, 4.0]);
+    }
+}
@@ -0,0 +1,29 @@
+//! Stop long running commands gracefully when Ctrl-C is pressed.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Catch the first Ctrl-C (SIGINT) so that it can be polled with `is_interrupted`. A second
+/// Ctrl-C terminates the process as usual.
+#[cfg(unix)]
+pub fn install_handler() {
+    extern "C" fn handle_sigint(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+    }
+
+    unsafe {
+        libc::signal(libc::SIGINT, handle_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install_handler() {}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
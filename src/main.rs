@@ -1,26 +1,50 @@
 use std::fs::File;
+use std::path::Path;
+
 use anyhow::Context;
 use clap::{Arg, ArgGroup, App, AppSettings, SubCommand};
 use geomatic::Point4326;
-use serde::Serialize;
 
 
+mod animation;
 mod bounding_box;
 mod compare;
 mod density;
+mod diff;
+mod flatgeobuf_writer;
+mod geojson_reader;
 mod geojson_writer;
+mod geometry;
+mod heatmap;
+mod interrupt;
 mod network;
+mod output;
 mod polyline;
 mod poi;
+mod render;
 mod route;
+mod route_export;
+mod route_filter;
+mod route_index;
+mod route_stats;
+mod route_tools;
 mod routing_machine;
 mod sampling;
+mod screenline;
+mod select_link;
+mod shapefile_writer;
+#[cfg(test)]
+mod test_util;
+mod trip_length;
+mod turns;
 
+use animation::FrameRecorder;
 use bounding_box::BoundingBox;
 use network::Network;
 use route::RouteCollectionWriter;
 use routing_machine::RoutingMachine;
 use sampling::Sampling;
+use trip_length::TripLength;
 
 
 fn main() -> anyhow::Result<()> {
@@ -45,11 +69,117 @@ fn main() -> anyhow::Result<()> {
                  .takes_value(true)
                  .required(true)
              )
+            .arg(Arg::with_name("resume")
+                 .long("resume")
+                 .help("Append the samples to an existing routes file, e.g. after an interrupted \
+                       run. The sampling options have to match the original run. The traffic counts of the \
+                       existing routes are restored first.")
+             )
             .arg(Arg::with_name("geojson")
                  .long("geojson")
                  .value_name("FILE")
-                 .help("Sets the output GeoJSON file to store the road network with traffic counts")
+                 .help("Sets the output GeoJSON file to store the road network with traffic counts. \
+                       Files ending with .geojsons or .geojsonl are written as GeoJSON text \
+                       sequences or newline-delimited GeoJSON, files ending with .fgb are written \
+                       as FlatGeobuf and files ending with .shp as ESRI Shapefiles.")
+                 .takes_value(true)
+             )
+            .arg(Arg::with_name("csv")
+                 .long("csv")
+                 .value_name("FILE")
+                 .help("Sets the output CSV file to store all edges with traffic counts, OSM node ids, \
+                       length and the geometry as WKT")
+                 .takes_value(true)
+             )
+            .arg(Arg::with_name("turns_csv")
+                 .long("turns-csv")
+                 .value_name("FILE")
+                 .help("Sets the output CSV file for the turning movement counts at intersections")
+                 .takes_value(true)
+             )
+            .arg(Arg::with_name("turns_geojson")
+                 .long("turns-geojson")
+                 .value_name("FILE")
+                 .help("Sets the output file for the turning movements at intersections as \
+                       LineStrings (GeoJSON, FlatGeobuf or Shapefile, selected by file \
+                       extension)")
+                 .takes_value(true)
+             )
+            .arg(Arg::with_name("trip_length_report")
+                 .long("trip-length-report")
+                 .value_name("FILE")
+                 .help("Sets the output CSV file to compare the shares of bee-line and network \
+                       distances per distance band with the target of --log-normal or \
+                       --trip-length-histogram")
+                 .takes_value(true)
+                 .requires("trip_length")
+             )
+            .arg(Arg::with_name("calibrate")
+                 .long("calibrate")
+                 .value_name("DISTANCE")
+                 .help("Adjust the sampling of destinations while sampling, so that the bee-line or \
+                       network distances of the routes match --trip-length-histogram")
                  .takes_value(true)
+                 .possible_values(&["bee-line", "network"])
+                 .requires("trip_length_histogram")
+             )
+            .arg(Arg::with_name("calibrate_every")
+                 .long("calibrate-every")
+                 .value_name("INT")
+                 .help("Sets the number of routes between adjustments of --calibrate")
+                 .takes_value(true)
+                 .default_value("200")
+                 .validator(is_number::<usize>)
+             )
+            .group(ArgGroup::with_name("trip_length")
+                 .args(&["log_normal", "trip_length_histogram"])
+             )
+            .arg(Arg::with_name("frames_every")
+                 .long("frames-every")
+                 .value_name("INT")
+                 .help("Record the cumulative traffic counts after every INT samples as a frame of an \
+                       animation (see --frames and --gif)")
+                 .takes_value(true)
+                 .validator(is_positive::<u32>)
+                 .requires("animation_output")
+             )
+            .args(&animation_output_args())
+            .group(ArgGroup::with_name("animation_output")
+                 .args(&["frames", "gif"])
+                 .multiple(true)
+                 .requires("frames_every"))
+            .arg(Arg::with_name("frame_size")
+                 .long("frame-size")
+                 .value_name("PIXELS")
+                 .help("Sets the width and height of each frame of --frames-every")
+                 .takes_value(true)
+                 .default_value("1024")
+                 .validator(is_positive::<u16>)
+             )
+            .arg(Arg::with_name("frame_delay")
+                 .long("frame-delay")
+                 .value_name("CENTISECONDS")
+                 .help("Sets the duration of each frame of the GIF in hundredths of a second")
+                 .takes_value(true)
+                 .default_value("10")
+                 .validator(is_number::<u16>)
+             )
+            .arg(Arg::with_name("merge_polylines")
+                 .long("merge-polylines")
+                 .help("Merge chains of edges between intersections into single LineStrings with count
+                       statistics in the GeoJSON output.")
+             )
+            .arg(Arg::with_name("edge_attributes")
+                 .long("edge-attributes")
+                 .help("Add the OSM node ids and the length of each edge to the output of --geojson")
+             )
+            .arg(Arg::with_name("precision")
+                 .long("precision")
+                 .value_name("DIGITS")
+                 .help("Sets the number of decimal places of coordinates in the GeoJSON and CSV output")
+                 .takes_value(true)
+                 .default_value("6")
+                 .validator(is_number::<usize>)
              )
             .arg(Arg::with_name("compare")
                  .long("compare")
@@ -58,10 +188,19 @@ fn main() -> anyhow::Result<()> {
                  .takes_value(true)
                  .number_of_values(2),
              )
+            .arg(Arg::with_name("connections")
+                 .long("connections")
+                 .value_name("FILE")
+                 .help("Sets the output file to store the matched connections of --compare \
+                       (GeoJSON or FlatGeobuf, selected by file extension)")
+                 .takes_value(true)
+                 .default_value("connections.geojson")
+             )
             .arg(Arg::with_name("png")
                  .long("png")
                  .value_name("FILE")
-                 .help("Sets the output PNG file to store a rendering of the road network with traffic counts")
+                 .help("Sets the output PNG file to store a rendering of the road network with traffic counts.
+                       Comparison results are drawn on top if --compare is given.")
                  .takes_value(true)
              )
             .arg(Arg::with_name("number")
@@ -73,65 +212,599 @@ fn main() -> anyhow::Result<()> {
                  .required(true)
                  .validator(is_number::<u32>)
              )
-            .arg(Arg::with_name("bounds")
-                 .long("bounds")
-                 .value_name("sw.lat sw.lon ne.lat ne.lon")
-                 .help("Sets the bounding box. Input values are the two coordinate pairs for the
-                       south-west and the north-east corner of the bounding box")
+            .args(&sampling_args())
+            .group(sampling_group())
+        )
+        .subcommand(SubCommand::with_name("animate")
+            .about("Render the traffic counts of a sweep over the maximum distance as an animation. \
+                   See \"sample --frames-every\" for the cumulative traffic counts while sampling.")
+            .arg(Arg::with_name("osrm")
+                 .long("osrm")
+                 .value_name("FILE")
+                 .help("Sets an input *.osrm file")
                  .takes_value(true)
-                 .number_of_values(4)
-                 .validator(is_number::<f64>)
+                 .required(true)
+            )
+            .arg(Arg::with_name("number")
+                 .long("number")
+                 .short("n")
+                 .value_name("INT")
+                 .help("Sets the number of samples per frame")
+                 .takes_value(true)
+                 .required(true)
+                 .validator(is_number::<u32>)
              )
-            .arg(Arg::with_name("max_dist")
-                 .long("max-dist")
-                 .value_name("METERS")
-                 .help("Sets the maximum distance between source and destination points in
-                       meters.")
+            .args(&animation_output_args())
+            .group(ArgGroup::with_name("animation_output")
+                 .args(&["frames", "gif"])
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("sweep_max_dist")
+                 .long("sweep-max-dist")
+                 .value_names(&["METERS", "STEPS"])
+                 .help("Render one frame for each of STEPS maximum distances, going from the value of
+                       --max-dist to METERS. Traffic counts are reset for each frame.")
                  .takes_value(true)
-                 .validator(is_number::<f64>)
+                 .number_of_values(2)
+                 .required(true)
+                 .requires("max_dist")
              )
-            .arg(Arg::with_name("uniform2d")
-                 .long("uniform2d")
-                 .help("Sample the 2D plane uniformly.")
-                 .requires_all(&["bounds", "max_dist"])
+            .arg(Arg::with_name("size")
+                 .long("size")
+                 .value_name("PIXELS")
+                 .help("Sets the width and height of each frame")
+                 .takes_value(true)
+                 .default_value("1024")
+                 .validator(is_positive::<u16>)
              )
-            .arg(Arg::with_name("weighted")
-                 .long("weighted")
-                 .value_name("FILE.csv")
-                 .help("sample from a list of weighted points from the given CSV file.")
+            .arg(Arg::with_name("delay")
+                 .long("delay")
+                 .value_name("CENTISECONDS")
+                 .help("Sets the duration of each frame of the GIF in hundredths of a second")
+                 .takes_value(true)
+                 .default_value("10")
+                 .validator(is_number::<u16>)
+             )
+            .args(&sampling_args())
+            .group(sampling_group())
+        )
+        .subcommand(SubCommand::with_name("diff")
+            .about("Compare the traffic counts of two runs on the same road network.")
+            .arg(Arg::with_name("before")
+                 .long("before")
+                 .value_name("FILE")
+                 .help("Sets the first input, either a *.routes file or a GeoJSON file with traffic counts")
+                 .takes_value(true)
+                 .required(true)
+            )
+            .arg(Arg::with_name("after")
+                 .long("after")
+                 .value_name("FILE")
+                 .help("Sets the second input, either a *.routes file or a GeoJSON file with traffic counts")
+                 .takes_value(true)
+                 .required(true)
+            )
+            .arg(Arg::with_name("osrm")
+                 .long("osrm")
+                 .value_name("FILE")
+                 .help("Sets the *.osrm file to replay *.routes files on. Defaults to the file that is
+                       referenced by the first *.routes file.")
+                 .takes_value(true)
+            )
+            .arg(Arg::with_name("property")
+                 .long("property")
+                 .value_name("GEOJSON PROPERTY")
+                 .help("Sets the name of the numerical property of GeoJSON inputs that contains the traffic counts")
+                 .takes_value(true)
+                 .default_value("number")
+            )
+            .arg(Arg::with_name("geojson")
+                 .long("geojson")
+                 .value_name("FILE")
+                 .help("Sets the output GeoJSON file to store both counts and the differences. Files \
+                       ending with .fgb are written as FlatGeobuf and files ending with .shp as \
+                       ESRI Shapefiles.")
                  .takes_value(true)
-                 .requires_all(&["max_dist"])
              )
-            .arg(Arg::with_name("complex")
-                 .long("complex")
-                 .help("Use complex sampling that combines different density inputs.")
-                 .takes_value(false)
-                 .requires_all(&["population", "pois"])
+            .arg(Arg::with_name("png")
+                 .long("png")
+                 .value_name("FILE")
+                 .help("Sets the output PNG file to store a rendering of the differences")
+                 .takes_value(true)
              )
-            .arg(Arg::with_name("population")
-                 .long("population")
-                 .value_name("FILE.csv")
-                 .help("Specify population density as weighted points from the given CSV file.")
+            .arg(Arg::with_name("relative")
+                 .long("relative")
+                 .help("Color the PNG rendering by relative instead of absolute differences.")
+             )
+            .arg(Arg::with_name("bounds")
+                 .long("bounds")
+                 .value_name("sw.lat sw.lon ne.lat ne.lon")
+                 .help("Sets the bounding box of the PNG rendering.")
                  .takes_value(true)
+                 .number_of_values(4)
+                 .validator(is_number::<f64>)
              )
-            .arg(Arg::with_name("pois")
-                 .long("pois")
+        )
+        .subcommand(SubCommand::with_name("heatmap")
+            .about("Render density inputs or the sampled end points of routes as a heatmap.")
+            .arg(Arg::with_name("density")
+                 .long("density")
                  .value_name("FILE.csv")
-                 .help("Specify POI density as weighted points from the given CSV file.")
+                 .help("Adds weighted points from the given CSV file (e.g. population or POI density)")
                  .takes_value(true)
-             )
-            .group(ArgGroup::with_name("sampling")
-                 .args(&["uniform2d", "weighted", "complex"])
+                 .multiple(true)
+                 .number_of_values(1)
+            )
+            .arg(Arg::with_name("routes")
+                 .long("routes")
+                 .value_name("FILE")
+                 .help("Adds the end points of the routes from the given *.routes file")
+                 .takes_value(true)
+            )
+            .group(ArgGroup::with_name("heatmap_input")
+                 .args(&["density", "routes"])
+                 .multiple(true)
                  .required(true))
+            .arg(Arg::with_name("endpoints")
+                 .long("endpoints")
+                 .value_name("WHICH")
+                 .help("Selects which end points of routes are included")
+                 .takes_value(true)
+                 .possible_values(&["sources", "destinations", "both"])
+                 .default_value("both")
+            )
+            .arg(Arg::with_name("png")
+                 .long("png")
+                 .value_name("FILE")
+                 .help("Sets the output PNG file")
+                 .takes_value(true)
+            )
+            .arg(Arg::with_name("geojson")
+                 .long("geojson")
+                 .value_name("FILE")
+                 .help("Sets the output GeoJSON file to store the grid cells as polygons")
+                 .takes_value(true)
+            )
+            .group(ArgGroup::with_name("heatmap_output")
+                 .args(&["png", "geojson"])
+                 .multiple(true)
+                 .required(true))
+            .arg(Arg::with_name("osrm")
+                 .long("osrm")
+                 .value_name("FILE")
+                 .help("Draws the road network of the given *.osrm file on top of the heatmap and uses its
+                       bounds if --bounds is not given")
+                 .takes_value(true)
+            )
+            .arg(Arg::with_name("bounds")
+                 .long("bounds")
+                 .value_name("sw.lat sw.lon ne.lat ne.lon")
+                 .help("Sets the bounding box. Input values are the two coordinate pairs for the
+                       south-west and the north-east corner of the bounding box")
+                 .takes_value(true)
+                 .number_of_values(4)
+                 .validator(is_number::<f64>)
+             )
+            .arg(Arg::with_name("cell_size")
+                 .long("cell-size")
+                 .value_name("METERS")
+                 .help("Sets the edge length of the grid cells")
+                 .takes_value(true)
+                 .default_value("100")
+                 .validator(is_positive_distance)
+             )
         )
         .subcommand(SubCommand::with_name("routes")
             .about("Read *.routes files.")
-            .arg(Arg::with_name("input")
-                 .long("input")
-                 .value_name("FILE")
-                 .help("Sets an input *.routes file")
-                 .takes_value(true)
-                 .required(true)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("export")
+                .about("Export routes as CSV and vector files.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("csv")
+                     .long("csv")
+                     .value_name("FILE")
+                     .help("Sets the output CSV file with the origin, destination, distance, bee \
+                           line distance, detour factor and duration of each route")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("geojson")
+                     .long("geojson")
+                     .value_name("FILE")
+                     .help("Sets the output file for the paths of the routes. The format is selected \
+                           by the file extension like for \"sample --geojson\".")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("od_points")
+                     .long("od-points")
+                     .value_name("FILE")
+                     .help("Sets the output file for the origin and destination points of the routes")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("filter")
+                     .long("filter")
+                     .value_name("EXPR")
+                     .help("Only export routes that match the expression, e.g. \
+                           \"detour > 10 && bee_line > 105\". The variables index, distance, \
+                           bee_line, detour, duration and nodes can be compared with numbers and \
+                           combined with &&, || and !.")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("osrm")
+                     .long("osrm")
+                     .value_name("FILE")
+                     .help("Sets the *.osrm file for the paths of the routes. Defaults to the file \
+                           that the routes were sampled on.")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("precision")
+                     .long("precision")
+                     .value_name("DIGITS")
+                     .help("Sets the number of decimal places of coordinates in the GeoJSON output")
+                     .takes_value(true)
+                     .default_value("6")
+                     .validator(is_number::<usize>)
+                )
+                .group(ArgGroup::with_name("outputs")
+                     .args(&["csv", "geojson", "od_points"])
+                     .multiple(true)
+                     .required(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("info")
+                .about("Print the header of a *.routes file including how the routes were sampled.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("index")
+                .about("Create the index FILE.idx for random access to the routes of an older or \
+                       unfinished *.routes file. Finished files are always indexed.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("show")
+                .about("Print individual routes of a *.routes file.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("route")
+                     .long("route")
+                     .value_name("INDEX")
+                     .help("Print the route with the given zero-based index")
+                     .takes_value(true)
+                     .validator(is_number::<u64>)
+                )
+                .arg(Arg::with_name("count")
+                     .long("count")
+                     .value_name("N")
+                     .help("Print N consecutive routes, starting at --route")
+                     .takes_value(true)
+                     .requires("route")
+                     .validator(is_number::<u64>)
+                )
+                .arg(Arg::with_name("random")
+                     .long("random")
+                     .value_name("N")
+                     .help("Print N randomly selected routes")
+                     .takes_value(true)
+                     .validator(is_number::<u64>)
+                )
+                .group(ArgGroup::with_name("selection")
+                     .args(&["route", "random"])
+                     .required(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("stats")
+                .about("Print and export the distributions of trip lengths, detour factors and \
+                       node counts.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("quantiles")
+                     .long("quantiles")
+                     .value_name("FILE")
+                     .help("Sets the output CSV file for mean, minimum, maximum and quantiles")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("histograms")
+                     .long("histograms")
+                     .value_name("FILE")
+                     .help("Sets the output CSV file for histograms")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("charts")
+                     .long("charts")
+                     .value_name("FILE")
+                     .help("Sets the output files for histogram charts. \"{}\" is replaced with \
+                           the name of each variable, e.g. hist_{}.svg. Files ending with .png \
+                           are rendered without labels.")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("bins")
+                     .long("bins")
+                     .value_name("N")
+                     .help("Sets the number of histogram bins between the minimum and the 99th \
+                           percentile")
+                     .takes_value(true)
+                     .default_value("50")
+                     .validator(is_number::<usize>)
+                )
+                .arg(Arg::with_name("filter")
+                     .long("filter")
+                     .value_name("EXPR")
+                     .help("Only include routes that match the expression (see \"routes export\")")
+                     .takes_value(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("select-link")
+                .about("Find the routes that use the selected edges and show where they come from \
+                       and go to.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("edge")
+                     .long("edge")
+                     .value_name("OSM_NODE_ID")
+                     .help("Selects the edge between two OSM nodes. Can be given several times.")
+                     .takes_value(true)
+                     .number_of_values(2)
+                     .multiple(true)
+                     .validator(is_number::<i64>)
+                )
+                .arg(Arg::with_name("near")
+                     .long("near")
+                     .value_name("lat lon")
+                     .help("Selects the edge that is nearest to the coordinate. Can be given \
+                           several times.")
+                     .takes_value(true)
+                     .number_of_values(2)
+                     .multiple(true)
+                     .validator(is_number::<f64>)
+                )
+                .group(ArgGroup::with_name("links")
+                     .args(&["edge", "near"])
+                     .multiple(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("osrm")
+                     .long("osrm")
+                     .value_name("FILE")
+                     .help("Sets the *.osrm file. Defaults to the file that the routes were sampled \
+                           on.")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("geojson")
+                     .long("geojson")
+                     .value_name("FILE")
+                     .help("Sets the output file for the network load of the selected routes")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("od_points")
+                     .long("od-points")
+                     .value_name("FILE")
+                     .help("Sets the output file for the origins and destinations of the selected \
+                           routes")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("links_output")
+                     .long("links")
+                     .value_name("FILE")
+                     .help("Sets the output file for the selected links")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("png")
+                     .long("png")
+                     .value_name("FILE")
+                     .help("Sets the output PNG file for a map of the selected routes")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("precision")
+                     .long("precision")
+                     .value_name("DIGITS")
+                     .help("Sets the number of decimal places of coordinates in the GeoJSON output")
+                     .takes_value(true)
+                     .default_value("6")
+                     .validator(is_number::<usize>)
+                )
+            )
+            .subcommand(SubCommand::with_name("screenlines")
+                .about("Count the routes that cross screenlines and cordons, by direction. \
+                       Crossings from the right to the left of a screenline and into a cordon are \
+                       inbound.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("lines")
+                     .long("lines")
+                     .value_name("FILE")
+                     .help("Sets a GeoJSON file with screenlines (LineString) and cordons \
+                           (Polygon). Features are named by their \"name\" property.")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("osrm")
+                     .long("osrm")
+                     .value_name("FILE")
+                     .help("Sets the *.osrm file. Defaults to the file that the routes were sampled \
+                           on.")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("counts")
+                     .long("counts")
+                     .value_name("FILE")
+                     .help("Sets the output CSV file for the crossing counts of each line")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("edges")
+                     .long("edges")
+                     .value_name("FILE")
+                     .help("Sets the output CSV file for the network edges that each line cuts")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("filter")
+                     .long("filter")
+                     .value_name("EXPR")
+                     .help("Only include routes that match the expression (see \"routes export\")")
+                     .takes_value(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("turns")
+                .about("Count the turning movements of all routes at intersections.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("osrm")
+                     .long("osrm")
+                     .value_name("FILE")
+                     .help("Sets the *.osrm file. Defaults to the file that the routes were sampled \
+                           on.")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("turns_csv")
+                     .long("csv")
+                     .value_name("FILE")
+                     .help("Sets the output CSV file for the turning movement counts at intersections")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("turns_geojson")
+                     .long("geojson")
+                     .value_name("FILE")
+                     .help("Sets the output file for the turning movements at intersections as \
+                           LineStrings (GeoJSON, FlatGeobuf or Shapefile, selected by file \
+                           extension)")
+                     .takes_value(true)
+                )
+                .group(ArgGroup::with_name("turns_output")
+                     .args(&["turns_csv", "turns_geojson"])
+                     .multiple(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("filter")
+                     .long("filter")
+                     .value_name("EXPR")
+                     .help("Only include routes that match the expression (see \"routes export\")")
+                     .takes_value(true)
+                )
+                .arg(Arg::with_name("precision")
+                     .long("precision")
+                     .value_name("DIGITS")
+                     .help("Sets the number of decimal places of coordinates in the GeoJSON output")
+                     .takes_value(true)
+                     .default_value("6")
+                     .validator(is_number::<usize>)
+                )
+            )
+            .subcommand(SubCommand::with_name("merge")
+                .about("Combine *.routes files that were sampled on the same *.osrm file.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets the input *.routes files")
+                     .takes_value(true)
+                     .multiple(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("output")
+                     .long("output")
+                     .value_name("FILE")
+                     .help("Sets the output *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("split")
+                .about("Split a *.routes file into parts of consecutive routes.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("parts")
+                     .long("parts")
+                     .value_name("N")
+                     .help("Sets the number of parts")
+                     .takes_value(true)
+                     .required(true)
+                     .validator(is_number::<u64>)
+                )
+                .arg(Arg::with_name("output")
+                     .long("output")
+                     .value_name("FILE")
+                     .help("Sets the output *.routes files. \"{}\" is replaced with the number of \
+                           each part, e.g. part_{}.routes")
+                     .takes_value(true)
+                     .required(true)
+                )
+            )
+            .subcommand(SubCommand::with_name("sample")
+                .about("Write a random subset of the routes of a *.routes file.")
+                .arg(Arg::with_name("input")
+                     .long("input")
+                     .value_name("FILE")
+                     .help("Sets an input *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("output")
+                     .long("output")
+                     .value_name("FILE")
+                     .help("Sets the output *.routes file")
+                     .takes_value(true)
+                     .required(true)
+                )
+                .arg(Arg::with_name("fraction")
+                     .long("fraction")
+                     .value_name("FRACTION")
+                     .help("Sets the probability to keep each route, between 0 and 1")
+                     .takes_value(true)
+                     .required(true)
+                     .validator(is_number::<f64>)
+                )
+                .arg(Arg::with_name("seed")
+                     .long("seed")
+                     .value_name("INT")
+                     .help("Sets the seed of the random number generator. A random seed is chosen \
+                           by default")
+                     .takes_value(true)
+                     .validator(is_number::<u64>)
+                )
             )
         )
         .subcommand(SubCommand::with_name("filter-poi")
@@ -162,17 +835,8 @@ fn run(matches: clap::ArgMatches) -> anyhow::Result<()> {
         let osrm_path = matches.value_of("osrm").unwrap();
         let routes_path = matches.value_of("routes").unwrap();
 
-        let bounds = if matches.is_present("bounds") {
-            let aabb: Vec<_> = matches.values_of("bounds").unwrap()
-                .map(|s| s.parse::<f64>().unwrap()).collect();
-            assert_eq!(aabb.len(), 4);
-            Some(BoundingBox::new(
-                Point4326::new(aabb[0], aabb[1]),
-                Point4326::new(aabb[2], aabb[3]))
-            )
-        } else {
-            None
-        };
+        let bounds = parse_bounds(matches);
+        let mut seed = parse_seed(matches);
 
         let mut machine = RoutingMachine::new();
         machine.test_connection()
@@ -185,81 +849,496 @@ fn run(matches: clap::ArgMatches) -> anyhow::Result<()> {
                 "Failed to read *.osrm file {:?}", osrm_path
             ))?;
 
-        let mut writer = RouteCollectionWriter::new(
-            routes_path,
-            osrm_path,
-            "sample",
-        )?;
+        let mut tracking = SampleTracking {
+            turns: if matches.is_present("turns_csv") || matches.is_present("turns_geojson") {
+                Some(turns::TurnCounts::for_network(&net))
+            } else {
+                None
+            },
+            calibration: None,
+            frames: matches.value_of("frames_every")
+                .map(|every| (FrameRecorder::new(), every.parse::<u32>().unwrap())),
+        };
 
-        if matches.is_present("uniform2d") {
-            let max_dist: f64 = matches.value_of("max_dist").unwrap().parse::<f64>()?;
-            let mut uni_sample = sampling::Uniform2D::new(bounds.unwrap(), max_dist);
-            sample(&mut uni_sample, number_of_samples, &mut machine, &mut writer, &mut net)?;
-        } else if matches.is_present("weighted") {
-            let max_dist: f64 = matches.value_of("max_dist").unwrap().parse::<f64>()?;
-            let csv_path = matches.value_of("weighted").unwrap();
-            let mut sampl = sampling::Weighted::from_csv(csv_path, bounds, max_dist)?;
-            sample(&mut sampl, number_of_samples, &mut machine, &mut writer, &mut net)?;
-        } else if matches.is_present("complex") {
-            let max_dist: f64 = matches.value_of("max_dist").unwrap().parse::<f64>()?;
-            let population_csv = matches.value_of("population").unwrap();
-            let poi_csv = matches.value_of("pois").unwrap();
-            let mut sampl = sampling::Complex::from_csv(population_csv, poi_csv, bounds, max_dist)?;
-            sample(&mut sampl, number_of_samples, &mut machine, &mut writer, &mut net)?;
-        }
+        let mut writer = if matches.is_present("resume") {
+            println!("Resume routes file {:?}", routes_path);
+            // Check the configuration before the file is modified.
+            {
+                let reader = route::RouteCollectionReader::new(routes_path)
+                    .with_context(|| format!("Failed to read the routes file {:?}", routes_path))?;
+                let header = reader.header();
+                if !route_tools::same_file(&header.osrm_file, osrm_path) {
+                    anyhow::bail!(
+                        "The routes file {:?} was sampled on {:?}, not on {:?}",
+                        routes_path,
+                        header.osrm_file,
+                        osrm_path,
+                    );
+                }
+                let differences = header.metadata
+                    .differences(&run_metadata(matches, bounds, seed, &machine)?);
+                if !differences.is_empty() {
+                    anyhow::bail!(
+                        "Cannot resume {:?} with different options: {}",
+                        routes_path,
+                        differences.join("; "),
+                    );
+                }
+                if matches.is_present("seed") && seed != header.metadata.seed {
+                    anyhow::bail!(
+                        "Cannot resume {:?} with --seed {}, it was sampled with seed {}",
+                        routes_path,
+                        seed,
+                        header.metadata.seed,
+                    );
+                }
+            }
+            let writer = RouteCollectionWriter::resume(
+                routes_path,
+                |route| {
+                    net.bump_edges(&route.node_ids);
+                    if let Some(turns) = &mut tracking.turns {
+                        turns.add_route(&route.node_ids);
+                    }
+                },
+            ).with_context(|| format!("Failed to resume the routes file {:?}", routes_path))?;
+            seed = writer.header().resume_seed();
+            println!("Restored {} routes", writer.header().number_of_routes());
+            writer
+        } else {
+            RouteCollectionWriter::new(
+                routes_path,
+                osrm_path,
+                "sample",
+                run_metadata(matches, bounds, seed, &machine)?,
+            )?
+        };
+
+        interrupt::install_handler();
+        let mut sampl = build_sampling(matches, bounds, seed)?;
+        tracking.calibration = match matches.value_of("calibrate") {
+            Some(measure) => {
+                let measure = if measure == "network" {
+                    trip_length::DistanceMeasure::Network
+                } else {
+                    trip_length::DistanceMeasure::BeeLine
+                };
+                let every = matches.value_of("calibrate_every").unwrap().parse::<usize>().unwrap();
+                let target = parse_trip_length(matches)?.unwrap();
+                Some(trip_length::Calibration::new(&target, measure, every)?)
+            },
+            None => None,
+        };
+        sample(&mut *sampl, number_of_samples, &mut machine, &mut writer, &mut net, &mut tracking)?;
 
         writer.finish()?;
 
-        if let Some(geojson_path) = matches.value_of("geojson") {
-            net.write_to_geojson(geojson_path)?;
+        if let Some((mut recorder, _)) = tracking.frames {
+            // Include the last samples if they don't fill a whole interval
+            if net.edge_counts() != recorder.last_counts() {
+                recorder.record(&net);
+            }
+            println!("Render {} frames", recorder.number_of_frames());
+            let bounds = bounds.unwrap_or_else(|| net.get_bounds());
+            recorder.render(
+                &mut net,
+                bounds,
+                matches.value_of("frame_size").unwrap().parse::<u16>().unwrap() as u32,
+                matches.value_of("frames").map(Path::new),
+                matches.value_of("gif").map(Path::new),
+                matches.value_of("frame_delay").unwrap().parse::<u16>().unwrap(),
+            )?;
         }
 
-        if let Some(png_path) = matches.value_of("png") {
-            if let Some(bounds) = bounds {
-                net.write_png(png_path, bounds, 2048, 2048)?;
+        if let Some(trip_length) = parse_trip_length(matches)? {
+            let max_dist = parse_max_dist(matches, Some(&trip_length)).unwrap();
+            let mut bee_line = vec![];
+            let mut network = vec![];
+            for route in route::RouteCollectionReader::new(routes_path)? {
+                let route = route?;
+                bee_line.push(route.distance_bee_line());
+                network.push(route.distance);
+            }
+            let report = trip_length.verify(max_dist, &bee_line, &network, 20);
+            report.print_summary();
+            if let Some(path) = matches.value_of("trip_length_report") {
+                report.write_csv(path)?;
+            }
+        }
+
+        let precision = matches.value_of("precision").unwrap().parse::<usize>().unwrap();
+
+        if let Some(csv_path) = matches.value_of("csv") {
+            net.write_to_csv(csv_path, precision)?;
+        }
+
+        if let Some(turns) = &tracking.turns {
+            write_turns(matches, turns, &net, precision)?;
+        }
+
+        if let Some(geojson_path) = matches.value_of("geojson") {
+            let edge_attributes = matches.is_present("edge_attributes");
+            if matches.is_present("merge_polylines") {
+                let polylines = net.build_polylines();
+                polylines.write_to_file_with_counts(geojson_path, &net, precision, edge_attributes)?;
             } else {
-                net.write_png(png_path, net.get_bounds(), 2048, 2048)?;
+                net.write_to_file(geojson_path, precision, edge_attributes)?;
             }
         }
 
-        if let Some(mut compare_args) = matches.values_of("compare") {
+        let comparison = if let Some(mut compare_args) = matches.values_of("compare") {
             let geojson_path = compare_args.next().unwrap();
             let number_property = compare_args.next().unwrap();
-            compare::compare(&net, geojson_path, number_property)?;
+            let connections_path = matches.value_of("connections").unwrap();
+            Some(compare::compare(&net, geojson_path, number_property, connections_path)?)
+        } else {
+            None
+        };
+
+        if let Some(png_path) = matches.value_of("png") {
+            let bounds = bounds.unwrap_or_else(|| net.get_bounds());
+            if let Some(comparison) = &comparison {
+                let mut canvas = net.render_image(bounds, 2048, 2048);
+                compare::draw_comparison(&mut canvas, bounds, comparison);
+                canvas.pixmap.save_png(png_path)
+                    .with_context(|| format!("Failed to write PNG file {:?}", png_path))?;
+            } else {
+                net.write_png(png_path, bounds, 2048, 2048)?;
+            }
         }
-    } else if let Some(matches) = matches.subcommand_matches("routes") {
-        let routes_path = matches.value_of("input").unwrap();
-        let reader = route::RouteCollectionReader::new(&routes_path)
+    } else if let Some(matches) = matches.subcommand_matches("animate") {
+        let number_of_samples = matches.value_of("number").unwrap().parse::<u32>().unwrap();
+        let osrm_path = matches.value_of("osrm").unwrap();
+        let size = matches.value_of("size").unwrap().parse::<u16>().unwrap() as u32;
+        let delay = matches.value_of("delay").unwrap().parse::<u16>().unwrap();
+        let bounds = parse_bounds(matches);
+
+        let mut machine = RoutingMachine::new();
+        machine.test_connection()
+            .context("Failed to connect to routing server. Start osrm-routed like this:\
+                     \n    osrm-routed --algorithm mld an_example_file.osrm")?;
+
+        println!("Read *.osrm file {:?}", osrm_path);
+        let mut net = Network::from_path(osrm_path)
             .with_context(|| format!(
-                "Failed to read the routes file {:?}", routes_path
+                "Failed to read *.osrm file {:?}", osrm_path
             ))?;
 
-        #[derive(Serialize)]
-        struct CsvRecord {
-            num_nodes: usize,
-            distance: f64,
-            distance_bee_line: f64,
+        let mut sampl = build_sampling(matches, bounds, parse_seed(matches))?;
+        let mut recorder = FrameRecorder::new();
+
+        let mut sweep_args = matches.values_of("sweep_max_dist").unwrap();
+        let from: f64 = matches.value_of("max_dist").unwrap().parse::<f64>()?;
+        let to: f64 = sweep_args.next().unwrap().parse::<f64>()
+            .context("--sweep-max-dist: METERS needs to be a number")?;
+        if !(to.is_finite() && to > 0.0) {
+            anyhow::bail!("--sweep-max-dist: METERS needs to be a finite positive number");
+        }
+        let steps: u32 = sweep_args.next().unwrap().parse::<u32>()
+            .context("--sweep-max-dist: STEPS needs to be a positive integer")?;
+        for step in 0..steps {
+            let max_dist = if steps > 1 {
+                from + (to - from) * (step as f64 / (steps - 1) as f64)
+            } else {
+                from
+            };
+            println!("Frame {}/{}: max distance {:.1} m", step + 1, steps, max_dist);
+            sampl.set_max_dist(max_dist);
+            net.reset_edge_counts();
+            for _ in 0..number_of_samples {
+                let route = sample_route(&mut *sampl, &mut machine)?;
+                net.bump_edges(&route.node_ids);
+            }
+            recorder.record(&net);
         }
 
-        let mut csv_writer = csv::Writer::from_path("distances.csv")?;
-        let net = Network::from_path(&reader.header().osrm_file)?;
+        let bounds = bounds.unwrap_or_else(|| net.get_bounds());
+        println!("Render {} frames", recorder.number_of_frames());
+        recorder.render(
+            &mut net,
+            bounds,
+            size,
+            matches.value_of("frames").map(Path::new),
+            matches.value_of("gif").map(Path::new),
+            delay,
+        )?;
+    } else if let Some(matches) = matches.subcommand_matches("diff") {
+        let before_path = Path::new(matches.value_of("before").unwrap());
+        let after_path = Path::new(matches.value_of("after").unwrap());
+        let number_property = matches.value_of("property").unwrap();
 
-        for (i, route) in reader.enumerate() {
-            let route = route?;
-            csv_writer.serialize(CsvRecord {
-                num_nodes: route.node_ids.len(),
-                distance: route.distance,
-                distance_bee_line: route.distance_bee_line(),
-            })?;
+        // Load the network if any of the inputs has to be replayed.
+        let mut net = {
+            let routes_paths: Vec<&Path> = [before_path, after_path].iter()
+                .copied()
+                .filter(|p| diff::is_routes_path(p))
+                .collect();
+            let mut osrm_files = vec![];
+            for path in &routes_paths {
+                let reader = route::RouteCollectionReader::new(path)
+                    .with_context(|| format!("Failed to read the routes file {:?}", path))?;
+                osrm_files.push(reader.header().osrm_file.clone());
+            }
+            if osrm_files.len() == 2 && !route_tools::same_file(&osrm_files[0], &osrm_files[1]) {
+                anyhow::bail!(
+                    "The routes files were sampled on different *.osrm files: {:?} and {:?}",
+                    osrm_files[0],
+                    osrm_files[1],
+                );
+            }
+            let osrm_path = matches.value_of("osrm")
+                .map(|p| p.to_string())
+                .or_else(|| osrm_files.first().cloned());
+            match osrm_path {
+                Some(osrm_path) if !routes_paths.is_empty() => {
+                    println!("Read *.osrm file {:?}", osrm_path);
+                    Some(Network::from_path(&osrm_path)
+                        .with_context(|| format!("Failed to read *.osrm file {:?}", osrm_path))?)
+                },
+                _ => None,
+            }
+        };
 
-            let dist = route.distance;
-            let dist_bl = route.distance_bee_line();
-            if dist_bl * 10.0 < dist && dist_bl > 105.0 {
-                route.write_to_geojson(format!("long_route_{}.geojson", i), &net)?;
+        let before = diff::EdgeCounts::from_path(before_path, number_property, net.as_mut())
+            .with_context(|| format!("Failed to read {:?}", before_path))?;
+        let after = diff::EdgeCounts::from_path(after_path, number_property, net.as_mut())
+            .with_context(|| format!("Failed to read {:?}", after_path))?;
+        let network_diff = diff::NetworkDiff::new(&before, &after);
+
+        if let Some(geojson_path) = matches.value_of("geojson") {
+            network_diff.write_to_file(geojson_path)?;
+        }
+
+        if let Some(png_path) = matches.value_of("png") {
+            let bounds = parse_bounds(matches)
+                .or_else(|| network_diff.get_bounds())
+                .ok_or_else(|| anyhow::anyhow!("Both inputs are empty, nothing to render"))?;
+            network_diff.write_png(png_path, bounds, 2048, 2048, matches.is_present("relative"))?;
+        }
+    } else if let Some(matches) = matches.subcommand_matches("heatmap") {
+        let bounds = parse_bounds(matches);
+        let cell_size = matches.value_of("cell_size").unwrap().parse::<f64>().unwrap();
+        let mut heatmap = heatmap::Heatmap::new(cell_size);
+
+        if let Some(csv_paths) = matches.values_of("density") {
+            for csv_path in csv_paths {
+                let density = density::DensityClusters::from_csv(csv_path, bounds)?;
+                heatmap.add_density(&density);
             }
         }
 
-        csv_writer.flush()?;
+        if let Some(routes_path) = matches.value_of("routes") {
+            let endpoints = match matches.value_of("endpoints").unwrap() {
+                "sources" => heatmap::Endpoints::Sources,
+                "destinations" => heatmap::Endpoints::Destinations,
+                _ => heatmap::Endpoints::Both,
+            };
+            heatmap.add_routes(routes_path, endpoints)?;
+        }
+        println!("Heatmap has {} non-empty cells", heatmap.number_of_cells());
+
+        let net = match matches.value_of("osrm") {
+            Some(osrm_path) => {
+                println!("Read *.osrm file {:?}", osrm_path);
+                Some(Network::from_path(osrm_path)
+                    .with_context(|| format!("Failed to read *.osrm file {:?}", osrm_path))?)
+            },
+            None => None,
+        };
+
+        if let Some(geojson_path) = matches.value_of("geojson") {
+            heatmap.write_to_geojson(geojson_path)?;
+        }
+
+        if let Some(png_path) = matches.value_of("png") {
+            let bounds = bounds
+                .or_else(|| net.as_ref().map(|net| net.get_bounds()))
+                .or_else(|| heatmap.get_bounds())
+                .ok_or_else(|| anyhow::anyhow!("Inputs are empty, nothing to render"))?;
+            heatmap.write_png(png_path, bounds, 2048, 2048, net.as_ref())?;
+        }
+    } else if let Some(matches) = matches.subcommand_matches("routes") {
+        if let Some(matches) = matches.subcommand_matches("info") {
+            let routes_path = matches.value_of("input").unwrap();
+            let reader = route::RouteCollectionReader::new(routes_path)
+                .with_context(|| format!(
+                    "Failed to read the routes file {:?}", routes_path
+                ))?;
+            print!("{}", reader.header());
+        } else if let Some(matches) = matches.subcommand_matches("index") {
+            let routes_path = matches.value_of("input").unwrap();
+            let mut reader = route::RouteCollectionReader::new(routes_path)
+                .with_context(|| format!(
+                    "Failed to read the routes file {:?}", routes_path
+                ))?;
+            let index = reader.index()?;
+            index.write_for(routes_path)?;
+            println!(
+                "Wrote index of {} routes to {:?}",
+                index.len(),
+                route_index::RouteIndex::path_for(routes_path),
+            );
+        } else if let Some(matches) = matches.subcommand_matches("show") {
+            let routes_path = matches.value_of("input").unwrap();
+            if let Some(n) = matches.value_of("random") {
+                let n = n.parse::<u64>().unwrap();
+                let routes = route::MappedRouteCollection::open(routes_path)
+                    .with_context(|| format!(
+                        "Failed to read the routes file {:?}", routes_path
+                    ))?;
+                let n = n.min(routes.len());
+                let mut indices = rand::seq::index::sample(
+                    &mut rand::thread_rng(),
+                    routes.len() as usize,
+                    n as usize,
+                ).into_vec();
+                indices.sort_unstable();
+                for i in indices {
+                    print_route(i as u64, &routes.get(i as u64)?);
+                }
+            } else {
+                let mut reader = route::RouteCollectionReader::new(routes_path)
+                    .with_context(|| format!(
+                        "Failed to read the routes file {:?}", routes_path
+                    ))?;
+                let first = matches.value_of("route").unwrap().parse::<u64>().unwrap();
+                if let Some(count) = matches.value_of("count") {
+                    let end = first + count.parse::<u64>().unwrap();
+                    for (i, route) in (first..end).zip(reader.range(first..end)?) {
+                        print_route(i, &route?);
+                    }
+                } else {
+                    print_route(first, &reader.get(first)?);
+                }
+            }
+        } else if let Some(matches) = matches.subcommand_matches("merge") {
+            let inputs: Vec<&str> = matches.values_of("input").unwrap().collect();
+            route_tools::merge(&inputs, matches.value_of("output").unwrap())?;
+        } else if let Some(matches) = matches.subcommand_matches("split") {
+            route_tools::split(
+                matches.value_of("input").unwrap(),
+                matches.value_of("parts").unwrap().parse::<u64>().unwrap(),
+                matches.value_of("output").unwrap(),
+            )?;
+        } else if let Some(matches) = matches.subcommand_matches("sample") {
+            route_tools::subsample(
+                matches.value_of("input").unwrap(),
+                matches.value_of("output").unwrap(),
+                matches.value_of("fraction").unwrap().parse::<f64>().unwrap(),
+                parse_seed(matches),
+            )?;
+        } else if let Some(matches) = matches.subcommand_matches("stats") {
+            let filter = matches.value_of("filter").map(route_filter::Filter::parse).transpose()?;
+            let bins = matches.value_of("bins").unwrap().parse::<usize>().unwrap().max(1);
+            let stats = route_stats::TripStats::from_routes(
+                matches.value_of("input").unwrap(),
+                filter.as_ref(),
+            )?;
+            stats.print_summary();
+            if let Some(path) = matches.value_of("quantiles") {
+                stats.write_quantiles(path)
+                    .with_context(|| format!("Failed to write {:?}", path))?;
+            }
+            if let Some(path) = matches.value_of("histograms") {
+                stats.write_histograms(path, bins)
+                    .with_context(|| format!("Failed to write {:?}", path))?;
+            }
+            if let Some(pattern) = matches.value_of("charts") {
+                stats.write_charts(pattern, bins)?;
+            }
+        } else if let Some(matches) = matches.subcommand_matches("select-link") {
+            let routes_path = matches.value_of("input").unwrap();
+            let osrm_path = routes_osrm_path(matches, routes_path)?;
+            println!("Read *.osrm file {:?}", osrm_path);
+            let mut net = Network::from_path(&osrm_path)
+                .with_context(|| format!("Failed to read *.osrm file {:?}", osrm_path))?;
+
+            let mut selectors = vec![];
+            if let Some(values) = matches.values_of("edge") {
+                let ids: Vec<i64> = values.map(|v| v.parse::<i64>().unwrap()).collect();
+                for pair in ids.chunks(2) {
+                    selectors.push(select_link::LinkSelector::Nodes(
+                        network::OsmNodeId::new(pair[0]),
+                        network::OsmNodeId::new(pair[1]),
+                    ));
+                }
+            }
+            if let Some(values) = matches.values_of("near") {
+                let coords: Vec<f64> = values.map(|v| v.parse::<f64>().unwrap()).collect();
+                for pair in coords.chunks(2) {
+                    let (lat, lon) = (pair[0], pair[1]);
+                    if !((-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)) {
+                        anyhow::bail!("--near: invalid coordinate {} {}", lat, lon);
+                    }
+                    selectors.push(select_link::LinkSelector::Near(Point4326::new(lat, lon)));
+                }
+            }
+
+            let mut select = select_link::SelectLink::new(&net, &selectors)?;
+            select.scan_routes(routes_path, &mut net)?;
+
+            let precision = matches.value_of("precision").unwrap().parse::<usize>().unwrap();
+            if let Some(path) = matches.value_of("geojson") {
+                net.write_to_file(path, precision, false)?;
+            }
+            if let Some(path) = matches.value_of("od_points") {
+                select.write_od_points(path, precision)?;
+            }
+            if let Some(path) = matches.value_of("links_output") {
+                select.write_links(path, precision)?;
+            }
+            if let Some(path) = matches.value_of("png") {
+                let bounds = select.bounds().unwrap_or_else(|| net.get_bounds());
+                select.render_image(&net, bounds, 2048, 2048).pixmap.save_png(path)
+                    .with_context(|| format!("Failed to write PNG file {:?}", path))?;
+            }
+        } else if let Some(matches) = matches.subcommand_matches("screenlines") {
+            let routes_path = matches.value_of("input").unwrap();
+            let filter = matches.value_of("filter").map(route_filter::Filter::parse).transpose()?;
+            let lines_path = matches.value_of("lines").unwrap();
+            let mut lines = screenline::Screenline::read_all(lines_path)
+                .with_context(|| format!("Failed to read screenlines from {:?}", lines_path))?;
+
+            let osrm_path = routes_osrm_path(matches, routes_path)?;
+            println!("Read *.osrm file {:?}", osrm_path);
+            let net = Network::from_path(&osrm_path)
+                .with_context(|| format!("Failed to read *.osrm file {:?}", osrm_path))?;
+
+            for line in &mut lines {
+                line.find_edges(&net);
+            }
+            screenline::count_routes(&mut lines, routes_path, &net, filter.as_ref())?;
+            screenline::print_counts(&lines);
+            if let Some(path) = matches.value_of("counts") {
+                screenline::write_counts(&lines, path)?;
+            }
+            if let Some(path) = matches.value_of("edges") {
+                screenline::write_edges(&lines, path)?;
+            }
+        } else if let Some(matches) = matches.subcommand_matches("turns") {
+            let routes_path = matches.value_of("input").unwrap();
+            let filter = matches.value_of("filter").map(route_filter::Filter::parse).transpose()?;
+            let osrm_path = routes_osrm_path(matches, routes_path)?;
+            println!("Read *.osrm file {:?}", osrm_path);
+            let net = Network::from_path(&osrm_path)
+                .with_context(|| format!("Failed to read *.osrm file {:?}", osrm_path))?;
+
+            let mut turns = turns::TurnCounts::for_network(&net);
+            turns.add_routes(routes_path, filter.as_ref())?;
+            let precision = matches.value_of("precision").unwrap().parse::<usize>().unwrap();
+            write_turns(matches, &turns, &net, precision)?;
+        } else if let Some(matches) = matches.subcommand_matches("export") {
+            let filter = matches.value_of("filter").map(route_filter::Filter::parse).transpose()?;
+            route_export::export(matches.value_of("input").unwrap(), &route_export::ExportOptions {
+                csv: matches.value_of("csv"),
+                geojson: matches.value_of("geojson"),
+                od_points: matches.value_of("od_points"),
+                filter,
+                osrm: matches.value_of("osrm"),
+                precision: matches.value_of("precision").unwrap().parse::<usize>().unwrap(),
+            })?;
+        }
+
     } else if let Some(matches) = matches.subcommand_matches("filter-poi") {
         let input = matches.value_of("input").unwrap();
         let output = matches.value_of("output").unwrap();
@@ -270,6 +1349,98 @@ fn run(matches: clap::ArgMatches) -> anyhow::Result<()> {
 }
 
 
+/// Command line arguments that select and configure the sampling strategy.
+fn sampling_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("bounds")
+            .long("bounds")
+            .value_name("sw.lat sw.lon ne.lat ne.lon")
+            .help("Sets the bounding box. Input values are the two coordinate pairs for the
+                       south-west and the north-east corner of the bounding box")
+            .takes_value(true)
+            .number_of_values(4)
+            .validator(is_number::<f64>),
+        Arg::with_name("max_dist")
+            .long("max-dist")
+            .value_name("METERS")
+            .help("Sets the maximum distance between source and destination points in
+                       meters. Required unless --log-normal or --trip-length-histogram is given.")
+            .takes_value(true)
+            .validator(is_positive_distance),
+        Arg::with_name("log_normal")
+            .long("log-normal")
+            .value_names(&["MEAN_METERS", "SIGMA"])
+            .help("Select destinations so that the distances follow a log-normal distribution
+                       with the given mean in meters and standard deviation of the logarithm.
+                       The maximum distance defaults to the 99.9% quantile.")
+            .takes_value(true)
+            .number_of_values(2)
+            .validator(is_number::<f64>),
+        Arg::with_name("trip_length_histogram")
+            .long("trip-length-histogram")
+            .value_name("FILE.csv")
+            .help("Select destinations so that the distances follow the shares of the distance
+                       bands in the given CSV file with the columns lower, upper (in meters) and
+                       share. The maximum distance defaults to the end of the last band.")
+            .takes_value(true)
+            .conflicts_with("log_normal"),
+        Arg::with_name("uniform2d")
+            .long("uniform2d")
+            .help("Sample the 2D plane uniformly.")
+            .requires("bounds"),
+        Arg::with_name("weighted")
+            .long("weighted")
+            .value_name("FILE.csv")
+            .help("sample from a list of weighted points from the given CSV file.")
+            .takes_value(true),
+        Arg::with_name("complex")
+            .long("complex")
+            .help("Use complex sampling that combines different density inputs.")
+            .takes_value(false)
+            .requires_all(&["population", "pois"]),
+        Arg::with_name("population")
+            .long("population")
+            .value_name("FILE.csv")
+            .help("Specify population density as weighted points from the given CSV file.")
+            .takes_value(true),
+        Arg::with_name("pois")
+            .long("pois")
+            .value_name("FILE.csv")
+            .help("Specify POI density as weighted points from the given CSV file.")
+            .takes_value(true),
+        Arg::with_name("seed")
+            .long("seed")
+            .value_name("INT")
+            .help("Sets the seed of the random number generator to reproduce a previous run. A \
+                   random seed is chosen by default. Resumed runs continue with the seed of the \
+                   original run.")
+            .takes_value(true)
+            .validator(is_number::<u64>),
+    ]
+}
+
+/// Command line arguments for the output of animation frames.
+fn animation_output_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("frames")
+            .long("frames")
+            .value_name("DIR")
+            .help("Sets the output directory to store each frame as a PNG file")
+            .takes_value(true),
+        Arg::with_name("gif")
+            .long("gif")
+            .value_name("FILE")
+            .help("Sets the output file to store the animated GIF")
+            .takes_value(true),
+    ]
+}
+
+fn sampling_group<'a>() -> ArgGroup<'a> {
+    ArgGroup::with_name("sampling")
+        .args(&["uniform2d", "weighted", "complex"])
+        .required(true)
+}
+
 fn is_number<T: std::str::FromStr>(s: String) -> Result<(), String> {
     match s.parse::<T>() {
         Ok(_) => Ok(()),
@@ -277,32 +1448,234 @@ fn is_number<T: std::str::FromStr>(s: String) -> Result<(), String> {
     }
 }
 
+fn is_positive<T: std::str::FromStr + PartialOrd + Default>(s: String) -> Result<(), String> {
+    match s.parse::<T>() {
+        Ok(n) if n > T::default() => Ok(()),
+        _ => Err("need a positive number".to_string()),
+    }
+}
+
+fn is_positive_distance(s: String) -> Result<(), String> {
+    match s.parse::<f64>() {
+        Ok(meters) if meters.is_finite() && meters > 0.0 => Ok(()),
+        _ => Err("need a finite positive number".to_string()),
+    }
+}
+
+
+fn parse_bounds(matches: &clap::ArgMatches) -> Option<BoundingBox> {
+    if matches.is_present("bounds") {
+        let aabb: Vec<_> = matches.values_of("bounds").unwrap()
+            .map(|s| s.parse::<f64>().unwrap()).collect();
+        assert_eq!(aabb.len(), 4);
+        Some(BoundingBox::new(
+            Point4326::new(aabb[0], aabb[1]),
+            Point4326::new(aabb[2], aabb[3]))
+        )
+    } else {
+        None
+    }
+}
+
+/// The *.osrm file of the "osrm" argument or the one that the routes were sampled on.
+fn routes_osrm_path(matches: &clap::ArgMatches, routes_path: &str) -> anyhow::Result<String> {
+    match matches.value_of("osrm") {
+        Some(path) => Ok(path.to_string()),
+        None => Ok(route::RouteCollectionReader::new(routes_path)
+            .with_context(|| format!("Failed to read the routes file {:?}", routes_path))?
+            .header().osrm_file.clone()),
+    }
+}
+
+fn print_route(index: u64, route: &route::Route) {
+    println!(
+        "{}: {} -> {}, {:.1} m, {} s, {} nodes",
+        index,
+        route.start_coord.as_point4326(),
+        route.end_coord.as_point4326(),
+        route.distance,
+        route.duration.map_or("-".to_string(), |d| format!("{:.1}", d)),
+        route.node_ids.len(),
+    );
+}
+
+fn parse_seed(matches: &clap::ArgMatches) -> u64 {
+    matches.value_of("seed")
+        .map(|s| s.parse::<u64>().unwrap())
+        .unwrap_or_else(rand::random)
+}
+
+/// Describe the sampling configuration for the header of a *.routes file.
+fn run_metadata(
+    matches: &clap::ArgMatches,
+    bounds: Option<BoundingBox>,
+    seed: u64,
+    machine: &RoutingMachine,
+) -> anyhow::Result<route::RunMetadata>
+{
+    let mut sampler = ["uniform2d", "weighted", "complex"].iter()
+        .find(|name| matches.is_present(name))
+        .map(|name| name.to_string())
+        .unwrap_or_default();
+    let trip_length = parse_trip_length(matches)?;
+    if let Some(trip_length) = &trip_length {
+        sampler = format!("{}, {}", sampler, trip_length);
+    }
+
+    let mut input_files = vec![];
+    for role in &["weighted", "population", "pois", "trip_length_histogram"] {
+        if let Some(path) = matches.value_of(role) {
+            input_files.push(route::InputFile::new(*role, path)
+                .with_context(|| format!("Failed to read {:?} for its checksum", path))?);
+        }
+    }
+
+    Ok(route::RunMetadata {
+        nori_version: env!("CARGO_PKG_VERSION").to_string(),
+        created: chrono::Utc::now().to_rfc3339(),
+        sampler,
+        bounds: bounds.map(|b| [b.sw.lat(), b.sw.lon(), b.ne.lat(), b.ne.lon()]),
+        max_dist: parse_max_dist(matches, trip_length.as_ref()),
+        input_files,
+        seed,
+        osrm_url: machine.url().to_string(),
+        osrm_profile: machine.profile().to_string(),
+        operation: String::new(),
+        parts: vec![],
+    })
+}
+
+/// Create the sampling strategy that is selected by the arguments from `sampling_args`.
+fn build_sampling(matches: &clap::ArgMatches, bounds: Option<BoundingBox>, seed: u64)
+    -> anyhow::Result<Box<dyn Sampling>>
+{
+    let trip_length = parse_trip_length(matches)?;
+    let max_dist: f64 = parse_max_dist(matches, trip_length.as_ref())
+        .ok_or_else(|| anyhow::anyhow!("The maximum distance is missing (--max-dist)"))?;
+
+    let mut sampl: Box<dyn Sampling> = if matches.is_present("uniform2d") {
+        Box::new(sampling::Uniform2D::new(bounds.unwrap(), max_dist, seed))
+    } else if matches.is_present("weighted") {
+        let csv_path = matches.value_of("weighted").unwrap();
+        Box::new(sampling::Weighted::from_csv(csv_path, bounds, max_dist, seed)?)
+    } else if matches.is_present("complex") {
+        let population_csv = matches.value_of("population").unwrap();
+        let poi_csv = matches.value_of("pois").unwrap();
+        Box::new(sampling::Complex::from_csv(population_csv, poi_csv, bounds, max_dist, seed)?)
+    } else {
+        anyhow::bail!("No sampling strategy selected")
+    };
+    if let Some(trip_length) = trip_length {
+        if trip_length.cdf(max_dist) <= 0.0 {
+            anyhow::bail!(
+                "No trips of the {} are within the maximum distance of {} m", trip_length, max_dist
+            );
+        }
+        sampl.set_trip_length(trip_length);
+    }
+    Ok(sampl)
+}
+
+/// The trip length distribution that is selected by the arguments from `sampling_args`.
+fn parse_trip_length(matches: &clap::ArgMatches) -> anyhow::Result<Option<TripLength>> {
+    if let Some(mut values) = matches.values_of("log_normal") {
+        let mean = values.next().unwrap().parse::<f64>()?;
+        let sigma = values.next().unwrap().parse::<f64>()?;
+        Ok(Some(TripLength::log_normal(mean, sigma).context("--log-normal")?))
+    } else if let Some(path) = matches.value_of("trip_length_histogram") {
+        Ok(Some(TripLength::from_csv(path)
+            .with_context(|| format!("Failed to read the trip length histogram {:?}", path))?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The "max_dist" argument or the default of the trip length distribution.
+fn parse_max_dist(matches: &clap::ArgMatches, trip_length: Option<&TripLength>) -> Option<f64> {
+    matches.value_of("max_dist")
+        .map(|s| s.parse::<f64>().unwrap())
+        .or_else(|| trip_length.map(|t| t.default_max_dist()))
+}
+
+/// Generate a pair of points and find the shortest path between them.
+fn sample_route<S: Sampling + ?Sized>(sampl: &mut S, machine: &mut RoutingMachine)
+    -> anyhow::Result<route::Route>
+{
+    loop {
+        let source = sampl.gen_source();
+        if let Some(destination) = sampl.gen_destination(source) {
+            return machine.find_route(source, destination);
+        }
+    }
+}
+
+/// State besides the traffic counts of the network that is updated with each sampled route.
+struct SampleTracking {
+    turns: Option<turns::TurnCounts>,
+    calibration: Option<trip_length::Calibration>,
+    /// Frames of the animation and the number of samples between frames
+    frames: Option<(FrameRecorder, u32)>,
+}
 
-fn sample<S: Sampling>(
+fn sample<S: Sampling + ?Sized>(
     sampl: &mut S,
     number_of_samples: u32,
     machine: &mut RoutingMachine,
     writer: &mut RouteCollectionWriter<File>,
     net: &mut Network,
+    tracking: &mut SampleTracking,
 ) -> anyhow::Result<()>
 {
     for i in 0..number_of_samples {
-        let a;
-        let b;
-        loop {
-            let source = sampl.gen_source();
-            if let Some(destination) = sampl.gen_destination(source) {
-                a = source;
-                b = destination;
-                break;
-            }
+        if interrupt::is_interrupted() {
+            println!("Interrupted after {} of {} samples", i, number_of_samples);
+            break;
         }
-
-        println!("{:.2}%, {}: {} {}", (100.0 * (i + 1) as f64) / (number_of_samples as f64), i + 1, a, b);
-        let res = machine.find_route(a, b)?;
+        let res = sample_route(sampl, machine)?;
+        println!(
+            "{:.2}%, {}: {} {}",
+            (100.0 * (i + 1) as f64) / (number_of_samples as f64),
+            i + 1,
+            res.start_coord.as_point4326(),
+            res.end_coord.as_point4326(),
+        );
         let res = writer.write_route(res)?;
         net.bump_edges(&res.node_ids);
+        if let Some(turns) = &mut tracking.turns {
+            turns.add_route(&res.node_ids);
+        }
+        if let Some(calibration) = &mut tracking.calibration {
+            if let Some(adjusted) = calibration.add_route(&res) {
+                println!(
+                    "Adjusted the trip length distribution (largest difference of shares: {:.3})",
+                    calibration.last_difference,
+                );
+                sampl.set_trip_length(adjusted);
+            }
+        }
+        if let Some((recorder, every)) = &mut tracking.frames {
+            if (i + 1) % *every == 0 {
+                recorder.record(net);
+            }
+        }
     }
     Ok(())
 }
 
+/// Write the outputs of the "turns_csv" and "turns_geojson" arguments.
+fn write_turns(
+    matches: &clap::ArgMatches,
+    turns: &turns::TurnCounts,
+    net: &Network,
+    precision: usize,
+) -> anyhow::Result<()>
+{
+    if let Some(path) = matches.value_of("turns_csv") {
+        turns.write_csv(path, net)?;
+    }
+    if let Some(path) = matches.value_of("turns_geojson") {
+        turns.write_to_file(path, net, precision)
+            .with_context(|| format!("Failed to write {:?}", path))?;
+    }
+    Ok(())
+}
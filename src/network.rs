@@ -6,8 +6,10 @@ use osrmreader::{Entry, OsrmReader};
 use serde::{Serialize, Deserialize};
 
 use crate::bounding_box::BoundingBox;
-use crate::geojson_writer::GeoJsonWriter;
+use crate::geometry::Geometry;
+use crate::output::{self, Feature};
 use crate::polyline::PolylineCollection;
+use crate::render::{self, ColorScale, Projection};
 
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
@@ -19,6 +21,16 @@ pub struct NodeId(u32);
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct EdgeId(u32);
 
+impl OsmNodeId {
+    pub fn new(id: i64) -> Self {
+        OsmNodeId(id)
+    }
+
+    pub fn as_i64(self) -> i64 {
+        self.0
+    }
+}
+
 /// An undefined OSM edge.
 /// TODO Maybe use NonZeroI64 for OsmNodeId?
 pub const UNDEF_OSM_EDGE: (OsmNodeId, OsmNodeId) = (OsmNodeId(0), OsmNodeId(0));
@@ -45,6 +57,15 @@ pub struct Network {
     osm_2_node_id: HashMap<OsmNodeId, NodeId>,
 }
 
+#[derive(Serialize)]
+struct CsvRecord {
+    osm_source: i64,
+    osm_target: i64,
+    number: usize,
+    length: f64,
+    wkt: String,
+}
+
 pub struct FullEdge {
     /// first point
     pub a: Node,
@@ -68,6 +89,13 @@ impl FullEdge {
     pub fn osm_ids(&self) -> (OsmNodeId, OsmNodeId) {
         (self.a.osm_node_id, self.b.osm_node_id)
     }
+
+    /// Length in meters
+    pub fn length(&self) -> f64 {
+        let a = self.a.as_point3035();
+        let b = self.b.as_point3035();
+        (a.coords.0 - b.coords.0).hypot(a.coords.1 - b.coords.1)
+    }
 }
 
 impl Network {
@@ -77,6 +105,19 @@ impl Network {
             .and_then(|id| self.nodes_vec.get(id.0 as usize).copied())
     }
 
+    /// Return the number of routes that passed the edge between the given nodes in either
+    /// direction or None if there is no such edge.
+    pub fn edge_number(&self, a: OsmNodeId, b: OsmNodeId) -> Option<usize> {
+        let a_id = *self.osm_2_node_id.get(&a)?;
+        let b_id = *self.osm_2_node_id.get(&b)?;
+        let forward = self.edges_map.get(&(a_id, b_id)).map(|e| self.edges_vec[e.0 as usize].number);
+        let backward = self.edges_map.get(&(b_id, a_id)).map(|e| self.edges_vec[e.0 as usize].number);
+        match (forward, backward) {
+            (None, None) => None,
+            (f, b) => Some(f.unwrap_or(0) + b.unwrap_or(0)),
+        }
+    }
+
     pub fn bump_edges(&mut self, nodes: &[OsmNodeId]) {
         for win in nodes.windows(2) {
             let a_id = self.osm_2_node_id.get(&win[0]);
@@ -96,17 +137,31 @@ impl Network {
         }
     }
 
-    pub fn write_to_geojson<P: AsRef<Path>>(&self, output_path: P) -> anyhow::Result<()> {
-        let mut writer = GeoJsonWriter::from_path(output_path)?;
+    /// Write all edges with traffic as LineStrings to a GeoJSON, FlatGeobuf or Shapefile (see
+    /// `output::create`). `precision` is the number of decimal places of coordinates. If
+    /// `edge_attributes` is true, the OSM node ids and the length of each edge are added.
+    pub fn write_to_file<P: AsRef<Path>>(&self, output_path: P, precision: usize, edge_attributes: bool)
+        -> anyhow::Result<()>
+    {
+        let mut writer = output::create(output_path)?;
+        writer.set_precision(precision);
+        writer.set_write_bbox(true);
 
         for edge in self.edges() {
             if edge.number < 1 {
                 continue;
             }
 
-            let mut ls = writer.add_line_string(&[edge.a.as_point4326(), edge.b.as_point4326()])?;
-            ls.add_property("number", edge.number)?;
-            ls.finish()?;
+            let geometry = Geometry::LineString(vec![edge.a.as_point4326(), edge.b.as_point4326()]);
+            let mut feature = Feature::new(geometry).with_property("number", edge.number);
+            if edge_attributes {
+                let (source, target) = edge.osm_ids();
+                feature = feature
+                    .with_property("osm_source", source.as_i64())
+                    .with_property("osm_target", target.as_i64())
+                    .with_property("length", edge.length());
+            }
+            writer.write_feature(&feature)?;
         }
 
         writer.finish()?;
@@ -114,6 +169,33 @@ impl Network {
         Ok(())
     }
 
+    /// Write all edges with traffic to a CSV file with the OSM node ids of both ends, the number
+    /// of routes, the length in meters and the geometry as WKT. The node ids allow joining the
+    /// results with other datasets without a spatial match.
+    pub fn write_to_csv<P: AsRef<Path>>(&self, output_path: P, precision: usize) -> anyhow::Result<()> {
+        let mut writer = csv::Writer::from_path(output_path)?;
+
+        for edge in self.edges() {
+            if edge.number < 1 {
+                continue;
+            }
+
+            let (source, target) = edge.osm_ids();
+            let geometry = Geometry::LineString(vec![edge.a.as_point4326(), edge.b.as_point4326()]);
+            writer.serialize(CsvRecord {
+                osm_source: source.as_i64(),
+                osm_target: target.as_i64(),
+                number: edge.number,
+                length: edge.length(),
+                wkt: geometry.to_wkt(precision),
+            })?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
     pub fn edges(&self) -> impl Iterator<Item=FullEdge> + '_ {
         self.edges_vec.iter().map(move |edge| {
             let source = self.nodes_vec[edge.source_node_id.0 as usize];
@@ -180,6 +262,36 @@ impl Network {
         })
     }
 
+    /// Create a network from OSM node ids with coordinates (id, lat, lon) and directed edges
+    /// between these nodes.
+    #[cfg(test)]
+    pub fn from_nodes(nodes: &[(i64, f64, f64)], edges: &[(i64, i64)]) -> Network {
+        let nodes_vec: Vec<Node> = nodes.iter()
+            .map(|&(id, lat, lon)| Node {
+                osm_node_id: OsmNodeId(id),
+                raw_lat: (lat * 1e6).round() as i32,
+                raw_lon: (lon * 1e6).round() as i32,
+            })
+            .collect();
+        let osm_2_node_id: HashMap<OsmNodeId, NodeId> = nodes_vec.iter()
+            .enumerate()
+            .map(|(i, n)| (n.osm_node_id, NodeId(i as u32)))
+            .collect();
+        let mut edges_vec = vec![];
+        let mut edges_map = HashMap::new();
+        for &(a, b) in edges {
+            let (a, b) = (osm_2_node_id[&OsmNodeId(a)], osm_2_node_id[&OsmNodeId(b)]);
+            edges_map.insert((a, b), EdgeId(edges_vec.len() as u32));
+            edges_vec.push(Edge { source_node_id: a, target_node_id: b, number: 0 });
+        }
+        Network {
+            nodes_vec,
+            edges_vec,
+            edges_map,
+            osm_2_node_id,
+        }
+    }
+
     pub fn get_bounds(&self) -> BoundingBox {
         let mut edges_iter = self.edges();
 
@@ -232,91 +344,98 @@ impl Network {
         }
     }
 
+    /// Return the edge that is closest to the given point or None if the network has no edges or
+    /// the coordinates are not finite.
+    pub fn nearest_edge(&self, point: Point4326) -> Option<FullEdge> {
+        if !(point.lat().is_finite() && point.lon().is_finite()) {
+            return None;
+        }
+        let p = laea::forward(point);
+        let distance_2 = |edge: &FullEdge| {
+            let (a, b) = (edge.a.as_point3035(), edge.b.as_point3035());
+            let (dx, dy) = (b.coords.0 - a.coords.0, b.coords.1 - a.coords.1);
+            let len_2 = dx * dx + dy * dy;
+            let t = if len_2 > 0.0 {
+                (((p.coords.0 - a.coords.0) * dx + (p.coords.1 - a.coords.1) * dy) / len_2)
+                    .clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let (x, y) = (a.coords.0 + t * dx - p.coords.0, a.coords.1 + t * dy - p.coords.1);
+            x * x + y * y
+        };
+        self.edges()
+            .map(|edge| (distance_2(&edge), edge))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, edge)| edge)
+    }
+
+    /// Return the current traffic count of each edge.
+    pub fn edge_counts(&self) -> Vec<usize> {
+        self.edges_vec.iter().map(|e| e.number).collect()
+    }
+
+    /// Overwrite the traffic counts of all edges, e.g. with a snapshot from `edge_counts`.
+    pub fn set_edge_counts(&mut self, counts: &[usize]) {
+        assert_eq!(counts.len(), self.edges_vec.len());
+        for (edge, &number) in self.edges_vec.iter_mut().zip(counts) {
+            edge.number = number;
+        }
+    }
+
+    /// Set the traffic counts of all edges to zero.
+    pub fn reset_edge_counts(&mut self) {
+        for edge in &mut self.edges_vec {
+            edge.number = 0;
+        }
+    }
+
+    /// Return the highest traffic count of all edges.
+    pub fn max_edge_count(&self) -> usize {
+        self.edges_vec.iter().map(|e| e.number).max().unwrap_or(0)
+    }
+
     /// Render the network as an image.
     pub fn render_image(&self, bounds: BoundingBox, width: u32, height: u32)
         -> tiny_skia::Canvas
     {
-        let mut canvas = tiny_skia::Canvas::new(width, height).unwrap();
-        canvas.pixmap.fill(tiny_skia::Color::WHITE);
+        self.render_image_with_max(bounds, width, height, self.max_edge_count())
+    }
+
+    /// Render the network as an image. Colors are normalized with the given maximum count, so
+    /// that several images can share the same color scale.
+    pub fn render_image_with_max(&self, bounds: BoundingBox, width: u32, height: u32, max_number: usize)
+        -> tiny_skia::Canvas
+    {
+        let mut canvas = render::new_canvas(width, height);
 
         if self.edges_map.is_empty() {
             return canvas;
         }
 
-        let bounds_3035 = bounds.get_3035_bounds();
-
-        let bounds_width = bounds_3035.ne.coords.0 - bounds_3035.sw.coords.0;
-        let bounds_height = bounds_3035.ne.coords.1 - bounds_3035.sw.coords.1;
-
-        let canvas_ratio = width as f64 / height as f64;
-        let bounds_ratio = bounds_width / bounds_height;
-
-
-        let (scale, offset_x, offset_y) = if bounds_ratio > canvas_ratio {
-            let scale = width as f64 / bounds_width;
-            (
-                scale,
-                0.0,
-                (height as f64 - bounds_height * scale) * 0.5,
-            )
-        } else {
-            let scale = height as f64 / bounds_height;
-            (
-                scale,
-                (width as f64 - bounds_width * scale) * 0.5,
-                0.0,
-            )
-        };
+        let projection = Projection::new(bounds, width, height);
+        let color_scale = ColorScale::yl_gn();
 
-        let max_number = self.edges().max_by_key(|e| e.number).unwrap().number;
-        let s2l = |r: u8, g: u8, b: u8| -> palette::LinSrgb {
-            palette::Srgb::new(
-                r as f32 / 255.0,
-                g as f32 / 255.0,
-                b as f32 / 255.0,
-            ).into_linear()
+        let mut paint = tiny_skia::Paint {
+            anti_alias: true,
+            ..Default::default()
+        };
+        let stroke = tiny_skia::Stroke {
+            width: 4.0,
+            line_cap: tiny_skia::LineCap::Round,
+            ..Default::default()
         };
-
-        // ColorBrewer YlGn color scale
-        let colors: Vec<palette::LinSrgb> = vec![
-            s2l(247,252,185),
-            s2l(217,240,163),
-            s2l(173,221,142),
-            s2l(120,198,121),
-            s2l(65,171,93),
-            s2l(35,132,67),
-            s2l(0,104,55),
-            s2l(0,69,41),
-        ];
-        let gradient = palette::Gradient::new(colors);
-        let mut paint = tiny_skia::Paint::default();
-        paint.anti_alias = true;
-        let mut stroke = tiny_skia::Stroke::default();
-        stroke.width = 4.0;
-        stroke.line_cap = tiny_skia::LineCap::Round;
 
         let mut edges: Vec<_> = self.edges().filter(|e| e.number > 0).collect();
         edges.sort_by_key(|e| e.number);
 
         for edge in edges {
-            let a: Point3035 = edge.a.as_point3035();
-            let b: Point3035 = edge.b.as_point3035();
-            let path = {
-                let mut pb = tiny_skia::PathBuilder::new();
-                pb.move_to(
-                    (offset_x + (a.coords.0 - bounds_3035.sw.coords.0) * scale) as f32,
-                    (offset_y + (bounds_3035.ne.coords.1 - a.coords.1) * scale) as f32,
-                );
-                pb.line_to(
-                    (offset_x + (b.coords.0 - bounds_3035.sw.coords.0) * scale) as f32,
-                    (offset_y + (bounds_3035.ne.coords.1 - b.coords.1) * scale) as f32,
-                );
-                pb.finish().unwrap()
+            let path = match projection.line_path(edge.a.as_point3035(), edge.b.as_point3035()) {
+                Some(path) => path,
+                None => continue,
             };
 
-            let c = palette::Srgb::from_linear(gradient.get(edge.number as f32 / max_number as f32));
-
-            paint.set_color(tiny_skia::Color::from_rgba(c.red, c.green, c.blue, 1.0).unwrap());
+            paint.set_color(color_scale.color(edge.number as f32 / max_number as f32));
             canvas.stroke_path(&path, &paint, &stroke);
         }
         canvas
@@ -335,3 +454,26 @@ impl Network {
         PolylineCollection::new(self)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_edge() {
+        // An L-shaped network: 1 -> 2 to the east, 2 -> 3 to the north
+        let net = Network::from_nodes(
+            &[(1, 52.5, 13.4), (2, 52.5, 13.5), (3, 52.6, 13.5)],
+            &[(1, 2), (2, 3)],
+        );
+        let nearest = |lat, lon| net.nearest_edge(Point4326::new(lat, lon)).map(|e| e.osm_ids());
+        let id = OsmNodeId::new;
+        assert_eq!(nearest(52.49, 13.45), Some((id(1), id(2))));
+        assert_eq!(nearest(52.55, 13.51), Some((id(2), id(3))));
+        // Beyond the end of an edge, the distance to its end point counts.
+        assert_eq!(nearest(52.7, 13.5), Some((id(2), id(3))));
+        assert_eq!(nearest(f64::NAN, 13.4), None);
+        assert!(Network::from_nodes(&[], &[]).nearest_edge(Point4326::new(52.5, 13.4)).is_none());
+    }
+}
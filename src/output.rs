@@ -0,0 +1,75 @@
+//! Write features to vector file formats that are selected by file extension.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::flatgeobuf_writer::FlatGeobufWriter;
+use crate::geojson_writer::GeoJsonWriter;
+use crate::geometry::Geometry;
+use crate::shapefile_writer::ShapefileWriter;
+
+
+/// A geometry with properties that can be written to any output format.
+#[derive(Clone, Debug)]
+pub struct Feature {
+    pub geometry: Geometry,
+    /// Properties in the order they should appear in the output.
+    pub properties: Vec<(String, Value)>,
+    pub id: Option<Value>,
+}
+
+impl Feature {
+    pub fn new(geometry: Geometry) -> Self {
+        Feature {
+            geometry,
+            properties: vec![],
+            id: None,
+        }
+    }
+
+    /// Add a property. Non-finite numbers are turned into `null`.
+    pub fn with_property<V: Into<Value>>(mut self, key: &str, value: V) -> Self {
+        self.properties.push((key.to_string(), value.into()));
+        self
+    }
+
+    pub fn with_id<V: Into<Value>>(mut self, id: V) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// All properties including the id, for formats that have no separate feature ids. The id is
+    /// added as an `id` property unless there already is one.
+    pub fn properties_with_id(&self) -> impl Iterator<Item=(&str, &Value)> {
+        let id = self.id.as_ref()
+            .filter(|_| !self.properties.iter().any(|(key, _)| key == "id"))
+            .map(|id| ("id", id));
+        self.properties.iter().map(|(key, value)| (key.as_str(), value)).chain(id)
+    }
+}
+
+/// Something that features can be written to.
+pub trait FeatureSink {
+    fn write_feature(&mut self, feature: &Feature) -> anyhow::Result<()>;
+
+    /// Set the number of decimal places of coordinates. Only affects text formats.
+    fn set_precision(&mut self, _precision: usize) {}
+
+    /// Enable or disable writing the bounds of all features, if the format makes this optional.
+    fn set_write_bbox(&mut self, _write_bbox: bool) {}
+
+    /// Write all remaining data. No features can be written afterwards.
+    fn finish(&mut self) -> anyhow::Result<()>;
+}
+
+/// Create a feature sink for the given path. `*.fgb` files are written as FlatGeobuf, `*.shp`
+/// files as ESRI Shapefiles, everything else as GeoJSON (see `GeoJsonFormat::from_path`).
+pub fn create<P: AsRef<Path>>(path: P) -> anyhow::Result<Box<dyn FeatureSink>> {
+    let ext = path.as_ref().extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+    Ok(match ext.as_deref() {
+        Some("fgb") => Box::new(FlatGeobufWriter::from_path(path)?),
+        Some("shp") => Box::new(ShapefileWriter::from_path(path)?),
+        _ => Box::new(GeoJsonWriter::from_path(path)?),
+    })
+}
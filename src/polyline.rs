@@ -1,10 +1,12 @@
 use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
-use geomatic::Point4326;
+use geomatic::{laea, Point3035, Point4326};
 
 use crate::geojson_writer::GeoJsonWriter;
+use crate::geometry::Geometry;
 use crate::network::{Network, OsmNodeId};
+use crate::output::{self, Feature};
 
 
 pub struct PolyPoint {
@@ -20,6 +22,8 @@ pub struct PolylineCollection {
     pub polylines: Vec<Polyline>,
     /// Which polyline does the given edge belong to? Returns index into Vec.
     membership: HashMap<(OsmNodeId, OsmNodeId), u32>,
+    /// Nodes with more than two neighbors
+    intersections: HashSet<OsmNodeId>,
 }
 
 impl PolylineCollection {
@@ -28,6 +32,7 @@ impl PolylineCollection {
         let mut poly_collection = PolylineCollection {
             polylines: vec![],
             membership: HashMap::new(),
+            intersections: HashSet::new(),
         };
 
         // Build adjacency graph
@@ -43,6 +48,10 @@ impl PolylineCollection {
             }
             adja
         };
+        poly_collection.intersections = adja.iter()
+            .filter(|(_, (_, v))| v.len() > 2)
+            .map(|(id, _)| *id)
+            .collect();
 
         // Edges that have been processed
         let mut seen: HashSet<(OsmNodeId, OsmNodeId)> = HashSet::new();
@@ -130,12 +139,18 @@ impl PolylineCollection {
             .cloned()
     }
 
+    /// Return all nodes with more than two neighbors.
+    pub fn intersections(&self) -> &HashSet<OsmNodeId> {
+        &self.intersections
+    }
+
     pub fn write_to_geojson<P: AsRef<Path>>(&self, output_path: P) -> anyhow::Result<()> {
         let mut writer = GeoJsonWriter::from_path(output_path)?;
 
         for (i, poly) in self.polylines.iter().enumerate() {
             let coords: Vec<_> = poly.points.iter().map(|p| p.point).collect();
             let mut ls = writer.add_line_string(&coords)?;
+            ls.set_id(i)?;
             ls.add_property("id", i)?;
             ls.finish()?;
         }
@@ -144,4 +159,192 @@ impl PolylineCollection {
 
         Ok(())
     }
+
+    /// Write each polyline that carries traffic as a single LineString with statistics of the
+    /// traffic counts of its edges. This is a lot more compact than writing each edge separately
+    /// (see `Network::write_to_file`). Counts of both directions of an edge are added up.
+    /// If `edge_attributes` is true, the OSM node ids of both ends are added.
+    pub fn write_to_file_with_counts<P: AsRef<Path>>(
+        &self,
+        output_path: P,
+        net: &Network,
+        precision: usize,
+        edge_attributes: bool,
+    ) -> anyhow::Result<()>
+    {
+        let mut writer = output::create(output_path)?;
+        writer.set_precision(precision);
+        writer.set_write_bbox(true);
+
+        for (i, poly) in self.polylines.iter().enumerate() {
+            let stats = match poly.count_stats(net) {
+                Some(stats) if stats.max > 0 => stats,
+                _ => continue,
+            };
+
+            let coords: Vec<_> = poly.points.iter().map(|p| p.point).collect();
+            let mut feature = Feature::new(Geometry::LineString(coords))
+                .with_id(i)
+                .with_property("number_min", stats.min)
+                .with_property("number_max", stats.max)
+                .with_property("number_mean", stats.mean)
+                .with_property("number_weighted_mean", stats.length_weighted_mean)
+                .with_property("length", stats.length)
+                .with_property("number_of_edges", stats.number_of_edges);
+            if let (true, Some(first), Some(last)) = (edge_attributes, poly.points.first(), poly.points.last()) {
+                feature = feature
+                    .with_property("osm_source", first.id.as_i64())
+                    .with_property("osm_target", last.id.as_i64());
+            }
+            writer.write_feature(&feature)?;
+        }
+
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+/// Statistics of the traffic counts of all edges of a polyline.
+pub struct CountStats {
+    pub min: usize,
+    pub max: usize,
+    /// Mean of all edges
+    pub mean: f64,
+    /// Mean of all edges weighted by their length
+    pub length_weighted_mean: f64,
+    /// Length of the polyline in meters
+    pub length: f64,
+    pub number_of_edges: usize,
+}
+
+impl Polyline {
+    /// Compute statistics of the traffic counts of the edges of this polyline. Returns None if
+    /// the polyline has no edges.
+    pub fn count_stats(&self, net: &Network) -> Option<CountStats> {
+        let mut min = usize::MAX;
+        let mut max = 0;
+        let mut sum = 0.0;
+        let mut weighted_sum = 0.0;
+        let mut length = 0.0;
+        let mut number_of_edges = 0;
+
+        for win in self.points.windows(2) {
+            let number = net.edge_number(win[0].id, win[1].id).unwrap_or(0);
+            let a: Point3035 = laea::forward(win[0].point);
+            let b: Point3035 = laea::forward(win[1].point);
+            let edge_length = (a.coords.0 - b.coords.0).hypot(a.coords.1 - b.coords.1);
+
+            min = min.min(number);
+            max = max.max(number);
+            sum += number as f64;
+            weighted_sum += number as f64 * edge_length;
+            length += edge_length;
+            number_of_edges += 1;
+        }
+
+        if number_of_edges == 0 {
+            return None;
+        }
+
+        Some(CountStats {
+            min,
+            max,
+            mean: sum / number_of_edges as f64,
+            length_weighted_mean: if length > 0.0 { weighted_sum / length } else { sum / number_of_edges as f64 },
+            length,
+            number_of_edges,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geojson::GeoJson;
+    use crate::test_util::TempFile;
+
+    /// A line from node 1 to the intersection 4 with edges of 100, 200 and 100 m, and two
+    /// branches from 4 to 5 and 6.
+    fn test_network() -> Network {
+        let node = |id: i64, x: f64, y: f64| {
+            let p: Point4326 = laea::backward(Point3035::new(4_550_000.0 + x, 3_270_000.0 + y));
+            (id, p.lat(), p.lon())
+        };
+        let mut net = Network::from_nodes(
+            &[
+                node(1, 0.0, 0.0),
+                node(2, 100.0, 0.0),
+                node(3, 300.0, 0.0),
+                node(4, 400.0, 0.0),
+                node(5, 500.0, 0.0),
+                node(6, 400.0, 100.0),
+            ],
+            &[(1, 2), (2, 3), (3, 4), (4, 5), (4, 6)],
+        );
+        let id = OsmNodeId::new;
+        net.bump_edges(&[id(1), id(2), id(3), id(4), id(5)]);
+        net.bump_edges(&[id(5), id(4), id(3), id(2), id(1)]);
+        net.bump_edges(&[id(2), id(3)]);
+        net
+    }
+
+    #[test]
+    fn test_count_stats() {
+        let id = OsmNodeId::new;
+        let net = test_network();
+        let polylines = PolylineCollection::new(&net);
+        assert_eq!(polylines.polylines.len(), 3);
+
+        let poly = &polylines.polylines[polylines.lookup_edge((id(2), id(1))).unwrap() as usize];
+        assert_eq!(poly.points.len(), 4);
+        let stats = poly.count_stats(&net).unwrap();
+        assert_eq!((stats.min, stats.max, stats.number_of_edges), (2, 3, 3));
+        assert!((stats.mean - 7.0 / 3.0).abs() < 1e-9);
+        assert!((stats.length - 400.0).abs() < 0.5, "{}", stats.length);
+        // (2 * 100 + 3 * 200 + 2 * 100) / 400
+        assert!((stats.length_weighted_mean - 2.5).abs() < 1e-3);
+
+        let branch = &polylines.polylines[polylines.lookup_edge((id(4), id(6))).unwrap() as usize];
+        let stats = branch.count_stats(&net).unwrap();
+        assert_eq!((stats.min, stats.max, stats.number_of_edges), (0, 0, 1));
+
+        let single = Polyline { points: vec![PolyPoint { id: id(1), point: poly.points[0].point }] };
+        assert!(single.count_stats(&net).is_none());
+    }
+
+    #[test]
+    fn test_write_to_file_with_counts() {
+        let net = test_network();
+        let path = TempFile::new("polyline_counts.geojson");
+        PolylineCollection::new(&net).write_to_file_with_counts(&path, &net, 6, true).unwrap();
+
+        let fc = match std::fs::read_to_string(&path).unwrap().parse::<GeoJson>().unwrap() {
+            GeoJson::FeatureCollection(fc) => fc,
+            _ => panic!("not a FeatureCollection"),
+        };
+        // The branch without traffic is left out.
+        assert_eq!(fc.features.len(), 2);
+        let mut features: Vec<_> = fc.features.iter()
+            .map(|f| {
+                let props = f.properties.as_ref().unwrap();
+                let line_len = match &f.geometry.as_ref().unwrap().value {
+                    geojson::Value::LineString(line) => line.len(),
+                    _ => panic!("not a LineString"),
+                };
+                let mut ends = vec![props["osm_source"].as_i64().unwrap(), props["osm_target"].as_i64().unwrap()];
+                ends.sort();
+                (
+                    ends,
+                    line_len,
+                    props["number_min"].as_u64().unwrap(),
+                    props["number_max"].as_u64().unwrap(),
+                    props["number_of_edges"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        features.sort();
+        assert_eq!(features, vec![(vec![1, 4], 4, 2, 3, 3), (vec![4, 5], 2, 2, 2, 1)]);
+    }
 }
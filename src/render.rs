@@ -0,0 +1,243 @@
+//! Common building blocks for rendering maps.
+
+use geomatic::Point3035;
+
+use crate::bounding_box::{BoundingBox, BoundingBox3035};
+
+
+/// Maps projected coordinates (EPSG:3035) to pixel coordinates of an image.
+///
+/// The given bounds are fitted into the image while preserving the aspect ratio.
+#[derive(Copy, Clone, Debug)]
+pub struct Projection {
+    bounds_3035: BoundingBox3035,
+    scale: f64,
+    offset_x: f64,
+    offset_y: f64,
+}
+
+impl Projection {
+    pub fn new(bounds: BoundingBox, width: u32, height: u32) -> Self {
+        let bounds_3035 = bounds.get_3035_bounds();
+
+        let bounds_width = bounds_3035.ne.coords.0 - bounds_3035.sw.coords.0;
+        let bounds_height = bounds_3035.ne.coords.1 - bounds_3035.sw.coords.1;
+
+        let canvas_ratio = width as f64 / height as f64;
+        let bounds_ratio = bounds_width / bounds_height;
+
+        let (scale, offset_x, offset_y) = if bounds_ratio > canvas_ratio {
+            let scale = width as f64 / bounds_width;
+            (
+                scale,
+                0.0,
+                (height as f64 - bounds_height * scale) * 0.5,
+            )
+        } else {
+            let scale = height as f64 / bounds_height;
+            (
+                scale,
+                (width as f64 - bounds_width * scale) * 0.5,
+                0.0,
+            )
+        };
+
+        Projection {
+            bounds_3035,
+            scale,
+            offset_x,
+            offset_y,
+        }
+    }
+
+    /// Return the pixel coordinates of the given point.
+    pub fn project(&self, point: Point3035) -> (f32, f32) {
+        (
+            (self.offset_x + (point.coords.0 - self.bounds_3035.sw.coords.0) * self.scale) as f32,
+            (self.offset_y + (self.bounds_3035.ne.coords.1 - point.coords.1) * self.scale) as f32,
+        )
+    }
+
+    /// Return a path of a straight line between two points.
+    pub fn line_path(&self, a: Point3035, b: Point3035) -> Option<tiny_skia::Path> {
+        let a = self.project(a);
+        let b = self.project(b);
+        let mut pb = tiny_skia::PathBuilder::new();
+        pb.move_to(a.0, a.1);
+        pb.line_to(b.0, b.1);
+        pb.finish()
+    }
+}
+
+/// Create a canvas with a white background.
+pub fn new_canvas(width: u32, height: u32) -> tiny_skia::Canvas {
+    let mut canvas = tiny_skia::Canvas::new(width, height).unwrap();
+    canvas.pixmap.fill(tiny_skia::Color::WHITE);
+    canvas
+}
+
+/// Horizontal alignment of text relative to its anchor point.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Align {
+    Center,
+    Right,
+}
+
+/// Glyphs of a 5x7 pixel font for digits, lowercase letters and a few symbols. Each row is a bit
+/// mask with the leftmost pixel in the highest of five bits.
+const FONT: [(char, [u8; 7]); 41] = [
+    ('0', [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110]),
+    ('1', [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('2', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111]),
+    ('3', [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110]),
+    ('4', [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010]),
+    ('5', [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110]),
+    ('6', [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110]),
+    ('7', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000]),
+    ('8', [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110]),
+    ('9', [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100]),
+    ('.', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100]),
+    ('-', [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000]),
+    ('[', [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110]),
+    (']', [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110]),
+    ('_', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111]),
+    ('a', [0b00000, 0b00000, 0b01110, 0b00001, 0b01111, 0b10001, 0b01111]),
+    ('b', [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b11110]),
+    ('c', [0b00000, 0b00000, 0b01110, 0b10000, 0b10000, 0b10001, 0b01110]),
+    ('d', [0b00001, 0b00001, 0b01101, 0b10011, 0b10001, 0b10001, 0b01111]),
+    ('e', [0b00000, 0b00000, 0b01110, 0b10001, 0b11111, 0b10000, 0b01110]),
+    ('f', [0b00110, 0b01001, 0b01000, 0b11100, 0b01000, 0b01000, 0b01000]),
+    ('g', [0b00000, 0b01111, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110]),
+    ('h', [0b10000, 0b10000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001]),
+    ('i', [0b00100, 0b00000, 0b01100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('j', [0b00010, 0b00000, 0b00110, 0b00010, 0b00010, 0b10010, 0b01100]),
+    ('k', [0b10000, 0b10000, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010]),
+    ('l', [0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('m', [0b00000, 0b00000, 0b11010, 0b10101, 0b10101, 0b10001, 0b10001]),
+    ('n', [0b00000, 0b00000, 0b10110, 0b11001, 0b10001, 0b10001, 0b10001]),
+    ('o', [0b00000, 0b00000, 0b01110, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('p', [0b00000, 0b00000, 0b11110, 0b10001, 0b11110, 0b10000, 0b10000]),
+    ('q', [0b00000, 0b00000, 0b01101, 0b10011, 0b01111, 0b00001, 0b00001]),
+    ('r', [0b00000, 0b00000, 0b10110, 0b11001, 0b10000, 0b10000, 0b10000]),
+    ('s', [0b00000, 0b00000, 0b01110, 0b10000, 0b01110, 0b00001, 0b11110]),
+    ('t', [0b01000, 0b01000, 0b11100, 0b01000, 0b01000, 0b01001, 0b00110]),
+    ('u', [0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b10011, 0b01101]),
+    ('v', [0b00000, 0b00000, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100]),
+    ('w', [0b00000, 0b00000, 0b10001, 0b10001, 0b10101, 0b10101, 0b01010]),
+    ('x', [0b00000, 0b00000, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001]),
+    ('y', [0b00000, 0b00000, 0b10001, 0b10001, 0b01111, 0b00001, 0b01110]),
+    ('z', [0b00000, 0b00000, 0b11111, 0b00010, 0b00100, 0b01000, 0b11111]),
+];
+
+/// Width of a glyph including the space to the next one, in font pixels
+const GLYPH_ADVANCE: f32 = 6.0;
+
+/// Draw text with a built-in bitmap font, so that no font files are needed. Uppercase letters are
+/// drawn as lowercase, other unknown characters as spaces. `x` is the anchor according to `align`,
+/// `y` the top of the text. Each font pixel is drawn as a square with the edge length `scale`.
+pub fn draw_text(
+    canvas: &mut tiny_skia::Canvas,
+    text: &str,
+    x: f32,
+    y: f32,
+    scale: f32,
+    align: Align,
+    color: tiny_skia::Color,
+) {
+    let width = (text.chars().count() as f32 * GLYPH_ADVANCE - 1.0).max(0.0) * scale;
+    let left = match align {
+        Align::Center => x - width / 2.0,
+        Align::Right => x - width,
+    };
+    let mut paint = tiny_skia::Paint::default();
+    paint.set_color(color);
+    for (i, c) in text.chars().enumerate() {
+        let c = c.to_ascii_lowercase();
+        let rows = match FONT.iter().find(|(glyph, _)| *glyph == c) {
+            Some((_, rows)) => rows,
+            None => continue,
+        };
+        let glyph_left = left + i as f32 * GLYPH_ADVANCE * scale;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..5 {
+                if bits & (0b10000 >> col) != 0 {
+                    let px = glyph_left + col as f32 * scale;
+                    let py = y + row as f32 * scale;
+                    if let Some(rect) = tiny_skia::Rect::from_ltrb(px, py, px + scale, py + scale) {
+                        canvas.fill_rect(rect, &paint);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A color gradient that is interpolated in linear RGB.
+pub struct ColorScale {
+    gradient: palette::Gradient<palette::LinSrgb>,
+}
+
+impl ColorScale {
+    fn from_srgb8(colors: &[(u8, u8, u8)]) -> Self {
+        let colors: Vec<palette::LinSrgb> = colors.iter().map(|&(r, g, b)| {
+            palette::Srgb::new(
+                r as f32 / 255.0,
+                g as f32 / 255.0,
+                b as f32 / 255.0,
+            ).into_linear()
+        }).collect();
+        ColorScale {
+            gradient: palette::Gradient::new(colors),
+        }
+    }
+
+    /// Sequential ColorBrewer YlGn color scale
+    pub fn yl_gn() -> Self {
+        Self::from_srgb8(&[
+            (247,252,185),
+            (217,240,163),
+            (173,221,142),
+            (120,198,121),
+            (65,171,93),
+            (35,132,67),
+            (0,104,55),
+            (0,69,41),
+        ])
+    }
+
+    /// Sequential ColorBrewer YlOrRd color scale
+    pub fn yl_or_rd() -> Self {
+        Self::from_srgb8(&[
+            (255,255,204),
+            (255,237,160),
+            (254,217,118),
+            (254,178,76),
+            (253,141,60),
+            (252,78,42),
+            (227,26,28),
+            (189,0,38),
+            (128,0,38),
+        ])
+    }
+
+    /// Diverging ColorBrewer RdBu color scale, from blue (0.0) over white (0.5) to red (1.0).
+    pub fn bu_rd() -> Self {
+        Self::from_srgb8(&[
+            (33,102,172),
+            (67,147,195),
+            (146,197,222),
+            (209,229,240),
+            (247,247,247),
+            (253,219,199),
+            (244,165,130),
+            (214,96,77),
+            (178,24,43),
+        ])
+    }
+
+    /// Return the color at the given position in the range [0.0, 1.0].
+    pub fn color(&self, t: f32) -> tiny_skia::Color {
+        let c = palette::Srgb::from_linear(self.gradient.get(t.clamp(0.0, 1.0)));
+        tiny_skia::Color::from_rgba(c.red, c.green, c.blue, 1.0).unwrap()
+    }
+}
@@ -1,15 +1,29 @@
-use std::io::{BufReader, BufWriter, Read, Seek, Write};
-use std::fs::File;
-use std::path::Path;
+use std::fmt;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::fs::{File, OpenOptions};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
+use anyhow::Context;
 use bincode;
 use geomatic::{laea, Point4326};
+use memmap::Mmap;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 
-use crate::geojson_writer::GeoJsonWriter;
+use crate::geometry::Geometry;
 use crate::network::{Network, OsmNodeId};
+use crate::route_index::RouteIndex;
 
 
+/// Signature at the start of each `*.routes` file.
+pub const MAGIC_BYTES: &[u8; 8] = b"NORIRTS\0";
+
+/// Version of the file format that is written. Files with the same major version and an older
+/// minor version are upgraded while reading. Files of version 0.2 have no signature.
+pub const MAJOR_VERSION: u16 = 0;
+pub const MINOR_VERSION: u16 = 6;
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct LatLon32 {
     raw_lat: i32,
@@ -24,7 +38,7 @@ impl LatLon32 {
         }
     }
 
-    fn as_point4326(&self) -> Point4326 {
+    pub fn as_point4326(&self) -> Point4326 {
         Point4326::new(
             (self.raw_lat as f64) * 1e-6,
             (self.raw_lon as f64) * 1e-6,
@@ -36,8 +50,147 @@ impl LatLon32 {
 pub struct Route {
     pub start_coord: LatLon32,
     pub end_coord: LatLon32,
+    /// Stored as variable-length differences since version 0.5 (see `compact_node_ids`)
+    #[serde(with = "compact_node_ids")]
     pub node_ids: Vec<OsmNodeId>,
+    /// Length in meters
     pub distance: f64,
+    /// Travel time in seconds. Not available in files before version 0.3.
+    pub duration: Option<f64>,
+}
+
+/// A route as stored in files of version 0.2
+#[derive(Deserialize)]
+struct RouteV0_2 {
+    start_coord: LatLon32,
+    end_coord: LatLon32,
+    node_ids: Vec<OsmNodeId>,
+    distance: f64,
+}
+
+impl From<RouteV0_2> for Route {
+    fn from(route: RouteV0_2) -> Self {
+        Route {
+            start_coord: route.start_coord,
+            end_coord: route.end_coord,
+            node_ids: route.node_ids,
+            distance: route.distance,
+            duration: None,
+        }
+    }
+}
+
+/// A route as stored in files of version 0.3 and 0.4
+#[derive(Serialize, Deserialize)]
+struct RouteV0_4 {
+    start_coord: LatLon32,
+    end_coord: LatLon32,
+    node_ids: Vec<OsmNodeId>,
+    distance: f64,
+    duration: Option<f64>,
+}
+
+impl From<RouteV0_4> for Route {
+    fn from(route: RouteV0_4) -> Self {
+        Route {
+            start_coord: route.start_coord,
+            end_coord: route.end_coord,
+            node_ids: route.node_ids,
+            distance: route.distance,
+            duration: route.duration,
+        }
+    }
+}
+
+/// Encode node ids as the differences between consecutive ids. Neighbouring nodes of a route
+/// usually have similar ids, so the zigzag-encoded differences fit into one or two bytes with a
+/// variable-length (LEB128) encoding instead of eight bytes per id.
+mod compact_node_ids {
+    use std::fmt;
+
+    use serde::{Deserializer, Serializer};
+    use serde::de::{self, Visitor};
+
+    use crate::network::OsmNodeId;
+
+    pub fn encode(node_ids: &[OsmNodeId]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(node_ids.len() * 2 + 2);
+        write_varint(&mut buf, node_ids.len() as u64);
+        let mut prev = 0i64;
+        for id in node_ids {
+            let delta = id.as_i64().wrapping_sub(prev);
+            write_varint(&mut buf, ((delta << 1) ^ (delta >> 63)) as u64);
+            prev = id.as_i64();
+        }
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Vec<OsmNodeId>, &'static str> {
+        let len = read_varint(&mut buf)?;
+        // Every id takes at least one byte
+        if len > buf.len() as u64 {
+            return Err("too many node ids");
+        }
+        let mut node_ids = Vec::with_capacity(len as usize);
+        let mut prev = 0i64;
+        for _ in 0..len {
+            let zigzag = read_varint(&mut buf)?;
+            let delta = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+            prev = prev.wrapping_add(delta);
+            node_ids.push(OsmNodeId::new(prev));
+        }
+        if !buf.is_empty() {
+            return Err("trailing bytes after node ids");
+        }
+        Ok(node_ids)
+    }
+
+    fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn read_varint(buf: &mut &[u8]) -> Result<u64, &'static str> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = buf.split_first().ok_or("unexpected end of node ids")?;
+            *buf = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint is too long")
+    }
+
+    pub fn serialize<S: Serializer>(node_ids: &[OsmNodeId], serializer: S)
+        -> Result<S::Ok, S::Error>
+    {
+        serializer.serialize_bytes(&encode(node_ids))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D)
+        -> Result<Vec<OsmNodeId>, D::Error>
+    {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = Vec<OsmNodeId>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("encoded node ids")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                decode(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_bytes(BytesVisitor)
+    }
 }
 
 impl Route {
@@ -50,113 +203,1118 @@ impl Route {
         dx.hypot(dy)
     }
 
-    pub fn write_to_geojson<P: AsRef<Path>>(&self, output_path: P, network: &Network) -> anyhow::Result<()> {
-        let mut writer = GeoJsonWriter::from_path(output_path)?;
+    /// The path through the network. Fails if a node is not part of the network.
+    pub fn geometry(&self, network: &Network) -> anyhow::Result<Geometry> {
+        let coords = self.node_ids.iter()
+            .map(|&n| network.get_node(n).map(|n| n.as_point4326())
+                .ok_or_else(|| anyhow::anyhow!("Node {} is not part of the network", n.as_i64())))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Geometry::LineString(coords))
+    }
+}
+
+/// An input file of a run together with its SHA-256 checksum to detect later changes.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct InputFile {
+    /// What the file is used for, e.g. "population"
+    pub role: String,
+    pub path: String,
+    /// Hex-encoded SHA-256 digest of the contents
+    pub sha256: String,
+}
+
+impl InputFile {
+    pub fn new<S: Into<String>>(role: S, path: &str) -> anyhow::Result<Self> {
+        Self::from_reader(role, path, File::open(path)?)
+    }
+
+    /// Compute the checksum of the contents of `reader`, which were read from `path`.
+    pub fn from_reader<S: Into<String>, R: Read>(role: S, path: &str, mut reader: R)
+        -> anyhow::Result<Self>
+    {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut reader, &mut hasher)?;
+        let sha256 = hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect();
+        Ok(InputFile {
+            role: role.into(),
+            path: path.to_string(),
+            sha256,
+        })
+    }
+}
+
+/// Everything that is needed to tell how the routes of a file were created.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+pub struct RunMetadata {
+    /// Version of nori that created the file
+    pub nori_version: String,
+    /// Time of creation (RFC 3339)
+    pub created: String,
+    /// Name of the sampling strategy, e.g. "uniform2d"
+    pub sampler: String,
+    /// South-west and north-east corner (lat, lon, lat, lon)
+    pub bounds: Option<[f64; 4]>,
+    /// Maximum distance between source and destination in meters
+    pub max_dist: Option<f64>,
+    pub input_files: Vec<InputFile>,
+    /// Seed of the random number generator of the sampling strategy
+    pub seed: u64,
+    pub osrm_url: String,
+    pub osrm_profile: String,
+    /// How the routes were derived from other collections, e.g. "merge". Empty for sampled
+    /// routes.
+    pub operation: String,
+    /// Metadata of the collections that the routes were derived from
+    pub parts: Vec<RunMetadata>,
+}
+
+/// The run metadata of files of version 0.4 and 0.5
+#[derive(Serialize, Deserialize)]
+struct RunMetadataV0_5 {
+    nori_version: String,
+    created: String,
+    sampler: String,
+    bounds: Option<[f64; 4]>,
+    max_dist: Option<f64>,
+    input_files: Vec<InputFile>,
+    seed: u64,
+    osrm_url: String,
+    osrm_profile: String,
+}
+
+impl From<RunMetadataV0_5> for RunMetadata {
+    fn from(meta: RunMetadataV0_5) -> Self {
+        RunMetadata {
+            nori_version: meta.nori_version,
+            created: meta.created,
+            sampler: meta.sampler,
+            bounds: meta.bounds,
+            max_dist: meta.max_dist,
+            input_files: meta.input_files,
+            seed: meta.seed,
+            osrm_url: meta.osrm_url,
+            osrm_profile: meta.osrm_profile,
+            operation: String::new(),
+            parts: vec![],
+        }
+    }
+}
+
+impl RunMetadata {
+    /// Metadata of a collection that was derived from the given collections by `operation`.
+    /// Settings that all parts have in common are kept.
+    pub fn derived<S: Into<String>>(operation: S, parts: Vec<RunMetadata>) -> Self {
+        fn common<T: Clone + PartialEq + Default>(parts: &[RunMetadata], f: impl Fn(&RunMetadata) -> T) -> T {
+            match parts.split_first() {
+                Some((first, rest)) if rest.iter().all(|p| f(p) == f(first)) => f(first),
+                _ => T::default(),
+            }
+        }
 
-        let coords: Option<Vec<_>> = self.node_ids.iter().map(|&n| network.get_node(n).map(|n| n.as_point4326())).collect();
-        let coords = coords.unwrap();
-        let mut ls = writer.add_line_string(&coords)?;
-        ls.add_property("distance", self.distance)?;
-        ls.add_property("distance_bee_line", self.distance_bee_line())?;
-        ls.finish()?;
+        let mut input_files: Vec<InputFile> = vec![];
+        for input in parts.iter().flat_map(|p| &p.input_files) {
+            if !input_files.contains(input) {
+                input_files.push(input.clone());
+            }
+        }
 
-        writer.finish()?;
+        RunMetadata {
+            nori_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: chrono::Utc::now().to_rfc3339(),
+            sampler: common(&parts, |p| p.sampler.clone()),
+            bounds: common(&parts, |p| p.bounds),
+            max_dist: common(&parts, |p| p.max_dist),
+            input_files,
+            seed: common(&parts, |p| p.seed),
+            osrm_url: common(&parts, |p| p.osrm_url.clone()),
+            osrm_profile: common(&parts, |p| p.osrm_profile.clone()),
+            operation: operation.into(),
+            parts,
+        }
+    }
 
+    /// Describe the settings of `other` that differ from this run and would change the sampled
+    /// routes. The version, time of creation, seed and server URL are not compared.
+    pub fn differences(&self, other: &RunMetadata) -> Vec<String> {
+        let mut differences = vec![];
+        if self.operation != other.operation {
+            differences.push(format!("operation {:?} instead of {:?}", other.operation, self.operation));
+        }
+        if self.sampler != other.sampler {
+            differences.push(format!("sampler {:?} instead of {:?}", other.sampler, self.sampler));
+        }
+        if self.bounds != other.bounds {
+            differences.push(format!("bounds {:?} instead of {:?}", other.bounds, self.bounds));
+        }
+        if self.max_dist != other.max_dist {
+            differences.push(format!("max. distance {:?} instead of {:?}", other.max_dist, self.max_dist));
+        }
+        if self.osrm_profile != other.osrm_profile {
+            differences.push(format!(
+                "OSRM profile {:?} instead of {:?}", other.osrm_profile, self.osrm_profile
+            ));
+        }
+        // Input files may have been moved, but their contents must not have changed.
+        let contents = |meta: &RunMetadata| {
+            let mut contents: Vec<(String, String)> = meta.input_files.iter()
+                .map(|input| (input.role.clone(), input.sha256.clone()))
+                .collect();
+            contents.sort();
+            contents
+        };
+        if contents(self) != contents(other) {
+            let paths = |meta: &RunMetadata| meta.input_files.iter()
+                .map(|input| format!("{} {:?}", input.role, input.path))
+                .collect::<Vec<_>>()
+                .join(", ");
+            differences.push(format!(
+                "input files [{}] instead of [{}] (or their contents changed)", paths(other), paths(self)
+            ));
+        }
+        differences
+    }
+
+    fn write_to(&self, f: &mut fmt::Formatter<'_>, indent: &str) -> fmt::Result {
+        if !self.operation.is_empty() {
+            writeln!(f, "{}operation:        {}", indent, self.operation)?;
+        }
+        writeln!(f, "{}nori version:     {}", indent, self.nori_version)?;
+        writeln!(f, "{}created:          {}", indent, self.created)?;
+        writeln!(f, "{}sampler:          {}", indent, self.sampler)?;
+        if let Some(b) = self.bounds {
+            writeln!(f, "{}bounds:           {} {} {} {}", indent, b[0], b[1], b[2], b[3])?;
+        }
+        if let Some(max_dist) = self.max_dist {
+            writeln!(f, "{}max distance:     {} m", indent, max_dist)?;
+        }
+        writeln!(f, "{}seed:             {}", indent, self.seed)?;
+        writeln!(f, "{}osrm url:         {}", indent, self.osrm_url)?;
+        writeln!(f, "{}osrm profile:     {}", indent, self.osrm_profile)?;
+        for input in &self.input_files {
+            writeln!(f, "{}input ({}): {}", indent, input.role, input.path)?;
+            writeln!(f, "{}    sha256: {}", indent, input.sha256)?;
+        }
+        for (i, part) in self.parts.iter().enumerate() {
+            writeln!(f, "{}part {}:", indent, i + 1)?;
+            part.write_to(f, &format!("{}    ", indent))?;
+        }
         Ok(())
     }
 }
 
+/// The header follows the signature. The version numbers always come first, so that the rest of
+/// the file can be read according to its version.
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct RouteCollectionHeader {
     major_version: u16,
     minor_version: u16,
     pub osrm_file: String,
     scenario: String,
+    /// Zero while the file is being written. Readers then read routes until the end of the file,
+    /// so that routes from interrupted runs can be recovered.
+    number_of_routes: u64,
+    /// Empty for files before version 0.4
+    pub metadata: RunMetadata,
+}
+
+/// The header of files of version 0.2 and 0.3
+#[derive(Serialize, Deserialize)]
+struct RouteCollectionHeaderV0_3 {
+    major_version: u16,
+    minor_version: u16,
+    osrm_file: String,
+    scenario: String,
     number_of_routes: u64,
 }
 
+/// The header of files of version 0.4 and 0.5
+#[derive(Serialize, Deserialize)]
+struct RouteCollectionHeaderV0_5 {
+    major_version: u16,
+    minor_version: u16,
+    osrm_file: String,
+    scenario: String,
+    number_of_routes: u64,
+    metadata: RunMetadataV0_5,
+}
+
+impl From<RouteCollectionHeaderV0_5> for RouteCollectionHeader {
+    fn from(header: RouteCollectionHeaderV0_5) -> Self {
+        RouteCollectionHeader {
+            major_version: header.major_version,
+            minor_version: header.minor_version,
+            osrm_file: header.osrm_file,
+            scenario: header.scenario,
+            number_of_routes: header.number_of_routes,
+            metadata: header.metadata.into(),
+        }
+    }
+}
+
+impl From<RouteCollectionHeaderV0_3> for RouteCollectionHeader {
+    fn from(header: RouteCollectionHeaderV0_3) -> Self {
+        RouteCollectionHeader {
+            major_version: header.major_version,
+            minor_version: header.minor_version,
+            osrm_file: header.osrm_file,
+            scenario: header.scenario,
+            number_of_routes: header.number_of_routes,
+            metadata: RunMetadata::default(),
+        }
+    }
+}
+
+impl RouteCollectionHeader {
+    /// Major and minor version of the file format. Upgraded files keep their original version.
+    pub fn version(&self) -> (u16, u16) {
+        (self.major_version, self.minor_version)
+    }
+
+    pub fn number_of_routes(&self) -> u64 {
+        self.number_of_routes
+    }
+
+    pub fn scenario(&self) -> &str {
+        &self.scenario
+    }
+
+    /// Seed to continue sampling after the routes of this file. It differs from the seed of the
+    /// original run, so that resumed runs add new routes instead of repeating the first ones,
+    /// but is still reproducible.
+    pub fn resume_seed(&self) -> u64 {
+        self.metadata.seed.wrapping_add(self.number_of_routes)
+    }
+}
+
+impl fmt::Display for RouteCollectionHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "format version:   {}.{}", self.major_version, self.minor_version)?;
+        if self.number_of_routes == 0 {
+            writeln!(f, "number of routes: 0 (or the file is unfinished)")?;
+        } else {
+            writeln!(f, "number of routes: {}", self.number_of_routes)?;
+        }
+        writeln!(f, "osrm file:        {}", self.osrm_file)?;
+        writeln!(f, "scenario:         {}", self.scenario)?;
+        if self.minor_version < 4 {
+            return writeln!(f, "(no run metadata before format version 0.4)");
+        }
+        self.metadata.write_to(f, "")
+    }
+}
+
 pub struct RouteCollectionWriter<W: Write> {
     writer: BufWriter<W>,
     header: RouteCollectionHeader,
+    /// Path of the file, to write an index next to it. None for other writers.
+    path: Option<PathBuf>,
+    /// Byte offset of each route that has been written
+    offsets: Vec<u64>,
+    /// Current length of the file
+    position: u64,
 }
 
 
 impl RouteCollectionWriter<File> {
-    pub fn new<P: AsRef<Path>, S: Into<String>>(path: P, osrm_file: S, scenario: S)
+    /// Create a `*.routes` file. `finish` also writes an index next to it. An index of a previous
+    /// file at this path is removed, because it could be mistaken for the index of the new file.
+    pub fn new<P: AsRef<Path>, S: Into<String>>(path: P, osrm_file: S, scenario: S, metadata: RunMetadata)
         -> anyhow::Result<RouteCollectionWriter<File>>
     {
-        let mut writer = BufWriter::new(File::create(path)?);
+        let index_path = RouteIndex::path_for(&path);
+        match std::fs::remove_file(&index_path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                return Err(err)
+                    .with_context(|| format!("Failed to remove the old index {:?}", index_path));
+            },
+            _ => {},
+        }
+        let mut writer = Self::from_writer(File::create(&path)?, osrm_file, scenario, metadata)?;
+        writer.path = Some(path.as_ref().to_path_buf());
+        Ok(writer)
+    }
+
+    /// Continue writing to an existing file, e.g. after an interrupted run. All complete routes of
+    /// the file are passed to `replay`, an incomplete route at the end is removed.
+    pub fn resume<P: AsRef<Path>, F: FnMut(&Route)>(path: P, mut replay: F)
+        -> anyhow::Result<RouteCollectionWriter<File>>
+    {
+        let mut reader = RouteCollectionReader::new(&path)?;
+        if reader.header.version() != (MAJOR_VERSION, MINOR_VERSION) {
+            let (major, minor) = reader.header.version();
+            anyhow::bail!(
+                "Can only resume files of format version {}.{}, but the file has version {}.{}",
+                MAJOR_VERSION,
+                MINOR_VERSION,
+                major,
+                minor,
+            );
+        }
+
+        let mut offsets = vec![];
+        loop {
+            let offset = reader.reader.offset;
+            match reader.next() {
+                Some(route) => {
+                    replay(&route?);
+                    offsets.push(offset);
+                },
+                None => break,
+            }
+        }
+        let valid_len = reader.valid_len();
+        let mut header = reader.header;
+
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(valid_len)?;
+
+        // Mark the file as unfinished until `finish` is called.
+        header.number_of_routes = 0;
+        file.seek(SeekFrom::Start(MAGIC_BYTES.len() as u64))?;
+        bincode::serialize_into(&mut file, &header)?;
+        file.seek(SeekFrom::End(0))?;
+        header.number_of_routes = offsets.len() as u64;
+
+        Ok(RouteCollectionWriter {
+            writer: BufWriter::new(file),
+            header,
+            path: Some(path.as_ref().to_path_buf()),
+            offsets,
+            position: valid_len,
+        })
+    }
+}
+
+impl<W: Write> RouteCollectionWriter<W> {
+    /// Write the header of a `*.routes` file to any writer, e.g. an in-memory buffer.
+    pub fn from_writer<S: Into<String>>(writer: W, osrm_file: S, scenario: S, metadata: RunMetadata)
+        -> anyhow::Result<RouteCollectionWriter<W>>
+    {
+        let mut writer = BufWriter::new(writer);
 
         // write header
         let header = RouteCollectionHeader {
-            major_version: 0,
-            minor_version: 2,
+            major_version: MAJOR_VERSION,
+            minor_version: MINOR_VERSION,
             osrm_file: osrm_file.into(),
             scenario: scenario.into(),
             number_of_routes: 0,
+            metadata,
         };
+        writer.write_all(MAGIC_BYTES)?;
         bincode::serialize_into(&mut writer, &header)?;
+        let position = MAGIC_BYTES.len() as u64 + bincode::serialized_size(&header)?;
 
         Ok(RouteCollectionWriter {
             writer,
             header,
+            path: None,
+            offsets: vec![],
+            position,
         })
     }
 
+    pub fn header(&self) -> &RouteCollectionHeader {
+        &self.header
+    }
+
     pub fn write_route(&mut self, route: Route) -> anyhow::Result<Route> {
-        bincode::serialize_into(&mut self.writer, &route)?;
+        let buf = bincode::serialize(&route)?;
+        self.writer.write_all(&buf)?;
+        self.offsets.push(self.position);
+        self.position += buf.len() as u64;
         self.header.number_of_routes += 1;
         Ok(route)
     }
+}
 
-    pub fn finish(mut self) -> anyhow::Result<()> {
-        // Move to start of file
-        self.writer.seek(std::io::SeekFrom::Start(0))?;
+impl<W: Write + Seek> RouteCollectionWriter<W> {
+    /// Write the final header and, for files, an index of all routes (see `RouteIndex`). Returns
+    /// the underlying writer.
+    pub fn finish(mut self) -> anyhow::Result<W> {
+        // Move to start of header
+        self.writer.seek(SeekFrom::Start(MAGIC_BYTES.len() as u64))?;
         // Write header again, but with correct number_of_routes
         bincode::serialize_into(&mut self.writer, &self.header)?;
         // Always flush!
         self.writer.flush()?;
+
+        if let Some(path) = &self.path {
+            RouteIndex::new(self.position, self.offsets).write_for(path)
+                .with_context(|| format!(
+                    "Failed to write index {:?}", RouteIndex::path_for(path)
+                ))?;
+        }
+        self.writer.into_inner().map_err(|err| err.into_error().into())
+    }
+}
+
+/// A buffered reader that keeps track of the byte offset from the start of the file.
+struct OffsetReader<R: Read> {
+    inner: BufReader<R>,
+    offset: u64,
+}
+
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for OffsetReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.offset += amt as u64;
+    }
+}
+
+impl<R: Read + Seek> OffsetReader<R> {
+    fn seek_to(&mut self, offset: u64) -> std::io::Result<()> {
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
         Ok(())
     }
 }
 
+/// Read the signature and the header of a `*.routes` file and check the version. Reads exactly
+/// the bytes of the header, without seeking.
+fn read_header<R: Read>(reader: &mut R) -> anyhow::Result<RouteCollectionHeader> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)
+        .map_err(|_| anyhow::anyhow!("File is too short to be a *.routes file"))?;
+
+    // Files of version 0.2 start with the header, so the bytes that have been read already belong
+    // to it.
+    let has_signature = &magic == MAGIC_BYTES;
+    let mut version = [0u8; 4];
+    let prefix: &[u8] = if has_signature {
+        reader.read_exact(&mut version)?;
+        &version
+    } else {
+        &magic
+    };
+    let (major, minor): (u16, u16) = bincode::deserialize(prefix)?;
+    check_version(major, minor, has_signature)?;
+
+    // The version numbers are part of the header.
+    let mut reader = prefix.chain(reader);
+    Ok(if minor < 4 {
+        bincode::deserialize_from::<_, RouteCollectionHeaderV0_3>(&mut reader)?.into()
+    } else if minor < 6 {
+        bincode::deserialize_from::<_, RouteCollectionHeaderV0_5>(&mut reader)?.into()
+    } else {
+        bincode::deserialize_from(&mut reader)?
+    })
+}
+
+/// Read a route that is stored in the given format version.
+fn read_route<R: Read>(version: (u16, u16), reader: R) -> bincode::Result<Route> {
+    match version {
+        (0, 2) => bincode::deserialize_from::<_, RouteV0_2>(reader).map(Route::from),
+        (0, 3) | (0, 4) => bincode::deserialize_from::<_, RouteV0_4>(reader).map(Route::from),
+        _ => bincode::deserialize_from(reader),
+    }
+}
+
 pub struct RouteCollectionReader<R: Read> {
-    reader: BufReader<R>,
+    reader: OffsetReader<R>,
     header: RouteCollectionHeader,
+    /// Path of the file, to load its index. None for other readers.
+    path: Option<PathBuf>,
     route_index: u64,
+    /// Byte offset of the first route
+    data_offset: u64,
+    /// Byte offset of an incomplete route at the end of an unfinished file
+    truncated_at: Option<u64>,
+    /// Loaded on first random access
+    index: Option<RouteIndex>,
 }
 
 impl RouteCollectionReader<File> {
+    /// Open a `*.routes` file and check its signature and version.
     pub fn new<P: AsRef<Path>>(path: P) -> anyhow::Result<RouteCollectionReader<File>> {
-        let mut reader = BufReader::new(File::open(path)?);
+        let mut reader = Self::from_reader(File::open(&path)?)?;
+        reader.path = Some(path.as_ref().to_path_buf());
+        Ok(reader)
+    }
+}
 
-        // read header
-        let header = bincode::deserialize_from(&mut reader)?;
+impl<R: Read> RouteCollectionReader<R> {
+    /// Read routes from the start of a `*.routes` file in any reader, e.g. stdin or an in-memory
+    /// buffer. Random access (`get`, `range`) is only available for readers that implement `Seek`.
+    pub fn from_reader(reader: R) -> anyhow::Result<RouteCollectionReader<R>> {
+        let mut reader = OffsetReader {
+            inner: BufReader::new(reader),
+            offset: 0,
+        };
+        let header = read_header(&mut reader)?;
+        let data_offset = reader.offset;
 
         Ok(RouteCollectionReader {
             reader,
             header,
+            path: None,
             route_index: 0,
+            data_offset,
+            truncated_at: None,
+            index: None,
         })
     }
 
     pub fn header(&self) -> &RouteCollectionHeader {
         &self.header
     }
+
+    /// Length of the file without an incomplete route at the end. Only valid after all routes
+    /// have been read.
+    fn valid_len(&self) -> u64 {
+        self.truncated_at.unwrap_or(self.reader.offset)
+    }
+
+    /// Read the next route of a file that has not been finished. Stops at the end of the file or
+    /// at an incomplete route.
+    fn read_unfinished(&mut self) -> Option<anyhow::Result<Route>> {
+        if self.truncated_at.is_some() {
+            return None;
+        }
+        let offset = self.reader.offset;
+        match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => {},
+            Err(err) => return Some(Err(err.into())),
+        }
+        match read_route(self.header.version(), &mut self.reader) {
+            Ok(route) => Some(Ok(route)),
+            Err(err) => match *err {
+                bincode::ErrorKind::Io(ref io_err) if io_err.kind() == ErrorKind::UnexpectedEof => {
+                    println!(
+                        "Warning: ignoring an incomplete route at the end of an unfinished file \
+                         (after {} routes)",
+                        self.route_index,
+                    );
+                    self.truncated_at = Some(offset);
+                    None
+                },
+                _ => Some(Err(err.into())),
+            },
+        }
+    }
+}
+
+impl<R: Read + Seek> RouteCollectionReader<R> {
+    /// The offsets of all routes. Loaded from the index file if it is up to date, otherwise all
+    /// routes are read once.
+    pub fn index(&mut self) -> anyhow::Result<&RouteIndex> {
+        if self.index.is_none() {
+            let loaded = match &self.path {
+                Some(path) => RouteIndex::load_for(path, self.header.number_of_routes)?,
+                None => None,
+            };
+            let index = match loaded {
+                Some(index) => index,
+                None => self.build_index()?,
+            };
+            self.index = Some(index);
+        }
+        Ok(self.index.as_ref().unwrap())
+    }
+
+    fn build_index(&mut self) -> anyhow::Result<RouteIndex> {
+        let (offset, route_index) = (self.reader.offset, self.route_index);
+        self.reader.seek_to(self.data_offset)?;
+        self.route_index = 0;
+
+        let mut offsets = vec![];
+        loop {
+            let offset = self.reader.offset;
+            match self.next() {
+                Some(route) => {
+                    route?;
+                    offsets.push(offset);
+                },
+                None => break,
+            }
+        }
+        let index = RouteIndex::new(self.valid_len(), offsets);
+
+        self.reader.seek_to(offset)?;
+        self.route_index = route_index;
+        Ok(index)
+    }
+
+    /// Number of complete routes, also for unfinished files.
+    pub fn len(&mut self) -> anyhow::Result<u64> {
+        Ok(self.index()?.len())
+    }
+
+    /// Read the route with the given index.
+    pub fn get(&mut self, index: u64) -> anyhow::Result<Route> {
+        let offset = self.index()?.offset(index)
+            .ok_or_else(|| anyhow::anyhow!("There is no route with index {}", index))?;
+        self.reader.seek_to(offset)?;
+        let route = read_route(self.header.version(), &mut self.reader)?;
+        self.route_index = index + 1;
+        Ok(route)
+    }
+
+    /// Iterate over the routes with indices in the given range.
+    pub fn range(&mut self, range: Range<u64>)
+        -> anyhow::Result<impl Iterator<Item=anyhow::Result<Route>> + '_>
+    {
+        let len = self.len()?;
+        if range.start > range.end || range.end > len {
+            anyhow::bail!("Invalid range {}..{} of {} routes", range.start, range.end, len);
+        }
+        if range.start < len {
+            let offset = self.index()?.offset(range.start).unwrap();
+            self.reader.seek_to(offset)?;
+        }
+        self.route_index = range.start;
+        let version = self.header.version();
+        let reader = &mut self.reader;
+        let route_index = &mut self.route_index;
+        Ok(range.map(move |_| {
+            *route_index += 1;
+            read_route(version, &mut *reader).map_err(|err| err.into())
+        }))
+    }
 }
 
-impl Iterator for RouteCollectionReader<File> {
+impl<R: Read> Iterator for RouteCollectionReader<R> {
     type Item = anyhow::Result<Route>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.route_index >= self.header.number_of_routes {
+        if self.header.number_of_routes == 0 {
+            let route = self.read_unfinished();
+            if route.is_some() {
+                self.route_index += 1;
+            }
+            route
+        } else if self.route_index >= self.header.number_of_routes {
             None
         } else {
             self.route_index += 1;
-            match bincode::deserialize_from(&mut self.reader) {
-                Ok(route) => Some(Ok(route)),
-                Err(err) => Some(Err(err.into())),
+            Some(read_route(self.header.version(), &mut self.reader).map_err(|err| err.into()))
+        }
+    }
+}
+
+/// A `*.routes` file that is mapped into memory for fast repeated random access.
+pub struct MappedRouteCollection {
+    mmap: Mmap,
+    header: RouteCollectionHeader,
+    index: RouteIndex,
+}
+
+impl MappedRouteCollection {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut reader = RouteCollectionReader::new(&path)?;
+        let index = reader.index()?.clone();
+        let file = File::open(&path)?;
+        // Safety: The file must not be modified while it is mapped.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(MappedRouteCollection {
+            mmap,
+            header: reader.header,
+            index,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.index.len()
+    }
+
+    pub fn get(&self, index: u64) -> anyhow::Result<Route> {
+        let offset = self.index.offset(index)
+            .ok_or_else(|| anyhow::anyhow!("There is no route with index {}", index))?;
+        let data = self.mmap.get(offset as usize..)
+            .ok_or_else(|| anyhow::anyhow!("The file is shorter than its index"))?;
+        Ok(read_route(self.header.version(), data)?)
+    }
+}
+
+/// Make sure that a file with the given version can be read.
+fn check_version(major: u16, minor: u16, has_signature: bool) -> anyhow::Result<()> {
+    if !has_signature {
+        if (major, minor) == (0, 2) {
+            return Ok(());
+        }
+        anyhow::bail!("Not a *.routes file (the file signature is missing)");
+    }
+    if major != MAJOR_VERSION || minor > MINOR_VERSION {
+        anyhow::bail!(
+            "The file has the format version {}.{}, but this version of nori can only read versions \
+             {}.0 to {}.{}{}",
+            major,
+            minor,
+            MAJOR_VERSION,
+            MAJOR_VERSION,
+            MINOR_VERSION,
+            if (major, minor) > (MAJOR_VERSION, MINOR_VERSION) { ". Please update nori." } else { "" },
+        );
+    }
+    if minor < 3 {
+        // Version 0.3 introduced the signature.
+        anyhow::bail!("Invalid format version {}.{} after file signature", major, minor);
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_route, TempFile};
+
+    #[test]
+    fn test_round_trip() {
+        let path = TempFile::new("round_trip.routes");
+        let metadata = RunMetadata {
+            sampler: "uniform2d".to_string(),
+            bounds: Some([52.0, 13.0, 53.0, 14.0]),
+            seed: 42,
+            ..Default::default()
+        };
+        let mut writer = RouteCollectionWriter::new(&path, "test.osrm", "sample", metadata.clone())
+            .unwrap();
+        for i in 0..3 {
+            writer.write_route(test_route(i)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let reader = RouteCollectionReader::new(&path).unwrap();
+        assert_eq!(reader.header().version(), (MAJOR_VERSION, MINOR_VERSION));
+        assert_eq!(reader.header().osrm_file, "test.osrm");
+        assert_eq!(reader.header().metadata, metadata);
+        let routes: Vec<Route> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(routes, (0..3).map(test_route).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_random_access() {
+        let path = TempFile::new("random_access.routes");
+        let mut writer = RouteCollectionWriter::new(
+            &path, "test.osrm", "sample", RunMetadata::default()
+        ).unwrap();
+        for i in 0..5 {
+            writer.write_route(test_route(i)).unwrap();
+        }
+        writer.finish().unwrap();
+        assert!(RouteIndex::load_for(&path, 5).unwrap().is_some());
+
+        let mut reader = RouteCollectionReader::new(&path).unwrap();
+        assert_eq!(reader.get(3).unwrap(), test_route(3));
+        assert_eq!(reader.get(0).unwrap(), test_route(0));
+        assert!(reader.get(5).is_err());
+        let routes: Vec<Route> = reader.range(1..4).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(routes, (1..4).map(test_route).collect::<Vec<_>>());
+        // Sequential reading continues after the range
+        assert_eq!(reader.next().unwrap().unwrap(), test_route(4));
+        assert!(reader.next().is_none());
+        assert!(reader.range(4..6).is_err());
+
+        let mapped = MappedRouteCollection::open(&path).unwrap();
+        assert_eq!(mapped.len(), 5);
+        assert_eq!(mapped.get(4).unwrap(), test_route(4));
+
+        // Without an index file, the offsets are found by reading all routes.
+        std::fs::remove_file(RouteIndex::path_for(&path)).unwrap();
+        let mut reader = RouteCollectionReader::new(&path).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap(), test_route(0));
+        assert_eq!(reader.len().unwrap(), 5);
+        assert_eq!(reader.get(2).unwrap(), test_route(2));
+        // Sequential reading continues after the last random access
+        assert_eq!(reader.next().unwrap().unwrap(), test_route(3));
+    }
+
+    #[test]
+    fn test_recover_unfinished() {
+        let path = TempFile::new("unfinished.routes");
+        {
+            let mut writer = RouteCollectionWriter::new(
+                &path, "test.osrm", "sample", RunMetadata::default()
+            ).unwrap();
+            for i in 0..3 {
+                writer.write_route(test_route(i)).unwrap();
+            }
+            // Dropped without calling finish
+        }
+        // Append half a route
+        let partial = bincode::serialize(&test_route(3)).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&partial[..partial.len() / 2]).unwrap();
+        drop(file);
+
+        let reader = RouteCollectionReader::new(&path).unwrap();
+        let routes: Vec<Route> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(routes, (0..3).map(test_route).collect::<Vec<_>>());
+
+        // Resuming removes the incomplete route
+        let mut replayed = vec![];
+        let mut writer = RouteCollectionWriter::resume(&path, |r| replayed.push(r.distance))
+            .unwrap();
+        assert_eq!(replayed, vec![0.0, 100.0, 200.0]);
+        writer.write_route(test_route(3)).unwrap();
+        writer.finish().unwrap();
+
+        let reader = RouteCollectionReader::new(&path).unwrap();
+        assert_eq!(reader.header().number_of_routes, 4);
+        let routes: Vec<Route> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(routes, (0..4).map(test_route).collect::<Vec<_>>());
+        assert_eq!(RouteIndex::load_for(&path, 4).unwrap().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_new_removes_old_index() {
+        let path = TempFile::new("old_index.routes");
+        let write = |i: i64| {
+            let mut writer = RouteCollectionWriter::new(
+                &path, "test.osrm", "sample", RunMetadata::default()
+            ).unwrap();
+            writer.write_route(test_route(i)).unwrap();
+            writer
+        };
+        write(1).finish().unwrap();
+        assert!(RouteIndex::path_for(&path).exists());
+
+        // A file of the same length that is not finished yet
+        let writer = write(2);
+        assert!(!RouteIndex::path_for(&path).exists());
+        drop(writer);
+        let mut reader = RouteCollectionReader::new(&path).unwrap();
+        assert_eq!(reader.get(0).unwrap(), test_route(2));
+    }
+
+    #[test]
+    fn test_resume_seed() {
+        use crate::sampling::{Sampling, Uniform2D};
+
+        let bounds = crate::bounding_box::BoundingBox::new(
+            Point4326::new(52.4, 13.2),
+            Point4326::new(52.6, 13.5),
+        );
+        let write_samples = |writer: &mut RouteCollectionWriter<File>, seed: u64| {
+            let mut sampler = Uniform2D::new(bounds, 5000.0, seed);
+            for _ in 0..100 {
+                let source = sampler.gen_source();
+                let destination = sampler.gen_destination(source).unwrap();
+                writer.write_route(Route {
+                    start_coord: LatLon32::new(source.lat(), source.lon()),
+                    end_coord: LatLon32::new(destination.lat(), destination.lon()),
+                    ..test_route(0)
+                }).unwrap();
+            }
+        };
+
+        let path = TempFile::new("resume_seed.routes");
+        let metadata = RunMetadata { seed: 42, ..RunMetadata::default() };
+        let mut writer = RouteCollectionWriter::new(&path, "test.osrm", "sample", metadata).unwrap();
+        write_samples(&mut writer, 42);
+        writer.finish().unwrap();
+
+        let mut writer = RouteCollectionWriter::resume(&path, |_| ()).unwrap();
+        let seed = writer.header().resume_seed();
+        assert_eq!(seed, 142);
+        write_samples(&mut writer, seed);
+        writer.finish().unwrap();
+
+        let mut od_pairs: Vec<_> = RouteCollectionReader::new(&path).unwrap()
+            .map(|r| {
+                let r = r.unwrap();
+                let (a, b) = (r.start_coord.as_point4326(), r.end_coord.as_point4326());
+                [a.lat(), a.lon(), b.lat(), b.lon()].iter().map(|v| v.to_bits()).collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(od_pairs.len(), 200);
+        od_pairs.sort();
+        od_pairs.dedup();
+        assert_eq!(od_pairs.len(), 200);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let mut writer = RouteCollectionWriter::from_writer(
+            std::io::Cursor::new(vec![]), "test.osrm", "sample", RunMetadata::default()
+        ).unwrap();
+        for i in 0..4 {
+            writer.write_route(test_route(i)).unwrap();
+        }
+        let buffer = writer.finish().unwrap().into_inner();
+
+        // Sequential reading without Seek
+        let reader = RouteCollectionReader::from_reader(&buffer[..]).unwrap();
+        assert_eq!(reader.header().number_of_routes(), 4);
+        let routes: Vec<Route> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(routes, (0..4).map(test_route).collect::<Vec<_>>());
+
+        // Random access builds the index in memory
+        let mut reader = RouteCollectionReader::from_reader(std::io::Cursor::new(&buffer)).unwrap();
+        assert_eq!(reader.len().unwrap(), 4);
+        assert_eq!(reader.get(2).unwrap(), test_route(2));
+        let routes: Vec<Route> = reader.range(0..2).unwrap().map(|r| r.unwrap()).collect();
+        assert_eq!(routes, (0..2).map(test_route).collect::<Vec<_>>());
+
+        assert!(RouteCollectionReader::from_reader(&buffer[..5]).is_err());
+        assert!(RouteCollectionReader::from_reader(&b"NOTROUTES\0\0\0"[..]).is_err());
+    }
+
+    #[test]
+    fn test_unfinished_stream() {
+        // A writer without Seek can not be finished, so the file stays unfinished.
+        let mut buffer = vec![];
+        {
+            let mut writer = RouteCollectionWriter::from_writer(
+                &mut buffer, "test.osrm", "sample", RunMetadata::default()
+            ).unwrap();
+            for i in 0..3 {
+                writer.write_route(test_route(i)).unwrap();
             }
         }
+        let partial = bincode::serialize(&test_route(3)).unwrap();
+        buffer.extend_from_slice(&partial[..partial.len() / 2]);
+
+        let reader = RouteCollectionReader::from_reader(&buffer[..]).unwrap();
+        assert_eq!(reader.header().number_of_routes(), 0);
+        let routes: Vec<Route> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(routes, (0..3).map(test_route).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_upgrade_v0_2() {
+        #[derive(Serialize)]
+        struct OldRoute {
+            start_coord: LatLon32,
+            end_coord: LatLon32,
+            node_ids: Vec<OsmNodeId>,
+            distance: f64,
+        }
+
+        let mut buffer = vec![];
+        let header = RouteCollectionHeaderV0_3 {
+            major_version: 0,
+            minor_version: 2,
+            osrm_file: "test.osrm".to_string(),
+            scenario: "sample".to_string(),
+            number_of_routes: 1,
+        };
+        bincode::serialize_into(&mut buffer, &header).unwrap();
+        let route = test_route(1);
+        bincode::serialize_into(&mut buffer, &OldRoute {
+            start_coord: route.start_coord,
+            end_coord: route.end_coord,
+            node_ids: route.node_ids,
+            distance: route.distance,
+        }).unwrap();
+
+        let reader = RouteCollectionReader::from_reader(&buffer[..]).unwrap();
+        assert_eq!(reader.header().version(), (0, 2));
+        let routes: Vec<Route> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(routes, vec![Route { duration: None, ..test_route(1) }]);
+    }
+
+    #[test]
+    fn test_upgrade_v0_3() {
+        let mut buffer = vec![];
+        buffer.write_all(MAGIC_BYTES).unwrap();
+        let header = RouteCollectionHeaderV0_3 {
+            major_version: 0,
+            minor_version: 3,
+            osrm_file: "test.osrm".to_string(),
+            scenario: "sample".to_string(),
+            number_of_routes: 1,
+        };
+        bincode::serialize_into(&mut buffer, &header).unwrap();
+        let route = test_route(1);
+        bincode::serialize_into(&mut buffer, &RouteV0_4 {
+            start_coord: route.start_coord,
+            end_coord: route.end_coord,
+            node_ids: route.node_ids,
+            distance: route.distance,
+            duration: route.duration,
+        }).unwrap();
+
+        let reader = RouteCollectionReader::from_reader(&buffer[..]).unwrap();
+        assert_eq!(reader.header().version(), (0, 3));
+        assert_eq!(reader.header().metadata, RunMetadata::default());
+        let routes: Vec<Route> = reader.map(|r| r.unwrap()).collect();
+        assert_eq!(routes, vec![test_route(1)]);
+    }
+
+    #[test]
+    fn test_input_file_checksum() {
+        let input = InputFile::from_reader("population", "abc.csv", &b"abc"[..]).unwrap();
+        assert_eq!(
+            input.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_metadata_differences() {
+        let input = |role: &str, path: &str, sha256: &str| InputFile {
+            role: role.to_string(),
+            path: path.to_string(),
+            sha256: sha256.to_string(),
+        };
+        let original = RunMetadata {
+            sampler: "weighted".to_string(),
+            max_dist: Some(5000.0),
+            input_files: vec![input("weighted", "a.csv", "01")],
+            seed: 1,
+            created: "2020-01-01T00:00:00+00:00".to_string(),
+            ..Default::default()
+        };
+        let resumed = RunMetadata {
+            seed: 2,
+            created: "2020-01-02T00:00:00+00:00".to_string(),
+            input_files: vec![input("weighted", "moved/a.csv", "01")],
+            ..original.clone()
+        };
+        assert!(original.differences(&resumed).is_empty());
+
+        let changed = RunMetadata {
+            max_dist: Some(1000.0),
+            input_files: vec![input("weighted", "a.csv", "02")],
+            ..resumed
+        };
+        let differences = original.differences(&changed);
+        assert_eq!(differences.len(), 2);
+        assert!(differences[0].starts_with("max. distance"));
+        assert!(differences[1].starts_with("input files"));
+    }
+
+    #[test]
+    fn test_compact_node_ids() {
+        let ids: Vec<OsmNodeId> = [
+            0, 1, 26_000_000, 25_999_998, 7_000_000_000, -1, i64::MAX, i64::MIN, 0,
+        ].iter().map(|&id| OsmNodeId::new(id)).collect();
+        let buf = compact_node_ids::encode(&ids);
+        assert_eq!(compact_node_ids::decode(&buf), Ok(ids));
+
+        // Nearby ids take a byte each, plus one byte for the length
+        let ids: Vec<OsmNodeId> = (0..10).map(|i| OsmNodeId::new(26_000_000 + i)).collect();
+        assert_eq!(compact_node_ids::encode(&ids).len(), 1 + 4 + 9);
+
+        assert!(compact_node_ids::decode(&[]).is_err());
+        assert!(compact_node_ids::decode(&[2, 0x80]).is_err());
+        assert!(compact_node_ids::decode(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_check_version() {
+        assert!(check_version(0, 2, false).is_ok());
+        assert!(check_version(MAJOR_VERSION, MINOR_VERSION, true).is_ok());
+        assert!(check_version(0, 3, false).is_err());
+        assert!(check_version(MAJOR_VERSION, MINOR_VERSION + 1, true).is_err());
+        assert!(check_version(MAJOR_VERSION + 1, 0, true).is_err());
+
+        let foreign = b"{\"type\": \"FeatureCollection\"}";
+        assert!(RouteCollectionReader::from_reader(&foreign[..]).is_err());
     }
 }
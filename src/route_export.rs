@@ -0,0 +1,193 @@
+//! Export the routes of a `*.routes` file as tables and vector files.
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::geometry::Geometry;
+use crate::network::Network;
+use crate::output::{self, Feature, FeatureSink};
+use crate::route::{Route, RouteCollectionReader};
+use crate::route_filter::{Filter, RouteValues};
+
+
+/// Selects the outputs of `export`. All paths are optional.
+pub struct ExportOptions<'a> {
+    /// CSV file with origin, destination, lengths and detour factor of each route
+    pub csv: Option<&'a str>,
+    /// Vector file with the path of each route
+    pub geojson: Option<&'a str>,
+    /// Vector file with the origin and destination points of each route
+    pub od_points: Option<&'a str>,
+    /// Only export routes that match
+    pub filter: Option<Filter>,
+    /// The *.osrm file for the route geometries. Defaults to the file from the header.
+    pub osrm: Option<&'a str>,
+    /// Number of decimal places of coordinates in text formats
+    pub precision: usize,
+}
+
+#[derive(Serialize)]
+struct CsvRecord {
+    index: u64,
+    origin_lat: f64,
+    origin_lon: f64,
+    destination_lat: f64,
+    destination_lon: f64,
+    distance: f64,
+    distance_bee_line: f64,
+    detour: Option<f64>,
+    duration: Option<f64>,
+    num_nodes: usize,
+}
+
+fn set_values(feature: Feature, values: &RouteValues) -> Feature {
+    let mut feature = feature
+        .with_property("distance", values.distance)
+        .with_property("distance_bee_line", values.bee_line);
+    if let Some(detour) = values.detour {
+        feature = feature.with_property("detour", detour);
+    }
+    if let Some(duration) = values.duration {
+        feature = feature.with_property("duration", duration);
+    }
+    feature.with_property("num_nodes", values.nodes as u64)
+}
+
+fn create_sink(path: &str, precision: usize) -> anyhow::Result<Box<dyn FeatureSink>> {
+    let mut sink = output::create(path)
+        .with_context(|| format!("Failed to create {:?}", path))?;
+    sink.set_precision(precision);
+    Ok(sink)
+}
+
+/// Write the routes of the given file to the outputs that are selected by `options`.
+pub fn export(input_path: &str, options: &ExportOptions) -> anyhow::Result<()> {
+    let reader = RouteCollectionReader::new(input_path)
+        .with_context(|| format!("Failed to read the routes file {:?}", input_path))?;
+
+    let net = if options.geojson.is_some() {
+        let osrm_path = options.osrm.unwrap_or(&reader.header().osrm_file).to_string();
+        println!("Read *.osrm file {:?}", osrm_path);
+        Some(Network::from_path(&osrm_path)
+            .with_context(|| format!("Failed to read *.osrm file {:?}", osrm_path))?)
+    } else {
+        None
+    };
+
+    let mut csv_writer = match options.csv {
+        Some(path) => Some(csv::Writer::from_path(path)
+            .with_context(|| format!("Failed to create {:?}", path))?),
+        None => None,
+    };
+    let mut routes_sink = options.geojson.map(|p| create_sink(p, options.precision)).transpose()?;
+    let mut points_sink = options.od_points.map(|p| create_sink(p, options.precision)).transpose()?;
+
+    let mut exported = 0;
+    for (index, route) in reader.enumerate() {
+        let route: Route = route?;
+        let values = RouteValues::new(index as u64, &route);
+        if let Some(filter) = &options.filter {
+            if !filter.matches(&values) {
+                continue;
+            }
+        }
+        exported += 1;
+
+        let origin = route.start_coord.as_point4326();
+        let destination = route.end_coord.as_point4326();
+
+        if let Some(csv_writer) = &mut csv_writer {
+            csv_writer.serialize(CsvRecord {
+                index: values.index,
+                origin_lat: origin.lat(),
+                origin_lon: origin.lon(),
+                destination_lat: destination.lat(),
+                destination_lon: destination.lon(),
+                distance: values.distance,
+                distance_bee_line: values.bee_line,
+                detour: values.detour,
+                duration: values.duration,
+                num_nodes: values.nodes,
+            })?;
+        }
+
+        if let (Some(sink), Some(net)) = (&mut routes_sink, &net) {
+            let geometry = route.geometry(net)
+                .with_context(|| format!("Route {} does not match the network", index))?;
+            sink.write_feature(&set_values(Feature::new(geometry), &values).with_id(values.index))?;
+        }
+
+        if let Some(sink) = &mut points_sink {
+            for &(role, point) in &[("origin", origin), ("destination", destination)] {
+                sink.write_feature(&Feature::new(Geometry::Point(point))
+                    .with_property("index", values.index)
+                    .with_property("role", role))?;
+            }
+        }
+    }
+
+    if let Some(mut csv_writer) = csv_writer {
+        csv_writer.flush()?;
+    }
+    if let Some(mut sink) = routes_sink {
+        sink.finish()?;
+    }
+    if let Some(mut sink) = points_sink {
+        sink.finish()?;
+    }
+    println!("Exported {} routes", exported);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::OsmNodeId;
+    use crate::route::{LatLon32, RouteCollectionWriter, RunMetadata};
+    use crate::test_util::TempFile;
+
+    #[test]
+    fn test_export_csv_and_points() {
+        let routes_path = TempFile::new("export.routes");
+        let csv_path = TempFile::new("export.csv");
+        let points_path = TempFile::new("export.geojson");
+
+        let mut writer = RouteCollectionWriter::new(
+            &routes_path, "test.osrm", "sample", RunMetadata::default()
+        ).unwrap();
+        for i in 0..4 {
+            writer.write_route(Route {
+                start_coord: LatLon32::new(52.5, 13.4),
+                end_coord: LatLon32::new(52.5, 13.41),
+                node_ids: vec![OsmNodeId::new(1), OsmNodeId::new(2)],
+                distance: 1000.0 * i as f64,
+                duration: None,
+            }).unwrap();
+        }
+        writer.finish().unwrap();
+
+        export(routes_path.to_str(), &ExportOptions {
+            csv: Some(csv_path.to_str()),
+            geojson: None,
+            od_points: Some(points_path.to_str()),
+            filter: Some(Filter::parse("detour > 1 && index != 3").unwrap()),
+            osrm: None,
+            precision: 6,
+        }).unwrap();
+
+        let mut csv_reader = csv::Reader::from_path(&csv_path).unwrap();
+        let indices: Vec<u64> = csv_reader.records()
+            .map(|r| r.unwrap()[0].parse().unwrap())
+            .collect();
+        assert_eq!(indices, vec![1, 2]);
+
+        let points: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(&points_path).unwrap()
+        ).unwrap();
+        let features = points["features"].as_array().unwrap();
+        assert_eq!(features.len(), 4);
+        assert_eq!(features[1]["properties"]["role"], "destination");
+        assert_eq!(features[1]["properties"]["index"], 1);
+    }
+}
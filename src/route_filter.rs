@@ -0,0 +1,325 @@
+//! Filter expressions to select routes, e.g. `detour > 10 && bee_line > 105`.
+//!
+//! Comparisons (`<`, `<=`, `>`, `>=`, `==`, `!=`) between numbers and the variables `index`,
+//! `distance`, `bee_line`, `detour`, `duration` and `nodes` can be combined with `&&`, `||`, `!`
+//! and parentheses. Comparisons with missing values (e.g. `duration` in old files) are false.
+
+use crate::route::Route;
+
+
+/// Values of a route that can be used in filter expressions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteValues {
+    pub index: u64,
+    /// Length of the route in meters
+    pub distance: f64,
+    /// Straight-line distance between start and end in meters
+    pub bee_line: f64,
+    /// Ratio of `distance` to `bee_line`. Not available if start and end are the same.
+    pub detour: Option<f64>,
+    /// Travel time in seconds
+    pub duration: Option<f64>,
+    /// Number of nodes
+    pub nodes: usize,
+}
+
+impl RouteValues {
+    pub fn new(index: u64, route: &Route) -> Self {
+        let bee_line = route.distance_bee_line();
+        RouteValues {
+            index,
+            distance: route.distance,
+            bee_line,
+            detour: if bee_line > 0.0 { Some(route.distance / bee_line) } else { None },
+            duration: route.duration,
+            nodes: route.node_ids.len(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Variable {
+    Index,
+    Distance,
+    BeeLine,
+    Detour,
+    Duration,
+    Nodes,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "index" => Variable::Index,
+            "distance" => Variable::Distance,
+            "bee_line" => Variable::BeeLine,
+            "detour" => Variable::Detour,
+            "duration" => Variable::Duration,
+            "nodes" => Variable::Nodes,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operand {
+    Number(f64),
+    Variable(Variable),
+}
+
+impl Operand {
+    fn value(self, values: &RouteValues) -> f64 {
+        match self {
+            Operand::Number(x) => x,
+            Operand::Variable(Variable::Index) => values.index as f64,
+            Operand::Variable(Variable::Distance) => values.distance,
+            Operand::Variable(Variable::BeeLine) => values.bee_line,
+            Operand::Variable(Variable::Detour) => values.detour.unwrap_or(f64::NAN),
+            Operand::Variable(Variable::Duration) => values.duration.unwrap_or(f64::NAN),
+            Operand::Variable(Variable::Nodes) => values.nodes as f64,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Comparison(Comparison),
+    And,
+    Or,
+    Not,
+    LeftParen,
+    RightParen,
+}
+
+fn tokenize(input: &str) -> anyhow::Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            },
+            ('<', Some('=')) => (Token::Comparison(Comparison::LessEqual), 2),
+            ('<', _) => (Token::Comparison(Comparison::Less), 1),
+            ('>', Some('=')) => (Token::Comparison(Comparison::GreaterEqual), 2),
+            ('>', _) => (Token::Comparison(Comparison::Greater), 1),
+            ('=', Some('=')) => (Token::Comparison(Comparison::Equal), 2),
+            ('!', Some('=')) => (Token::Comparison(Comparison::NotEqual), 2),
+            ('!', _) => (Token::Not, 1),
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('(', _) => (Token::LeftParen, 1),
+            (')', _) => (Token::RightParen, 1),
+            (c, _) if c.is_ascii_digit() || c == '.' || c == '-' => {
+                let mut len = 1;
+                while let Some(&c) = chars.get(i + len) {
+                    let exponent_sign = (c == '-' || c == '+') && chars[i + len - 1] == 'e';
+                    if !(c.is_ascii_digit() || c == '.' || c == 'e' || exponent_sign) {
+                        break;
+                    }
+                    len += 1;
+                }
+                let s: String = chars[i..i + len].iter().collect();
+                let x = s.parse::<f64>()
+                    .map_err(|_| anyhow::anyhow!("Invalid number {:?} in filter", s))?;
+                (Token::Number(x), len)
+            },
+            (c, _) if c.is_ascii_alphabetic() || c == '_' => {
+                let len = chars[i..].iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+                    .count();
+                (Token::Identifier(chars[i..i + len].iter().collect()), len)
+            },
+            (c, _) => anyhow::bail!("Unexpected character {:?} in filter", c),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Compare(Operand, Comparison, Operand),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+impl Expr {
+    fn matches(&self, values: &RouteValues) -> bool {
+        match self {
+            Expr::Compare(a, cmp, b) => {
+                let (a, b) = (a.value(values), b.value(values));
+                match cmp {
+                    Comparison::Less => a < b,
+                    Comparison::LessEqual => a <= b,
+                    Comparison::Greater => a > b,
+                    Comparison::GreaterEqual => a >= b,
+                    Comparison::Equal => a == b,
+                    Comparison::NotEqual => !a.is_nan() && !b.is_nan() && a != b,
+                }
+            },
+            Expr::And(a, b) => a.matches(values) && b.matches(values),
+            Expr::Or(a, b) => a.matches(values) || b.matches(values),
+            Expr::Not(a) => !a.matches(values),
+        }
+    }
+}
+
+/// A parsed filter expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser { tokens: &tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            anyhow::bail!("Unexpected {:?} in filter {:?}", token, input);
+        }
+        Ok(Filter { expr })
+    }
+
+    pub fn matches(&self, values: &RouteValues) -> bool {
+        self.expr.matches(values)
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> anyhow::Result<&'a Token> {
+        let token = self.peek()
+            .ok_or_else(|| anyhow::anyhow!("Unexpected end of filter"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> anyhow::Result<Expr> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.not()?)))
+            },
+            Some(Token::LeftParen) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                match self.next()? {
+                    Token::RightParen => Ok(expr),
+                    token => anyhow::bail!("Expected \")\" in filter, found {:?}", token),
+                }
+            },
+            _ => {
+                let a = self.operand()?;
+                let cmp = match self.next()? {
+                    Token::Comparison(cmp) => *cmp,
+                    token => anyhow::bail!("Expected a comparison in filter, found {:?}", token),
+                };
+                let b = self.operand()?;
+                Ok(Expr::Compare(a, cmp, b))
+            },
+        }
+    }
+
+    fn operand(&mut self) -> anyhow::Result<Operand> {
+        match self.next()? {
+            Token::Number(x) => Ok(Operand::Number(*x)),
+            Token::Identifier(name) => Variable::from_name(name)
+                .map(Operand::Variable)
+                .ok_or_else(|| anyhow::anyhow!(
+                    "Unknown variable {:?} in filter, expected one of index, distance, bee_line, \
+                     detour, duration or nodes",
+                    name,
+                )),
+            token => anyhow::bail!("Expected a number or a variable in filter, found {:?}", token),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(index: u64, distance: f64, bee_line: f64) -> RouteValues {
+        RouteValues {
+            index,
+            distance,
+            bee_line,
+            detour: if bee_line > 0.0 { Some(distance / bee_line) } else { None },
+            duration: None,
+            nodes: 10,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse("detour > 10 && bee_line > 105").unwrap();
+        assert!(filter.matches(&values(0, 2000.0, 150.0)));
+        assert!(!filter.matches(&values(0, 2000.0, 100.0)));
+        assert!(!filter.matches(&values(0, 0.0, 0.0)));
+
+        let filter = Filter::parse("!(index < 5 || index >= 10) && distance<=1e3 && bee_line > 1e-1")
+            .unwrap();
+        assert!(!filter.matches(&values(4, 500.0, 100.0)));
+        assert!(filter.matches(&values(5, 500.0, 100.0)));
+        assert!(!filter.matches(&values(5, 1500.0, 100.0)));
+        assert!(!filter.matches(&values(10, 500.0, 100.0)));
+
+        // Missing values never match
+        assert!(!Filter::parse("duration != 0").unwrap().matches(&values(0, 1.0, 1.0)));
+        assert!(Filter::parse("nodes == 10").unwrap().matches(&values(0, 1.0, 1.0)));
+        assert!(Filter::parse("-1 < index").unwrap().matches(&values(0, 1.0, 1.0)));
+
+        assert!(Filter::parse("distance >").is_err());
+        assert!(Filter::parse("length > 5").is_err());
+        assert!(Filter::parse("(index > 5").is_err());
+        assert!(Filter::parse("index > 5)").is_err());
+        assert!(Filter::parse("index > 5 & index < 6").is_err());
+    }
+}
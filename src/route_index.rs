@@ -0,0 +1,108 @@
+//! Sidecar files with the byte offsets of all routes in a `*.routes` file, for random access.
+
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+
+
+/// Signature at the start of each `*.routes.idx` file.
+pub const MAGIC_BYTES: &[u8; 8] = b"NORIIDX\0";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RouteIndex {
+    /// Length of the routes file when the index was created, to detect outdated indices
+    routes_len: u64,
+    /// Byte offset of each route from the start of the routes file
+    offsets: Vec<u64>,
+}
+
+impl RouteIndex {
+    pub fn new(routes_len: u64, offsets: Vec<u64>) -> Self {
+        RouteIndex {
+            routes_len,
+            offsets,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.offsets.len() as u64
+    }
+
+    pub fn offset(&self, index: u64) -> Option<u64> {
+        self.offsets.get(index as usize).copied()
+    }
+
+    /// Path of the index of the given routes file, e.g. `berlin.routes.idx`.
+    pub fn path_for<P: AsRef<Path>>(routes_path: P) -> PathBuf {
+        let mut path = OsString::from(routes_path.as_ref());
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+
+    /// Write the index next to the given routes file.
+    pub fn write_for<P: AsRef<Path>>(&self, routes_path: P) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(Self::path_for(routes_path))?);
+        writer.write_all(MAGIC_BYTES)?;
+        bincode::serialize_into(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load the index of the given routes file. Returns `None` if there is no index or if it does
+    /// not match the routes file anymore. The number of routes is only compared if it is known,
+    /// i.e. not 0 like in the header of an unfinished file.
+    pub fn load_for<P: AsRef<Path>>(routes_path: P, number_of_routes: u64)
+        -> anyhow::Result<Option<Self>>
+    {
+        let file = match File::open(Self::path_for(&routes_path)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        if reader.read_exact(&mut magic).is_err() || &magic != MAGIC_BYTES {
+            return Ok(None);
+        }
+        let index: RouteIndex = match bincode::deserialize_from(&mut reader) {
+            Ok(index) => index,
+            Err(_) => return Ok(None),
+        };
+
+        let routes_len = std::fs::metadata(&routes_path)?.len();
+        if index.routes_len != routes_len || (number_of_routes > 0 && index.len() != number_of_routes) {
+            return Ok(None);
+        }
+        Ok(Some(index))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempFile;
+
+    #[test]
+    fn test_load_for() {
+        let routes_path = TempFile::new("index.routes");
+        std::fs::write(&routes_path, [0u8; 100]).unwrap();
+        assert_eq!(RouteIndex::load_for(&routes_path, 2).unwrap(), None);
+
+        let index = RouteIndex::new(100, vec![40, 70]);
+        index.write_for(&routes_path).unwrap();
+        assert_eq!(RouteIndex::load_for(&routes_path, 2).unwrap(), Some(index.clone()));
+
+        // Unfinished routes files have no number of routes in the header
+        assert_eq!(RouteIndex::load_for(&routes_path, 0).unwrap(), Some(index));
+
+        // Outdated
+        assert_eq!(RouteIndex::load_for(&routes_path, 3).unwrap(), None);
+        std::fs::write(&routes_path, [0u8; 120]).unwrap();
+        assert_eq!(RouteIndex::load_for(&routes_path, 2).unwrap(), None);
+    }
+}
@@ -0,0 +1,488 @@
+//! Distributions of trip lengths and other properties of the routes in a `*.routes` file.
+
+use std::fmt::Write as FmtWrite;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::render::{self, Align};
+use crate::route::RouteCollectionReader;
+use crate::route_filter::{Filter, RouteValues};
+
+
+/// Quantiles that are reported in addition to minimum, maximum and mean.
+pub const QUANTILES: [f64; 8] = [0.05, 0.1, 0.25, 0.5, 0.75, 0.9, 0.95, 0.99];
+
+/// All values of one property of the routes, sorted.
+pub struct Variable {
+    pub name: &'static str,
+    pub unit: &'static str,
+    values: Vec<f64>,
+}
+
+impl Variable {
+    fn new(name: &'static str, unit: &'static str) -> Self {
+        Variable {
+            name,
+            unit,
+            values: vec![],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.values.first().copied()
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.values.last().copied()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.values.is_empty() {
+            None
+        } else {
+            Some(self.values.iter().sum::<f64>() / self.values.len() as f64)
+        }
+    }
+
+    /// Linear interpolation between the closest ranks.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let n = self.values.len();
+        if n == 0 {
+            return None;
+        }
+        let rank = q.clamp(0.0, 1.0) * (n - 1) as f64;
+        let lower = rank.floor() as usize;
+        let upper = rank.ceil() as usize;
+        let t = rank - lower as f64;
+        Some(self.values[lower] * (1.0 - t) + self.values[upper] * t)
+    }
+
+    /// Count the values in `bins` bins of equal width between the minimum and the 99th
+    /// percentile. Larger values are counted as overflow, so that a few outliers do not squeeze
+    /// the rest of the distribution into one bin.
+    pub fn histogram(&self, bins: usize) -> Histogram {
+        let start = self.min().unwrap_or(0.0);
+        let mut end = self.quantile(0.99).unwrap_or(1.0);
+        if end <= start {
+            end = start + 1.0;
+        }
+        let bin_width = (end - start) / bins as f64;
+        let mut counts = vec![0u64; bins];
+        let mut overflow = 0;
+        for &x in &self.values {
+            if x > end {
+                overflow += 1;
+            } else {
+                let bin = (((x - start) / bin_width) as usize).min(bins - 1);
+                counts[bin] += 1;
+            }
+        }
+        Histogram {
+            name: self.name,
+            unit: self.unit,
+            start,
+            bin_width,
+            counts,
+            overflow,
+        }
+    }
+}
+
+pub struct Histogram {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub start: f64,
+    pub bin_width: f64,
+    pub counts: Vec<u64>,
+    /// Number of values above the last bin
+    pub overflow: u64,
+}
+
+impl Histogram {
+    fn bin_start(&self, bin: usize) -> f64 {
+        self.start + bin as f64 * self.bin_width
+    }
+
+    fn end(&self) -> f64 {
+        self.bin_start(self.counts.len())
+    }
+
+    fn title(&self) -> String {
+        if self.unit.is_empty() {
+            self.name.to_string()
+        } else {
+            format!("{} [{}]", self.name, self.unit)
+        }
+    }
+
+    /// Write a bar chart. The format is selected by the file extension (*.svg or *.png).
+    pub fn write_chart<P: AsRef<Path>>(&self, path: P, width: u32, height: u32) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        if ext.as_deref() == Some("svg") {
+            std::fs::write(path, self.to_svg(width, height))
+                .with_context(|| format!("Failed to write SVG file {:?}", path))
+        } else {
+            self.render_image(width, height).pixmap.save_png(path)
+                .with_context(|| format!("Failed to write PNG file {:?}", path))
+        }
+    }
+
+    /// Rectangles of the bars in pixel coordinates (left, top, right, bottom) inside the plot
+    /// area with the given margins.
+    fn bars(&self, width: u32, height: u32, margin: Margin) -> Vec<(f32, f32, f32, f32)> {
+        let max_count = self.counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+        let plot_width = width as f32 - margin.left - margin.right;
+        let plot_height = height as f32 - margin.top - margin.bottom;
+        let bar_width = plot_width / self.counts.len().max(1) as f32;
+        let bottom = height as f32 - margin.bottom;
+        self.counts.iter().enumerate().map(|(i, &count)| {
+            let left = margin.left + i as f32 * bar_width;
+            (left, bottom - plot_height * count as f32 / max_count, left + bar_width, bottom)
+        }).collect()
+    }
+
+    /// Render a bar chart with the same labels as `to_svg`, using a built-in bitmap font.
+    pub fn render_image(&self, width: u32, height: u32) -> tiny_skia::Canvas {
+        let mut canvas = render::new_canvas(width, height);
+        let margin = Margin::labeled();
+        let bottom = height as f32 - margin.bottom;
+        let max_count = self.counts.iter().copied().max().unwrap_or(0);
+
+        let mut paint = tiny_skia::Paint::default();
+        paint.set_color(tiny_skia::Color::from_rgba8(70, 130, 180, 255));
+        for (left, top, right, bottom) in self.bars(width, height, margin) {
+            // Leave a gap between bars, but keep thin bars visible.
+            let right = (right - 1.0).max(left + 1.0);
+            if let Some(rect) = tiny_skia::Rect::from_ltrb(left, top.min(bottom - 1.0), right, bottom) {
+                canvas.fill_rect(rect, &paint);
+            }
+        }
+
+        let mut pb = tiny_skia::PathBuilder::new();
+        pb.move_to(margin.left, margin.top);
+        pb.line_to(margin.left, bottom);
+        pb.line_to(width as f32 - margin.right, bottom);
+        if let Some(path) = pb.finish() {
+            paint.set_color(tiny_skia::Color::BLACK);
+            canvas.stroke_path(&path, &paint, &tiny_skia::Stroke::default());
+        }
+
+        let black = tiny_skia::Color::BLACK;
+        render::draw_text(&mut canvas, &self.title(), width as f32 / 2.0, 8.0, 2.0, Align::Center, black);
+        // Five labels on the x axis, the maximum count on the y axis.
+        let plot_width = width as f32 - margin.left - margin.right;
+        for i in 0..=4 {
+            let x = margin.left + plot_width * i as f32 / 4.0;
+            let value = self.start + (self.end() - self.start) * i as f64 / 4.0;
+            render::draw_text(&mut canvas, &format_label(value), x, bottom + 8.0, 1.0, Align::Center, black);
+        }
+        let label = max_count.to_string();
+        render::draw_text(&mut canvas, &label, margin.left - 6.0, margin.top, 1.0, Align::Right, black);
+        if self.overflow > 0 {
+            let label = format!("{} more above {}", self.overflow, format_label(self.end()));
+            let right = width as f32 - margin.right;
+            render::draw_text(&mut canvas, &label, right, margin.top - 10.0, 1.0, Align::Right, black);
+        }
+        canvas
+    }
+
+    pub fn to_svg(&self, width: u32, height: u32) -> String {
+        let margin = Margin::labeled();
+        let bottom = height as f32 - margin.bottom;
+        let max_count = self.counts.iter().copied().max().unwrap_or(0);
+
+        let mut svg = String::new();
+        // Writing to a String cannot fail.
+        let _ = writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" \
+             viewBox=\"0 0 {w} {h}\" font-family=\"sans-serif\" font-size=\"12\">",
+            w = width,
+            h = height,
+        );
+        let _ = writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+        let _ = writeln!(
+            svg,
+            "<text x=\"{}\" y=\"20\" text-anchor=\"middle\" font-size=\"14\">{}</text>",
+            width / 2,
+            self.title(),
+        );
+        for (i, (left, top, right, bottom)) in self.bars(width, height, margin).into_iter().enumerate() {
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"steelblue\">\
+                 <title>{:.1} to {:.1}: {}</title></rect>",
+                left,
+                top,
+                (right - left - 1.0).max(0.5),
+                bottom - top,
+                self.bin_start(i),
+                self.bin_start(i + 1),
+                self.counts[i],
+            );
+        }
+        let _ = writeln!(
+            svg,
+            "<path d=\"M{l} {t} V{b} H{r}\" stroke=\"black\" fill=\"none\"/>",
+            l = margin.left,
+            t = margin.top,
+            b = bottom,
+            r = width as f32 - margin.right,
+        );
+
+        // Five labels on the x axis, the maximum count on the y axis.
+        let plot_width = width as f32 - margin.left - margin.right;
+        for i in 0..=4 {
+            let x = margin.left + plot_width * i as f32 / 4.0;
+            let value = self.start + (self.end() - self.start) * i as f64 / 4.0;
+            let _ = writeln!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>",
+                x,
+                bottom + 18.0,
+                format_label(value),
+            );
+        }
+        let _ = writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            margin.left - 6.0,
+            margin.top + 4.0,
+            max_count,
+        );
+        if self.overflow > 0 {
+            let _ = writeln!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{} more above {}</text>",
+                width as f32 - margin.right,
+                margin.top + 4.0,
+                self.overflow,
+                format_label(self.end()),
+            );
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Margin {
+    left: f32,
+    right: f32,
+    top: f32,
+    bottom: f32,
+}
+
+impl Margin {
+    fn labeled() -> Self {
+        Margin { left: 60.0, right: 20.0, top: 35.0, bottom: 30.0 }
+    }
+}
+
+fn format_label(value: f64) -> String {
+    if value.abs() >= 100.0 {
+        format!("{:.0}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+#[derive(Serialize)]
+struct QuantileRecord<'a> {
+    variable: &'a str,
+    count: usize,
+    mean: Option<f64>,
+    min: Option<f64>,
+    p05: Option<f64>,
+    p10: Option<f64>,
+    p25: Option<f64>,
+    p50: Option<f64>,
+    p75: Option<f64>,
+    p90: Option<f64>,
+    p95: Option<f64>,
+    p99: Option<f64>,
+    max: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct HistogramRecord<'a> {
+    variable: &'a str,
+    bin_start: f64,
+    bin_end: f64,
+    count: u64,
+}
+
+/// Network distance, bee-line distance, detour factor and number of nodes of all routes.
+pub struct TripStats {
+    pub variables: Vec<Variable>,
+}
+
+impl TripStats {
+    pub fn from_routes<P: AsRef<Path>>(path: P, filter: Option<&Filter>) -> anyhow::Result<Self> {
+        let reader = RouteCollectionReader::new(&path)
+            .with_context(|| format!("Failed to read the routes file {:?}", path.as_ref()))?;
+
+        let mut distance = Variable::new("distance", "m");
+        let mut bee_line = Variable::new("bee_line", "m");
+        let mut detour = Variable::new("detour", "");
+        let mut nodes = Variable::new("nodes", "");
+        for (index, route) in reader.enumerate() {
+            let values = RouteValues::new(index as u64, &route?);
+            if let Some(filter) = filter {
+                if !filter.matches(&values) {
+                    continue;
+                }
+            }
+            distance.values.push(values.distance);
+            bee_line.values.push(values.bee_line);
+            if let Some(d) = values.detour {
+                detour.values.push(d);
+            }
+            nodes.values.push(values.nodes as f64);
+        }
+
+        let mut variables = vec![distance, bee_line, detour, nodes];
+        for var in &mut variables {
+            var.values.sort_by(|a, b| a.total_cmp(b));
+        }
+        Ok(TripStats { variables })
+    }
+
+    pub fn print_summary(&self) {
+        println!(
+            "{:<10} {:>9} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "", "count", "mean", "min", "p25", "median", "p75", "max",
+        );
+        let fmt = |x: Option<f64>| x.map_or("-".to_string(), |x| format!("{:.2}", x));
+        for var in &self.variables {
+            println!(
+                "{:<10} {:>9} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+                var.name,
+                var.len(),
+                fmt(var.mean()),
+                fmt(var.min()),
+                fmt(var.quantile(0.25)),
+                fmt(var.quantile(0.5)),
+                fmt(var.quantile(0.75)),
+                fmt(var.max()),
+            );
+        }
+    }
+
+    pub fn write_quantiles<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let mut writer = csv::Writer::from_path(path)?;
+        for var in &self.variables {
+            let q: Vec<Option<f64>> = QUANTILES.iter().map(|&q| var.quantile(q)).collect();
+            writer.serialize(QuantileRecord {
+                variable: var.name,
+                count: var.len(),
+                mean: var.mean(),
+                min: var.min(),
+                p05: q[0],
+                p10: q[1],
+                p25: q[2],
+                p50: q[3],
+                p75: q[4],
+                p90: q[5],
+                p95: q[6],
+                p99: q[7],
+                max: var.max(),
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write all histograms to one CSV file. The overflow of each histogram is written as a bin
+    /// that ends at infinity.
+    pub fn write_histograms<P: AsRef<Path>>(&self, path: P, bins: usize) -> anyhow::Result<()> {
+        let mut writer = csv::Writer::from_path(path)?;
+        for var in &self.variables {
+            let hist = var.histogram(bins);
+            for (i, &count) in hist.counts.iter().enumerate() {
+                writer.serialize(HistogramRecord {
+                    variable: var.name,
+                    bin_start: hist.bin_start(i),
+                    bin_end: hist.bin_start(i + 1),
+                    count,
+                })?;
+            }
+            writer.serialize(HistogramRecord {
+                variable: var.name,
+                bin_start: hist.end(),
+                bin_end: f64::INFINITY,
+                count: hist.overflow,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write one chart per variable. `pattern` contains "{}", which is replaced by the name of
+    /// the variable.
+    pub fn write_charts(&self, pattern: &str, bins: usize) -> anyhow::Result<()> {
+        if !pattern.contains("{}") {
+            anyhow::bail!("The chart path {:?} needs to contain \"{{}}\"", pattern);
+        }
+        for var in &self.variables {
+            var.histogram(bins).write_chart(pattern.replace("{}", var.name), 800, 400)?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(values: &[f64]) -> Variable {
+        let mut var = Variable::new("test", "");
+        var.values = values.to_vec();
+        var
+    }
+
+    #[test]
+    fn test_quantile() {
+        let var = variable(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(var.quantile(0.0), Some(1.0));
+        assert_eq!(var.quantile(0.5), Some(3.0));
+        assert_eq!(var.quantile(0.625), Some(3.5));
+        assert_eq!(var.quantile(1.0), Some(5.0));
+        assert_eq!(var.mean(), Some(3.0));
+        assert_eq!(variable(&[]).quantile(0.5), None);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut values: Vec<f64> = (0..100).map(|i| i as f64).collect();
+        values.push(1000.0);
+        let hist = variable(&values).histogram(10);
+        assert_eq!(hist.start, 0.0);
+        assert_eq!(hist.counts.iter().sum::<u64>() + hist.overflow, 101);
+        assert_eq!(hist.overflow, 1);
+        assert_eq!(hist.counts[0], 10);
+
+        let svg = hist.to_svg(400, 200);
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<rect").count(), 11);
+
+        // The PNG chart has a title above the plot area
+        let canvas = hist.render_image(400, 200);
+        let title_pixels = canvas.pixmap.pixels()[..400 * 30].iter()
+            .filter(|p| p.red() < 128)
+            .count();
+        assert!(title_pixels > 0);
+
+        // All values are the same
+        let hist = variable(&[2.0, 2.0]).histogram(4);
+        assert_eq!(hist.counts, vec![2, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,233 @@
+//! Combine and divide `*.routes` files.
+
+use std::path::Path;
+
+use anyhow::Context;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use crate::route::{RouteCollectionReader, RouteCollectionWriter, RunMetadata};
+
+
+/// Compare paths by their canonical form if both exist.
+pub fn same_file<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> bool {
+    match (a.as_ref().canonicalize(), b.as_ref().canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a.as_ref() == b.as_ref(),
+    }
+}
+
+/// Make sure that writing the output does not overwrite one of the inputs before it is read.
+fn check_output(output_path: &str, input_paths: &[&str]) -> anyhow::Result<()> {
+    if let Some(input_path) = input_paths.iter().find(|input| same_file(output_path, input)) {
+        anyhow::bail!("The output file {:?} would overwrite the input file {:?}", output_path, input_path);
+    }
+    Ok(())
+}
+
+fn open(path: &str) -> anyhow::Result<RouteCollectionReader<std::fs::File>> {
+    RouteCollectionReader::new(path)
+        .with_context(|| format!("Failed to read the routes file {:?}", path))
+}
+
+/// Write all routes of the input files into one file. All inputs need to be sampled on the same
+/// *.osrm file with the same OSRM profile.
+pub fn merge(input_paths: &[&str], output_path: &str) -> anyhow::Result<()> {
+    check_output(output_path, input_paths)?;
+    let mut readers = input_paths.iter()
+        .map(|path| open(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let first = readers.first()
+        .ok_or_else(|| anyhow::anyhow!("No input files to merge"))?
+        .header();
+
+    for (path, reader) in input_paths.iter().zip(&readers).skip(1) {
+        let header = reader.header();
+        if !same_file(&header.osrm_file, &first.osrm_file) {
+            anyhow::bail!(
+                "Cannot merge routes of different networks: {:?} uses {:?}, but {:?} uses {:?}",
+                input_paths[0],
+                first.osrm_file,
+                path,
+                header.osrm_file,
+            );
+        }
+        let (a, b) = (&first.metadata.osrm_profile, &header.metadata.osrm_profile);
+        // Files before version 0.4 have no profile.
+        if !a.is_empty() && !b.is_empty() && a != b {
+            anyhow::bail!(
+                "Cannot merge routes of different OSRM profiles: {:?} uses {:?}, but {:?} uses {:?}",
+                input_paths[0],
+                a,
+                path,
+                b,
+            );
+        }
+    }
+
+    let scenario = if readers.iter().all(|r| r.header().scenario() == first.scenario()) {
+        first.scenario().to_string()
+    } else {
+        "merged".to_string()
+    };
+    let parts = readers.iter().map(|r| r.header().metadata.clone()).collect();
+    let mut writer = RouteCollectionWriter::new(
+        output_path,
+        first.osrm_file.clone(),
+        scenario,
+        RunMetadata::derived("merge", parts),
+    )?;
+
+    for (path, reader) in input_paths.iter().zip(&mut readers) {
+        let mut n = 0;
+        for route in reader {
+            writer.write_route(route.with_context(|| format!("Failed to read a route of {:?}", path))?)?;
+            n += 1;
+        }
+        println!("Merged {} routes from {:?}", n, path);
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Split a file into `parts` files with consecutive routes of about the same size. The output
+/// paths are created by replacing "{}" in `output_pattern` with the number of the part.
+pub fn split(input_path: &str, parts: u64, output_pattern: &str) -> anyhow::Result<()> {
+    if !output_pattern.contains("{}") {
+        anyhow::bail!("The output path {:?} needs to contain \"{{}}\"", output_pattern);
+    }
+    if parts == 0 {
+        anyhow::bail!("Cannot split into zero parts");
+    }
+    let output_paths: Vec<String> = (1..=parts)
+        .map(|part| output_pattern.replace("{}", &part.to_string()))
+        .collect();
+    for output_path in &output_paths {
+        check_output(output_path, &[input_path])?;
+    }
+    let mut reader = open(input_path)?;
+    let len = reader.len()?;
+    let header = reader.header();
+    let (osrm_file, scenario, metadata) = (
+        header.osrm_file.clone(),
+        header.scenario().to_string(),
+        header.metadata.clone(),
+    );
+
+    for (part, output_path) in (0..parts).zip(&output_paths) {
+        let range = (len * part / parts)..(len * (part + 1) / parts);
+        let mut writer = RouteCollectionWriter::new(
+            output_path,
+            osrm_file.clone(),
+            scenario.clone(),
+            RunMetadata::derived(
+                format!("split, part {} of {} (routes {}..{})", part + 1, parts, range.start, range.end),
+                vec![metadata.clone()],
+            ),
+        )?;
+        let n = range.end - range.start;
+        for route in reader.range(range)? {
+            writer.write_route(route?)?;
+        }
+        writer.finish()?;
+        println!("Wrote {} routes to {:?}", n, output_path);
+    }
+    Ok(())
+}
+
+/// Keep each route with the probability `fraction`.
+pub fn subsample(input_path: &str, output_path: &str, fraction: f64, seed: u64)
+    -> anyhow::Result<()>
+{
+    if !(0.0..=1.0).contains(&fraction) {
+        anyhow::bail!("The fraction needs to be between 0 and 1, not {}", fraction);
+    }
+    check_output(output_path, &[input_path])?;
+    let reader = open(input_path)?;
+    let header = reader.header();
+    let mut writer = RouteCollectionWriter::new(
+        output_path,
+        header.osrm_file.clone(),
+        header.scenario().to_string(),
+        RunMetadata::derived(
+            format!("sample, fraction {} (seed {})", fraction, seed),
+            vec![header.metadata.clone()],
+        ),
+    )?;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let (mut total, mut kept) = (0, 0);
+    for route in reader {
+        let route = route?;
+        total += 1;
+        if rng.gen_bool(fraction) {
+            writer.write_route(route)?;
+            kept += 1;
+        }
+    }
+    writer.finish()?;
+    println!("Kept {} of {} routes", kept, total);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{test_route, TempFile};
+
+    fn write(path: &TempFile, osrm_file: &str, seed: u64, routes: std::ops::Range<i64>) {
+        let metadata = RunMetadata { seed, sampler: "uniform2d".to_string(), ..Default::default() };
+        let mut writer = RouteCollectionWriter::new(path, osrm_file, "sample", metadata).unwrap();
+        for i in routes {
+            writer.write_route(test_route(i)).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    /// The first node id of each route, which is the number passed to `test_route`
+    fn ids(path: &str) -> Vec<i64> {
+        RouteCollectionReader::new(path).unwrap()
+            .map(|r| r.unwrap().node_ids[0].as_i64())
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_split_subsample() {
+        let (a, b, c) = (TempFile::new("a.routes"), TempFile::new("b.routes"), TempFile::new("c.routes"));
+        write(&a, "test.osrm", 1, 0..3);
+        write(&b, "test.osrm", 2, 3..5);
+        write(&c, "other.osrm", 3, 5..6);
+
+        let merged = TempFile::new("merged.routes");
+        merge(&[a.to_str(), b.to_str()], merged.to_str()).unwrap();
+        assert_eq!(ids(merged.to_str()), vec![0, 1, 2, 3, 4]);
+        let header = RouteCollectionReader::new(&merged).unwrap().header().metadata.clone();
+        assert_eq!(header.operation, "merge");
+        assert_eq!(header.sampler, "uniform2d");
+        assert_eq!(header.seed, 0);
+        assert_eq!(header.parts.len(), 2);
+        let invalid = TempFile::new("invalid.routes");
+        assert!(merge(&[a.to_str(), c.to_str()], invalid.to_str()).is_err());
+        // Outputs must not overwrite inputs
+        assert!(merge(&[a.to_str(), b.to_str()], b.to_str()).is_err());
+        assert_eq!(ids(b.to_str()), vec![3, 4]);
+
+        let (part1, part2) = (TempFile::new("part_1.routes"), TempFile::new("part_2.routes"));
+        let pattern = part1.to_str().replace("part_1", "part_{}");
+        split(merged.to_str(), 2, &pattern).unwrap();
+        assert_eq!(ids(part1.to_str()), vec![0, 1]);
+        assert_eq!(ids(part2.to_str()), vec![2, 3, 4]);
+        assert!(split(part1.to_str(), 2, &pattern).is_err());
+        assert_eq!(ids(part1.to_str()), vec![0, 1]);
+
+        let all = TempFile::new("all.routes");
+        subsample(merged.to_str(), all.to_str(), 1.0, 0).unwrap();
+        assert_eq!(ids(all.to_str()), ids(merged.to_str()));
+        let none = TempFile::new("none.routes");
+        subsample(merged.to_str(), none.to_str(), 0.0, 0).unwrap();
+        assert!(ids(none.to_str()).is_empty());
+        assert!(subsample(a.to_str(), a.to_str(), 0.5, 0).is_err());
+        assert_eq!(ids(a.to_str()), vec![0, 1, 2]);
+    }
+}
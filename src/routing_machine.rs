@@ -3,20 +3,39 @@ use serde::de::Deserialize;
 
 use crate::route::{LatLon32, Route};
 
+/// Address of the local osrm-routed server
+const DEFAULT_URL: &str = "http://127.0.0.1:5000";
+
+/// Profile that is used to find routes. osrm-routed serves whatever profile the *.osrm file was
+/// created with, regardless of this name.
+const DEFAULT_PROFILE: &str = "driving";
+
 pub struct RoutingMachine {
     client: reqwest::blocking::Client,
+    url: String,
+    profile: String,
 }
 
 impl RoutingMachine {
     pub fn new() -> Self {
         RoutingMachine {
             client: reqwest::blocking::Client::new(),
+            url: DEFAULT_URL.to_string(),
+            profile: DEFAULT_PROFILE.to_string(),
         }
     }
 
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
     pub fn test_connection(&self) -> anyhow::Result<()> {
         let resp = self.client.get(
-            "http://127.0.0.1:5000/route/v1/nearest/0.0,0.0;0.0,0.0"
+            &format!("{}/route/v1/nearest/0.0,0.0;0.0,0.0", self.url)
             )
             .send()?
             .text()?;
@@ -31,7 +50,7 @@ impl RoutingMachine {
 
     pub fn find_route(&self, a: Point4326, b: Point4326) -> anyhow::Result<Route> {
         let resp = self.client.get(
-            &format!("http://127.0.0.1:5000/route/v1/driving/{},{};{},{}", a.lon(), a.lat(), b.lon(), b.lat()))
+            &format!("{}/route/v1/{}/{},{};{},{}", self.url, self.profile, a.lon(), a.lat(), b.lon(), b.lat()))
             .query(&[("annotations", "nodes")])
             .send()?
             .text()?;
@@ -42,12 +61,14 @@ impl RoutingMachine {
         let distance = json_value["routes"][0]["distance"]
             .as_f64()
             .ok_or_else(|| anyhow::anyhow!("Route has no 'distance' field"))?;
+        let duration = json_value["routes"][0]["duration"].as_f64();
 
         let route = Route {
             start_coord: LatLon32::new(a.lat(), a.lon()),
             end_coord: LatLon32::new(b.lat(), b.lon()),
             node_ids,
             distance,
+            duration,
         };
 
         Ok(route)
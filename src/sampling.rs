@@ -2,28 +2,37 @@ use std::path::Path;
 
 use geomatic::{laea, Point4326, Point3035};
 use rand::prelude::*;
+use rand::rngs::StdRng;
 
 use crate::bounding_box::BoundingBox;
 use crate::density::DensityClusters;
+use crate::trip_length::TripLength;
 
 
 pub trait Sampling {
     fn gen_source(&mut self) -> Point4326;
     fn gen_destination(&mut self, source: Point4326) -> Option<Point4326>;
+    /// Change the maximum distance between source and destination points in meters.
+    fn set_max_dist(&mut self, max_dist: f64);
+    /// Select destinations so that the distances follow the given distribution, up to the
+    /// maximum distance.
+    fn set_trip_length(&mut self, trip_length: TripLength);
 }
 
 pub struct Uniform2D {
-    rng: rand::rngs::ThreadRng,
+    rng: StdRng,
     bounds: BoundingBox,
     max_dist: f64,
+    trip_length: Option<TripLength>,
 }
 
 impl Uniform2D {
-    pub fn new(bounds: BoundingBox, max_dist: f64) -> Self {
+    pub fn new(bounds: BoundingBox, max_dist: f64, seed: u64) -> Self {
         Uniform2D {
-            rng: rand::thread_rng(),
+            rng: StdRng::seed_from_u64(seed),
             bounds,
             max_dist,
+            trip_length: None,
         }
     }
 
@@ -48,28 +57,45 @@ impl Sampling for Uniform2D {
     }
 
     fn gen_destination(&mut self, source: Point4326) -> Option<Point4326> {
-        let delta = self.sample_circle(self.max_dist);
+        let delta = match &self.trip_length {
+            Some(trip_length) => {
+                let r = trip_length.sample(&mut self.rng, self.max_dist);
+                let angle = (2.0 * std::f64::consts::PI) * self.rng.gen::<f64>();
+                (r * angle.cos(), r * angle.sin())
+            },
+            None => self.sample_circle(self.max_dist),
+        };
         let p = laea::forward(source);
         let p = Point3035::new(p.coords.0 + delta.0, p.coords.1 + delta.1);
         Some(laea::backward(p))
     }
+
+    fn set_max_dist(&mut self, max_dist: f64) {
+        self.max_dist = max_dist;
+    }
+
+    fn set_trip_length(&mut self, trip_length: TripLength) {
+        self.trip_length = Some(trip_length);
+    }
 }
 
 
 pub struct Weighted {
-    rng: rand::rngs::ThreadRng,
+    rng: StdRng,
     density: DensityClusters,
     max_dist: f64,
+    trip_length: Option<TripLength>,
 }
 
 impl Weighted {
-    pub fn from_csv<P: AsRef<Path>>(path: P, bounds: Option<BoundingBox>, max_dist: f64)
+    pub fn from_csv<P: AsRef<Path>>(path: P, bounds: Option<BoundingBox>, max_dist: f64, seed: u64)
         -> anyhow::Result<Self>
     {
         Ok(Weighted {
-            rng: rand::thread_rng(),
+            rng: StdRng::seed_from_u64(seed),
             density: DensityClusters::from_csv(path, bounds)?,
             max_dist,
+            trip_length: None,
         })
     }
 }
@@ -80,31 +106,51 @@ impl Sampling for Weighted {
     }
 
     fn gen_destination(&mut self, source: Point4326) -> Option<Point4326> {
-        self.density.sample_point_within(&mut self.rng, source, self.max_dist)
+        match &self.trip_length {
+            Some(trip_length) => self.density.sample_point_within_weighted(
+                &mut self.rng, source, self.max_dist, |d| trip_length.area_weight(d)
+            ),
+            None => self.density.sample_point_within(&mut self.rng, source, self.max_dist),
+        }
+    }
+
+    fn set_max_dist(&mut self, max_dist: f64) {
+        self.max_dist = max_dist;
+    }
+
+    fn set_trip_length(&mut self, trip_length: TripLength) {
+        self.trip_length = Some(trip_length);
     }
 }
 
 
 pub struct Complex {
-    rng: rand::rngs::ThreadRng,
+    rng: StdRng,
     /// maximum distance in meters between source and destination points.
     max_dist: f64,
     density_population: DensityClusters,
     density_poi: DensityClusters,
+    trip_length: Option<TripLength>,
 }
 
 impl Complex {
-    pub fn from_csv<P, Q>(population_csv: P, poi_csv: Q, bounds: Option<BoundingBox>, max_dist: f64)
-        -> anyhow::Result<Self>
+    pub fn from_csv<P, Q>(
+        population_csv: P,
+        poi_csv: Q,
+        bounds: Option<BoundingBox>,
+        max_dist: f64,
+        seed: u64,
+    ) -> anyhow::Result<Self>
         where
             P: AsRef<Path>,
             Q: AsRef<Path>,
     {
         Ok(Complex {
-            rng: rand::thread_rng(),
+            rng: StdRng::seed_from_u64(seed),
             max_dist,
             density_population: DensityClusters::from_csv(population_csv, bounds)?,
             density_poi: DensityClusters::from_csv(poi_csv, bounds)?,
+            trip_length: None,
         })
     }
 }
@@ -119,10 +165,24 @@ impl Sampling for Complex {
     }
 
     fn gen_destination(&mut self, source: Point4326) -> Option<Point4326> {
-        if self.rng.gen::<bool>() {
-            self.density_population.sample_point_within(&mut self.rng, source, self.max_dist)
+        let density = if self.rng.gen::<bool>() {
+            &self.density_population
         } else {
-            self.density_poi.sample_point_within(&mut self.rng, source, self.max_dist)
+            &self.density_poi
+        };
+        match &self.trip_length {
+            Some(trip_length) => density.sample_point_within_weighted(
+                &mut self.rng, source, self.max_dist, |d| trip_length.area_weight(d)
+            ),
+            None => density.sample_point_within(&mut self.rng, source, self.max_dist),
         }
     }
+
+    fn set_max_dist(&mut self, max_dist: f64) {
+        self.max_dist = max_dist;
+    }
+
+    fn set_trip_length(&mut self, trip_length: TripLength) {
+        self.trip_length = Some(trip_length);
+    }
 }
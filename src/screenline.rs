@@ -0,0 +1,392 @@
+//! Count route crossings of screenlines and cordons, e.g. to compare with manual traffic counts.
+//!
+//! Screenlines are LineString features, cordons are Polygon features. A crossing is inbound if
+//! the route crosses from the right to the left side of a screenline (in digitizing direction) or
+//! enters a cordon, and outbound otherwise.
+
+use std::path::Path;
+
+use anyhow::Context;
+use geomatic::{laea, Point3035, Point4326};
+use serde::Serialize;
+
+use crate::geojson_reader::FeatureReader;
+use crate::geometry::Geometry;
+use crate::network::{Network, OsmNodeId};
+use crate::route::RouteCollectionReader;
+use crate::route_filter::{Filter, RouteValues};
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Screenline,
+    Cordon,
+}
+
+/// A screenline or cordon together with the crossings that have been counted so far.
+pub struct Screenline {
+    pub name: String,
+    pub kind: Kind,
+    /// Segments in EPSG:3035. For cordons, the inside is on the left of each segment.
+    segments: Vec<(Point3035, Point3035)>,
+    /// Bounding box of all segments as (min_x, min_y, max_x, max_y)
+    bounds: (f64, f64, f64, f64),
+    pub inbound: u64,
+    pub outbound: u64,
+    /// Number of routes with at least one crossing
+    pub routes: u64,
+    /// Network edges that cross the line
+    pub edges: Vec<(OsmNodeId, OsmNodeId)>,
+}
+
+#[derive(Serialize)]
+struct CountRecord<'a> {
+    name: &'a str,
+    kind: Kind,
+    inbound: u64,
+    outbound: u64,
+    total: u64,
+    routes: u64,
+    edges: usize,
+}
+
+#[derive(Serialize)]
+struct EdgeRecord<'a> {
+    name: &'a str,
+    osm_source: i64,
+    osm_target: i64,
+}
+
+fn cross(o: Point3035, a: Point3035, b: Point3035) -> f64 {
+    (a.coords.0 - o.coords.0) * (b.coords.1 - o.coords.1)
+        - (a.coords.1 - o.coords.1) * (b.coords.0 - o.coords.0)
+}
+
+/// Twice the signed area of a closed ring, positive for counterclockwise rings.
+fn signed_area(ring: &[Point3035]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0].coords.0 * w[1].coords.1 - w[1].coords.0 * w[0].coords.1)
+        .sum()
+}
+
+fn project(points: &[Point4326]) -> Vec<Point3035> {
+    points.iter().map(|&p| laea::forward(p)).collect()
+}
+
+impl Screenline {
+    fn new(name: String, kind: Kind, segments: Vec<(Point3035, Point3035)>) -> Self {
+        let mut bounds = (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for &(a, b) in &segments {
+            for p in &[a, b] {
+                bounds.0 = bounds.0.min(p.coords.0);
+                bounds.1 = bounds.1.min(p.coords.1);
+                bounds.2 = bounds.2.max(p.coords.0);
+                bounds.3 = bounds.3.max(p.coords.1);
+            }
+        }
+        Screenline {
+            name,
+            kind,
+            segments,
+            bounds,
+            inbound: 0,
+            outbound: 0,
+            routes: 0,
+            edges: vec![],
+        }
+    }
+
+    /// A screenline that follows the given points.
+    pub fn from_line(name: String, points: &[Point3035]) -> Self {
+        let segments = points.windows(2).map(|w| (w[0], w[1])).collect();
+        Self::new(name, Kind::Screenline, segments)
+    }
+
+    /// A cordon around the given polygons. The first ring of each polygon is the exterior ring,
+    /// all following rings are holes.
+    pub fn from_polygons(name: String, polygons: &[Vec<Vec<Point3035>>]) -> Self {
+        let mut segments = vec![];
+        for polygon in polygons {
+            for (i, ring) in polygon.iter().enumerate() {
+                let mut ring = ring.clone();
+                // Exterior rings counterclockwise and holes clockwise, so that the inside is
+                // always on the left.
+                if (i == 0) != (signed_area(&ring) > 0.0) {
+                    ring.reverse();
+                }
+                segments.extend(ring.windows(2).map(|w| (w[0], w[1])));
+            }
+        }
+        Self::new(name, Kind::Cordon, segments)
+    }
+
+    /// Read all LineString, MultiLineString, Polygon and MultiPolygon features of a GeoJSON file.
+    /// The name is taken from the property "name" if present.
+    pub fn read_all<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<Self>> {
+        let mut lines = vec![];
+        for (index, feature) in FeatureReader::from_path(&path)?.enumerate() {
+            let feature = feature?;
+            let name = feature.properties.as_ref()
+                .and_then(|p| p.get("name"))
+                .map(|name| match name.as_str() {
+                    Some(s) => s.to_string(),
+                    None => name.to_string(),
+                })
+                .unwrap_or_else(|| format!("{}", index));
+            let line = match feature.geometry.and_then(|g| Geometry::from_geojson(&g.value)) {
+                Some(Geometry::LineString(line)) => Self::from_line(name, &project(&line)),
+                Some(Geometry::MultiLineString(lines)) => {
+                    let segments = lines.iter()
+                        .flat_map(|line| {
+                            let points = project(line);
+                            points.windows(2).map(|w| (w[0], w[1])).collect::<Vec<_>>()
+                        })
+                        .collect();
+                    Self::new(name, Kind::Screenline, segments)
+                },
+                Some(Geometry::Polygon(rings)) => {
+                    let polygon = rings.iter().map(|r| project(r)).collect();
+                    Self::from_polygons(name, &[polygon])
+                },
+                Some(Geometry::MultiPolygon(polygons)) => {
+                    let polygons: Vec<_> = polygons.iter()
+                        .map(|rings| rings.iter().map(|r| project(r)).collect())
+                        .collect();
+                    Self::from_polygons(name, &polygons)
+                },
+                _ => {
+                    println!("Skipping feature {:?} without a line or polygon geometry", name);
+                    continue;
+                },
+            };
+            lines.push(line);
+        }
+        if lines.is_empty() {
+            anyhow::bail!("{:?} contains no lines or polygons", path.as_ref());
+        }
+        Ok(lines)
+    }
+
+    fn may_intersect(&self, p: Point3035, q: Point3035) -> bool {
+        p.coords.0.max(q.coords.0) >= self.bounds.0
+            && p.coords.1.max(q.coords.1) >= self.bounds.1
+            && p.coords.0.min(q.coords.0) <= self.bounds.2
+            && p.coords.1.min(q.coords.1) <= self.bounds.3
+    }
+
+    /// Count the crossings of the segment from `p` to `q` as (inbound, outbound).
+    ///
+    /// Points on the line count as right of it, and each segment of the line includes its start
+    /// but not its end, so that touching the line or passing through a vertex is counted once.
+    fn crossings(&self, p: Point3035, q: Point3035) -> (u64, u64) {
+        let mut counts = (0, 0);
+        if !self.may_intersect(p, q) {
+            return counts;
+        }
+        for &(a, b) in &self.segments {
+            let p_left = cross(a, b, p) > 0.0;
+            let q_left = cross(a, b, q) > 0.0;
+            if p_left == q_left {
+                continue;
+            }
+            // Position of the intersection along a-b
+            let denominator = cross(p, q, b) - cross(p, q, a);
+            let u = -cross(p, q, a) / denominator;
+            if (0.0..1.0).contains(&u) {
+                if q_left {
+                    counts.0 += 1;
+                } else {
+                    counts.1 += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    /// Add the crossings of a path. Returns true if the path crosses the line at least once.
+    pub fn add_path(&mut self, points: &[Point3035]) -> bool {
+        let (mut inbound, mut outbound) = (0, 0);
+        for w in points.windows(2) {
+            let (i, o) = self.crossings(w[0], w[1]);
+            inbound += i;
+            outbound += o;
+        }
+        self.inbound += inbound;
+        self.outbound += outbound;
+        if inbound + outbound > 0 {
+            self.routes += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Find all edges of the network that cross the line.
+    pub fn find_edges(&mut self, net: &Network) {
+        self.edges = net.edges()
+            .filter(|edge| {
+                let (i, o) = self.crossings(edge.a.as_point3035(), edge.b.as_point3035());
+                i + o > 0
+            })
+            .map(|edge| edge.osm_ids())
+            .collect();
+    }
+}
+
+/// Count the crossings of all routes of the given file that match the filter.
+pub fn count_routes(
+    lines: &mut [Screenline],
+    routes_path: &str,
+    net: &Network,
+    filter: Option<&Filter>,
+) -> anyhow::Result<()> {
+    let reader = RouteCollectionReader::new(routes_path)
+        .with_context(|| format!("Failed to read the routes file {:?}", routes_path))?;
+    let mut total = 0;
+    let mut crossing = 0;
+    for (index, route) in reader.enumerate() {
+        let route = route?;
+        if let Some(filter) = filter {
+            if !filter.matches(&RouteValues::new(index as u64, &route)) {
+                continue;
+            }
+        }
+        total += 1;
+        let points = route.node_ids.iter()
+            .map(|&n| net.get_node(n).map(|n| n.as_point3035())
+                .ok_or_else(|| anyhow::anyhow!("Node {} is not part of the network", n.as_i64())))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .with_context(|| format!("Route {} does not match the network", index))?;
+        let mut crosses = false;
+        for line in lines.iter_mut() {
+            crosses |= line.add_path(&points);
+        }
+        if crosses {
+            crossing += 1;
+        }
+    }
+    println!("{} of {} routes cross at least one line", crossing, total);
+    Ok(())
+}
+
+/// Print a table with the counts of each line.
+pub fn print_counts(lines: &[Screenline]) {
+    println!(
+        "{:<24} {:>10} {:>10} {:>10} {:>10} {:>6}",
+        "name", "kind", "inbound", "outbound", "routes", "edges",
+    );
+    for line in lines {
+        let kind = match line.kind {
+            Kind::Screenline => "screenline",
+            Kind::Cordon => "cordon",
+        };
+        println!(
+            "{:<24} {:>10} {:>10} {:>10} {:>10} {:>6}",
+            line.name, kind, line.inbound, line.outbound, line.routes, line.edges.len(),
+        );
+    }
+}
+
+/// Write the counts of each line as CSV.
+pub fn write_counts<P: AsRef<Path>>(lines: &[Screenline], path: P) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(&path)
+        .with_context(|| format!("Failed to create {:?}", path.as_ref()))?;
+    for line in lines {
+        writer.serialize(CountRecord {
+            name: &line.name,
+            kind: line.kind,
+            inbound: line.inbound,
+            outbound: line.outbound,
+            total: line.inbound + line.outbound,
+            routes: line.routes,
+            edges: line.edges.len(),
+        })?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the network edges that each line cuts as CSV.
+pub fn write_edges<P: AsRef<Path>>(lines: &[Screenline], path: P) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(&path)
+        .with_context(|| format!("Failed to create {:?}", path.as_ref()))?;
+    for line in lines {
+        for &(a, b) in &line.edges {
+            writer.serialize(EdgeRecord {
+                name: &line.name,
+                osm_source: a.as_i64(),
+                osm_target: b.as_i64(),
+            })?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempFile;
+
+    fn p(x: f64, y: f64) -> Point3035 {
+        Point3035::new(x, y)
+    }
+
+    #[test]
+    fn test_screenline() {
+        // Digitized northwards, so west is left
+        let points = [p(0.0, 0.0), p(0.0, 5.0), p(0.0, 10.0)];
+        let mut line = Screenline::from_line("a".to_string(), &points);
+        // East to west, then back
+        assert!(line.add_path(&[p(1.0, 1.0), p(-1.0, 2.0), p(1.0, 3.0)]));
+        assert_eq!((line.inbound, line.outbound), (1, 1));
+        // Through the shared vertex, counted once
+        assert!(line.add_path(&[p(1.0, 5.0), p(-1.0, 5.0)]));
+        assert_eq!((line.inbound, line.outbound), (2, 1));
+        // Touching the line and turning back
+        assert!(!line.add_path(&[p(1.0, 1.0), p(0.0, 1.0), p(1.0, 2.0)]));
+        // Passing north of the line
+        assert!(!line.add_path(&[p(1.0, 11.0), p(-1.0, 11.0)]));
+        assert_eq!(line.routes, 2);
+    }
+
+    #[test]
+    fn test_cordon() {
+        // Clockwise exterior ring with a counterclockwise hole
+        let exterior = vec![p(0.0, 0.0), p(0.0, 10.0), p(10.0, 10.0), p(10.0, 0.0), p(0.0, 0.0)];
+        let hole = vec![p(4.0, 4.0), p(6.0, 4.0), p(6.0, 6.0), p(4.0, 6.0), p(4.0, 4.0)];
+        let mut cordon = Screenline::from_polygons("c".to_string(), &[vec![exterior, hole]]);
+        assert_eq!(cordon.kind, Kind::Cordon);
+
+        // From outside into the hole
+        cordon.add_path(&[p(-1.0, 5.0), p(5.0, 5.0)]);
+        assert_eq!((cordon.inbound, cordon.outbound), (1, 1));
+        // Leaving the polygon
+        cordon.add_path(&[p(2.0, 2.0), p(2.0, -2.0)]);
+        assert_eq!((cordon.inbound, cordon.outbound), (1, 2));
+        // Completely inside
+        assert!(!cordon.add_path(&[p(1.0, 1.0), p(9.0, 1.0)]));
+    }
+
+    #[test]
+    fn test_read_all() {
+        let path = TempFile::new("screenlines.geojson");
+        std::fs::write(&path, r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "properties": {"name": "river"},
+             "geometry": {"type": "LineString", "coordinates": [[13.4, 52.5], [13.4, 52.6]]}},
+            {"type": "Feature", "properties": {},
+             "geometry": {"type": "Polygon", "coordinates":
+                [[[13.3, 52.4], [13.5, 52.4], [13.5, 52.6], [13.3, 52.6], [13.3, 52.4]]]}},
+            {"type": "Feature", "properties": {},
+             "geometry": {"type": "Point", "coordinates": [13.4, 52.5]}}
+        ]}"#).unwrap();
+
+        let lines = Screenline::read_all(&path).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!((lines[0].name.as_str(), lines[0].kind), ("river", Kind::Screenline));
+        assert_eq!((lines[1].name.as_str(), lines[1].kind), ("1", Kind::Cordon));
+        assert_eq!(lines[1].segments.len(), 4);
+    }
+}
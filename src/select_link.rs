@@ -0,0 +1,233 @@
+//! Select-link analysis: where does the traffic on a set of edges come from and go to?
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use geomatic::{laea, Point4326};
+
+use crate::bounding_box::BoundingBox;
+use crate::geometry::Geometry;
+use crate::network::{Network, OsmNodeId};
+use crate::output::{self, Feature};
+use crate::render::Projection;
+use crate::route::RouteCollectionReader;
+
+
+/// How a selected edge is specified on the command line.
+pub enum LinkSelector {
+    /// The OSM node ids at both ends of the edge
+    Nodes(OsmNodeId, OsmNodeId),
+    /// The edge that is nearest to a coordinate
+    Near(Point4326),
+}
+
+/// A route that uses one of the selected links.
+pub struct SelectedRoute {
+    /// Index of the route in the routes file
+    pub index: u64,
+    pub origin: Point4326,
+    pub destination: Point4326,
+    /// Index of the first selected link that the route uses
+    pub link: usize,
+}
+
+/// Routes that use the selected links. The network counts only include these routes.
+pub struct SelectLink {
+    /// Selected edges in both directions, mapped to the index of the link
+    links: HashMap<(OsmNodeId, OsmNodeId), usize>,
+    /// Geometry of each selected edge for rendering
+    link_lines: Vec<(Point4326, Point4326)>,
+    pub routes: Vec<SelectedRoute>,
+}
+
+impl SelectLink {
+    /// Find the selected edges in the network.
+    pub fn new(net: &Network, selectors: &[LinkSelector]) -> anyhow::Result<Self> {
+        let mut links = HashMap::new();
+        let mut link_lines = vec![];
+        for selector in selectors {
+            let (a, b) = match *selector {
+                LinkSelector::Nodes(a, b) => {
+                    if net.edge_number(a, b).is_none() {
+                        anyhow::bail!(
+                            "There is no edge between the nodes {} and {}", a.as_i64(), b.as_i64()
+                        );
+                    }
+                    (a, b)
+                },
+                LinkSelector::Near(point) => {
+                    let edge = net.nearest_edge(point)
+                        .ok_or_else(|| anyhow::anyhow!("The network has no edges"))?;
+                    let (a, b) = edge.osm_ids();
+                    println!(
+                        "Selected the edge between the nodes {} and {} near {}",
+                        a.as_i64(),
+                        b.as_i64(),
+                        point,
+                    );
+                    (a, b)
+                },
+            };
+            links.insert((a, b), link_lines.len());
+            links.insert((b, a), link_lines.len());
+            // Both nodes exist, see above.
+            let node_a = net.get_node(a).unwrap().as_point4326();
+            let node_b = net.get_node(b).unwrap().as_point4326();
+            link_lines.push((node_a, node_b));
+        }
+        Ok(SelectLink {
+            links,
+            link_lines,
+            routes: vec![],
+        })
+    }
+
+    /// The index of the first selected link that the given route uses.
+    fn used_link(&self, nodes: &[OsmNodeId]) -> Option<usize> {
+        nodes.windows(2).find_map(|w| self.links.get(&(w[0], w[1])).copied())
+    }
+
+    /// Add all routes of the given file that use one of the selected links to the network
+    /// counts. The previous counts are removed.
+    pub fn scan_routes(&mut self, routes_path: &str, net: &mut Network) -> anyhow::Result<()> {
+        let reader = RouteCollectionReader::new(routes_path)
+            .with_context(|| format!("Failed to read the routes file {:?}", routes_path))?;
+        net.reset_edge_counts();
+        self.routes.clear();
+
+        let mut total = 0;
+        for (index, route) in reader.enumerate() {
+            let route = route?;
+            total += 1;
+            if let Some(link) = self.used_link(&route.node_ids) {
+                net.bump_edges(&route.node_ids);
+                self.routes.push(SelectedRoute {
+                    index: index as u64,
+                    origin: route.start_coord.as_point4326(),
+                    destination: route.end_coord.as_point4326(),
+                    link,
+                });
+            }
+        }
+        println!("{} of {} routes use the selected links", self.routes.len(), total);
+        Ok(())
+    }
+
+    /// Write all selected links as a single MultiLineString with the number of selected routes.
+    pub fn write_links(&self, path: &str, precision: usize) -> anyhow::Result<()> {
+        let mut writer = output::create(path)?;
+        writer.set_precision(precision);
+        let links = self.link_lines.iter().map(|&(a, b)| vec![a, b]).collect();
+        writer.write_feature(&Feature::new(Geometry::MultiLineString(links))
+            .with_property("number", self.routes.len()))?;
+        writer.finish()
+    }
+
+    /// Write the origin and destination of each selected route as points.
+    pub fn write_od_points(&self, path: &str, precision: usize) -> anyhow::Result<()> {
+        let mut writer = output::create(path)?;
+        writer.set_precision(precision);
+        for route in &self.routes {
+            for &(role, point) in &[("origin", route.origin), ("destination", route.destination)] {
+                writer.write_feature(&Feature::new(Geometry::Point(point))
+                    .with_property("index", route.index)
+                    .with_property("role", role))?;
+            }
+        }
+        writer.finish()
+    }
+
+    /// Bounds of the selected links and all origins and destinations.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        BoundingBox::from_points(
+            self.link_lines.iter().flat_map(|&(a, b)| vec![a, b])
+                .chain(self.routes.iter().flat_map(|r| vec![r.origin, r.destination]))
+        )
+    }
+
+    /// Render a spider map: the network load of the selected routes, desire lines from each
+    /// origin (blue) to the selected link and from there to the destination (red), and the
+    /// selected links in magenta.
+    pub fn render_image(&self, net: &Network, bounds: BoundingBox, width: u32, height: u32)
+        -> tiny_skia::Canvas
+    {
+        let mut canvas = net.render_image(bounds, width, height);
+        let projection = Projection::new(bounds, width, height);
+
+        let mut paint = tiny_skia::Paint {
+            anti_alias: true,
+            ..Default::default()
+        };
+        let origin_color = tiny_skia::Color::from_rgba8(33, 102, 172, 160);
+        let destination_color = tiny_skia::Color::from_rgba8(178, 24, 43, 160);
+
+        // Desire lines are drawn thin and transparent, so that many overlapping lines show the
+        // main directions.
+        let origin_line = tiny_skia::Color::from_rgba8(33, 102, 172, 60);
+        let destination_line = tiny_skia::Color::from_rgba8(178, 24, 43, 60);
+        let desire_stroke = tiny_skia::Stroke {
+            width: 1.5,
+            ..Default::default()
+        };
+        for route in &self.routes {
+            let (a, b) = self.link_lines[route.link];
+            let link_center = laea::forward(Point4326::new(
+                (a.lat() + b.lat()) / 2.0,
+                (a.lon() + b.lon()) / 2.0,
+            ));
+            for &(point, color) in &[(route.origin, origin_line), (route.destination, destination_line)] {
+                if let Some(path) = projection.line_path(laea::forward(point), link_center) {
+                    paint.set_color(color);
+                    canvas.stroke_path(&path, &paint, &desire_stroke);
+                }
+            }
+        }
+
+        for route in &self.routes {
+            for &(point, color) in &[(route.origin, origin_color), (route.destination, destination_color)] {
+                let (x, y) = projection.project(laea::forward(point));
+                if let Some(circle) = tiny_skia::PathBuilder::from_circle(x, y, 4.0) {
+                    paint.set_color(color);
+                    canvas.fill_path(&circle, &paint, tiny_skia::FillRule::Winding);
+                }
+            }
+        }
+
+        let stroke = tiny_skia::Stroke {
+            width: 8.0,
+            line_cap: tiny_skia::LineCap::Round,
+            ..Default::default()
+        };
+        paint.set_color(tiny_skia::Color::from_rgba8(200, 0, 200, 255));
+        for &(a, b) in &self.link_lines {
+            if let Some(path) = projection.line_path(laea::forward(a), laea::forward(b)) {
+                canvas.stroke_path(&path, &paint, &stroke);
+            }
+        }
+        canvas
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_used_link() {
+        let id = OsmNodeId::new;
+        let select = SelectLink {
+            links: vec![((id(2), id(3)), 0), ((id(3), id(2)), 0), ((id(6), id(7)), 1)]
+                .into_iter()
+                .collect(),
+            link_lines: vec![],
+            routes: vec![],
+        };
+        assert_eq!(select.used_link(&[id(1), id(2), id(3)]), Some(0));
+        assert_eq!(select.used_link(&[id(4), id(3), id(2), id(1)]), Some(0));
+        assert_eq!(select.used_link(&[id(6), id(7), id(2), id(3)]), Some(1));
+        // Passing both nodes without using the edge in between
+        assert_eq!(select.used_link(&[id(2), id(5), id(3)]), None);
+        assert_eq!(select.used_link(&[id(2)]), None);
+    }
+}
@@ -0,0 +1,557 @@
+//! Write ESRI Shapefiles (`*.shp`, `*.shx`, `*.dbf`, `*.prj` and `*.cpg`).
+//!
+//! Geometries are written while features are added, but the attribute table is kept in memory
+//! until the writer is finished, because the dBase header has to describe all fields up front.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use geomatic::Point4326;
+use serde_json::Value;
+
+use crate::geometry::Geometry;
+use crate::output::{Feature, FeatureSink};
+
+
+/// Coordinate reference system WGS 84 in the flavor of WKT that ESRI software expects.
+const WGS84_PRJ: &str = "GEOGCS[\"GCS_WGS_1984\",DATUM[\"D_WGS_1984\",\
+    SPHEROID[\"WGS_1984\",6378137.0,298.257223563]],PRIMEM[\"Greenwich\",0.0],\
+    UNIT[\"Degree\",0.0174532925199433]]";
+
+/// Maximum length of field names in dBase files.
+const MAX_FIELD_NAME_LENGTH: usize = 10;
+
+/// Maximum width of character fields in dBase files.
+const MAX_TEXT_WIDTH: usize = 254;
+
+/// Width and number of decimal places of floating point fields.
+const FLOAT_WIDTH: usize = 24;
+const FLOAT_DECIMALS: usize = 15;
+
+/// Length of the main file header and the index file header in bytes.
+const HEADER_LENGTH: u32 = 100;
+
+/// Shape types of the Shapefile specification.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum ShapeType {
+    Null = 0,
+    Point = 1,
+    PolyLine = 3,
+    Polygon = 5,
+    MultiPoint = 8,
+}
+
+impl ShapeType {
+    fn of(geometry: &Geometry) -> ShapeType {
+        match geometry {
+            Geometry::Point(_) => ShapeType::Point,
+            Geometry::MultiPoint(_) => ShapeType::MultiPoint,
+            Geometry::LineString(_) | Geometry::MultiLineString(_) => ShapeType::PolyLine,
+            Geometry::Polygon(_) | Geometry::MultiPolygon(_) => ShapeType::Polygon,
+        }
+    }
+}
+
+/// Types of dBase fields
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum FieldKind {
+    Logical,
+    Integer,
+    Float,
+    Text,
+}
+
+impl FieldKind {
+    /// Returns None for null values, which are written as empty fields.
+    fn of(value: &Value) -> Option<FieldKind> {
+        match value {
+            Value::Null => None,
+            Value::Bool(_) => Some(FieldKind::Logical),
+            Value::Number(n) if n.is_i64() || n.is_u64() => Some(FieldKind::Integer),
+            Value::Number(_) => Some(FieldKind::Float),
+            _ => Some(FieldKind::Text),
+        }
+    }
+
+    /// Find a kind that can hold values of both kinds.
+    fn merge(self, other: FieldKind) -> FieldKind {
+        use FieldKind::*;
+        match (self, other) {
+            (a, b) if a == b => a,
+            (Integer, Float) | (Float, Integer) => Float,
+            _ => Text,
+        }
+    }
+}
+
+/// A field of the attribute table.
+struct Field {
+    /// The property key
+    key: String,
+    /// The key shortened to the length limit of dBase
+    name: String,
+    kind: FieldKind,
+    /// Largest width of all values in bytes
+    width: usize,
+}
+
+impl Field {
+    fn width(&self) -> usize {
+        match self.kind {
+            FieldKind::Logical => 1,
+            FieldKind::Integer => self.width.max(1),
+            FieldKind::Float => FLOAT_WIDTH,
+            FieldKind::Text => self.width.clamp(1, MAX_TEXT_WIDTH),
+        }
+    }
+
+    fn decimals(&self) -> usize {
+        if self.kind == FieldKind::Float { FLOAT_DECIMALS } else { 0 }
+    }
+
+    /// Format a value so that it fills the whole field.
+    fn format(&self, value: Option<&Value>) -> Vec<u8> {
+        let width = self.width();
+        let text = match (self.kind, value) {
+            (_, None) | (_, Some(Value::Null)) => {
+                let empty = if self.kind == FieldKind::Logical { "?" } else { "" };
+                return format!("{:width$}", empty, width = width).into_bytes();
+            },
+            (FieldKind::Logical, Some(v)) => {
+                return if v.as_bool().unwrap_or(false) { b"T".to_vec() } else { b"F".to_vec() };
+            },
+            (FieldKind::Integer, Some(v)) => v.to_string(),
+            (FieldKind::Float, Some(v)) => {
+                let v = v.as_f64().unwrap_or(0.0);
+                let fixed = format!("{:.*}", FLOAT_DECIMALS, v);
+                if fixed.len() <= width { fixed } else { format!("{:e}", v) }
+            },
+            (FieldKind::Text, Some(Value::String(s))) => s.clone(),
+            (FieldKind::Text, Some(v)) => v.to_string(),
+        };
+
+        let mut bytes = truncate(&text, width).as_bytes().to_vec();
+        if self.kind == FieldKind::Text {
+            bytes.resize(width, b' ');
+        } else {
+            // Numbers are right-aligned
+            let mut padded = vec![b' '; width - bytes.len()];
+            padded.append(&mut bytes);
+            bytes = padded;
+        }
+        bytes
+    }
+}
+
+/// Shorten a string to at most `max_len` bytes without splitting a character.
+fn truncate(s: &str, max_len: usize) -> &str {
+    let mut end = s.len().min(max_len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Turn a property key into a unique dBase field name with at most ten ASCII characters.
+/// Names that would collide after shortening get a numbered suffix, e.g. `number_mea` and
+/// `number_m_1`.
+fn field_name(key: &str, used: &HashSet<String>) -> String {
+    let ascii: String = key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    let ascii = if ascii.is_empty() { "field".to_string() } else { ascii };
+
+    let mut name = truncate(&ascii, MAX_FIELD_NAME_LENGTH).to_string();
+    let mut i = 1;
+    // dBase field names are case-insensitive
+    while used.contains(&name.to_uppercase()) {
+        let suffix = format!("_{}", i);
+        name = format!("{}{}", truncate(&ascii, MAX_FIELD_NAME_LENGTH - suffix.len()), suffix);
+        i += 1;
+    }
+    name
+}
+
+/// Write ESRI Shapefiles
+pub struct ShapefileWriter<W: Write + Seek> {
+    shp: BufWriter<W>,
+    shx: BufWriter<W>,
+    dbf: BufWriter<W>,
+    /// Shape type of all features, set by the first feature with a geometry
+    shape_type: Option<ShapeType>,
+    /// Bounds of all features (min x, min y, max x, max y)
+    bbox: Option<[f64; 4]>,
+    /// Length of the main file in bytes
+    shp_length: u32,
+    /// Properties of all features
+    records: Vec<Vec<(String, Value)>>,
+    finished: bool,
+}
+
+impl ShapefileWriter<File> {
+    /// Create the `*.shp` file and all accompanying files next to it.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> anyhow::Result<ShapefileWriter<File>> {
+        let path = path.as_ref();
+        std::fs::write(path.with_extension("prj"), WGS84_PRJ)?;
+        std::fs::write(path.with_extension("cpg"), "UTF-8")?;
+        ShapefileWriter::new(
+            File::create(path.with_extension("shp"))?,
+            File::create(path.with_extension("shx"))?,
+            File::create(path.with_extension("dbf"))?,
+        )
+    }
+}
+
+impl<W: Write + Seek> ShapefileWriter<W> {
+    pub fn new(shp: W, shx: W, dbf: W) -> anyhow::Result<ShapefileWriter<W>> {
+        let mut shp = BufWriter::new(shp);
+        let mut shx = BufWriter::new(shx);
+        // Headers are written when the writer is finished.
+        shp.write_all(&[0; HEADER_LENGTH as usize])?;
+        shx.write_all(&[0; HEADER_LENGTH as usize])?;
+        Ok(ShapefileWriter {
+            shp,
+            shx,
+            dbf: BufWriter::new(dbf),
+            shape_type: None,
+            bbox: None,
+            shp_length: HEADER_LENGTH,
+            records: vec![],
+            finished: false,
+        })
+    }
+
+    fn write_header(&mut self, length: u32, is_index: bool) -> anyhow::Result<()> {
+        let writer = if is_index { &mut self.shx } else { &mut self.shp };
+        let bbox = self.bbox.unwrap_or([0.0; 4]);
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&9994u32.to_be_bytes())?;
+        writer.write_all(&[0; 20])?;
+        // The length is given in 16-bit words.
+        writer.write_all(&(length / 2).to_be_bytes())?;
+        writer.write_all(&1000u32.to_le_bytes())?;
+        writer.write_all(&(self.shape_type.unwrap_or(ShapeType::Null) as u32).to_le_bytes())?;
+        for value in bbox.iter().chain(&[0.0; 4]) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_dbf(&mut self) -> anyhow::Result<()> {
+        // Collect all fields in the order of their first appearance.
+        let mut fields: Vec<Field> = vec![];
+        let mut used_names = HashSet::new();
+        for record in &self.records {
+            for (key, value) in record {
+                let kind = match FieldKind::of(value) {
+                    Some(kind) => kind,
+                    None => continue,
+                };
+                let width = match value {
+                    Value::String(s) => s.len(),
+                    v => v.to_string().len(),
+                };
+                match fields.iter_mut().find(|f| &f.key == key) {
+                    Some(field) => {
+                        field.kind = field.kind.merge(kind);
+                        field.width = field.width.max(width);
+                    },
+                    None => {
+                        let name = field_name(key, &used_names);
+                        if &name != key {
+                            println!("Shapefile field {:?} is written as {:?}", key, name);
+                        }
+                        used_names.insert(name.to_uppercase());
+                        fields.push(Field { key: key.clone(), name, kind, width });
+                    },
+                }
+            }
+        }
+
+        let header_length = 32 + 32 * fields.len() + 1;
+        let record_length = 1 + fields.iter().map(|f| f.width()).sum::<usize>();
+        let (year, month, day) = today();
+
+        self.dbf.write_all(&[0x03, (year - 1900) as u8, month, day])?;
+        self.dbf.write_all(&(self.records.len() as u32).to_le_bytes())?;
+        self.dbf.write_all(&(header_length as u16).to_le_bytes())?;
+        self.dbf.write_all(&(record_length as u16).to_le_bytes())?;
+        self.dbf.write_all(&[0; 20])?;
+
+        for field in &fields {
+            let mut name = [0u8; 11];
+            name[..field.name.len()].copy_from_slice(field.name.as_bytes());
+            self.dbf.write_all(&name)?;
+            let field_type = match field.kind {
+                FieldKind::Logical => b'L',
+                FieldKind::Integer | FieldKind::Float => b'N',
+                FieldKind::Text => b'C',
+            };
+            self.dbf.write_all(&[field_type, 0, 0, 0, 0, field.width() as u8, field.decimals() as u8])?;
+            self.dbf.write_all(&[0; 14])?;
+        }
+        self.dbf.write_all(&[0x0d])?;
+
+        for record in &self.records {
+            // Records that are not deleted start with a space.
+            self.dbf.write_all(b" ")?;
+            for field in &fields {
+                let value = record.iter().find(|(key, _)| key == &field.key).map(|(_, v)| v);
+                self.dbf.write_all(&field.format(value))?;
+            }
+        }
+        self.dbf.write_all(&[0x1a])?;
+        self.dbf.flush()?;
+
+        Ok(())
+    }
+
+    /// A private method that does not move self so Drop can call it.
+    fn mut_finish(&mut self) -> anyhow::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        self.shp.flush()?;
+        self.shx.flush()?;
+        let shx_length = HEADER_LENGTH + 8 * self.records.len() as u32;
+        self.write_header(self.shp_length, false)?;
+        self.write_header(shx_length, true)?;
+        self.write_dbf()
+    }
+}
+
+impl<W: Write + Seek> FeatureSink for ShapefileWriter<W> {
+    fn write_feature(&mut self, feature: &Feature) -> anyhow::Result<()> {
+        let shape_type = ShapeType::of(&feature.geometry);
+        match self.shape_type {
+            None => self.shape_type = Some(shape_type),
+            Some(t) if t != shape_type => {
+                anyhow::bail!(
+                    "Shapefiles can only store one geometry type, can't add {:?} to {:?}",
+                    shape_type,
+                    t,
+                );
+            },
+            _ => {},
+        }
+
+        let content = encode_geometry(&feature.geometry);
+        for p in geometry_points(&feature.geometry) {
+            let (x, y) = (p.lon(), p.lat());
+            self.bbox = Some(match self.bbox {
+                None => [x, y, x, y],
+                Some(b) => [b[0].min(x), b[1].min(y), b[2].max(x), b[3].max(y)],
+            });
+        }
+
+        let record_number = self.records.len() as u32 + 1;
+        let content_length = content.len() as u32 / 2;
+        self.shx.write_all(&(self.shp_length / 2).to_be_bytes())?;
+        self.shx.write_all(&content_length.to_be_bytes())?;
+        self.shp.write_all(&record_number.to_be_bytes())?;
+        self.shp.write_all(&content_length.to_be_bytes())?;
+        self.shp.write_all(&content)?;
+        self.shp_length += 8 + content.len() as u32;
+
+        self.records.push(feature.properties_with_id()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect());
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.mut_finish()
+    }
+}
+
+impl<W: Write + Seek> Drop for ShapefileWriter<W> {
+    fn drop(&mut self) {
+        // drop can't return errors :(
+        let _ = self.mut_finish();
+    }
+}
+
+fn geometry_points(geometry: &Geometry) -> Vec<Point4326> {
+    match geometry {
+        Geometry::Point(p) => vec![*p],
+        Geometry::MultiPoint(points) | Geometry::LineString(points) => points.clone(),
+        Geometry::MultiLineString(parts) | Geometry::Polygon(parts) => parts.concat(),
+        Geometry::MultiPolygon(polygons) => polygons.concat().concat(),
+    }
+}
+
+/// Encode the record contents of a geometry. Empty geometries are written as null shapes.
+fn encode_geometry(geometry: &Geometry) -> Vec<u8> {
+    let mut buf = vec![];
+    let put_f64 = |buf: &mut Vec<u8>, v: f64| buf.extend_from_slice(&v.to_le_bytes());
+
+    if let Geometry::Point(p) = geometry {
+        buf.extend_from_slice(&(ShapeType::Point as u32).to_le_bytes());
+        put_f64(&mut buf, p.lon());
+        put_f64(&mut buf, p.lat());
+        return buf;
+    }
+
+    let parts: Vec<Vec<Point4326>> = match geometry {
+        Geometry::Point(_) => unreachable!(),
+        Geometry::MultiPoint(points) | Geometry::LineString(points) => vec![points.clone()],
+        Geometry::MultiLineString(lines) => lines.clone(),
+        Geometry::Polygon(rings) => orient_rings(rings),
+        Geometry::MultiPolygon(polygons) => polygons.iter().flat_map(|p| orient_rings(p)).collect(),
+    };
+    let points: Vec<Point4326> = parts.concat();
+
+    if points.is_empty() {
+        buf.extend_from_slice(&(ShapeType::Null as u32).to_le_bytes());
+        return buf;
+    }
+
+    let shape_type = ShapeType::of(geometry);
+    buf.extend_from_slice(&(shape_type as u32).to_le_bytes());
+
+    let min_x = points.iter().map(|p| p.lon()).fold(f64::INFINITY, f64::min);
+    let min_y = points.iter().map(|p| p.lat()).fold(f64::INFINITY, f64::min);
+    let max_x = points.iter().map(|p| p.lon()).fold(f64::NEG_INFINITY, f64::max);
+    let max_y = points.iter().map(|p| p.lat()).fold(f64::NEG_INFINITY, f64::max);
+    for &v in &[min_x, min_y, max_x, max_y] {
+        put_f64(&mut buf, v);
+    }
+
+    if shape_type != ShapeType::MultiPoint {
+        buf.extend_from_slice(&(parts.len() as u32).to_le_bytes());
+    }
+    buf.extend_from_slice(&(points.len() as u32).to_le_bytes());
+    if shape_type != ShapeType::MultiPoint {
+        let mut start = 0;
+        for part in &parts {
+            buf.extend_from_slice(&(start as u32).to_le_bytes());
+            start += part.len();
+        }
+    }
+    for p in &points {
+        put_f64(&mut buf, p.lon());
+        put_f64(&mut buf, p.lat());
+    }
+
+    buf
+}
+
+/// Close all rings and orient them as required by the specification: The exterior ring is
+/// clockwise and all holes are counterclockwise.
+fn orient_rings(rings: &[Vec<Point4326>]) -> Vec<Vec<Point4326>> {
+    rings.iter().enumerate().map(|(i, ring)| {
+        let mut ring = ring.clone();
+        if let (Some(first), Some(last)) = (ring.first(), ring.last()) {
+            if first.coords != last.coords {
+                ring.push(*first);
+            }
+        }
+        let is_clockwise = signed_area(&ring) < 0.0;
+        if is_clockwise != (i == 0) {
+            ring.reverse();
+        }
+        ring
+    }).collect()
+}
+
+/// Twice the signed area of a closed ring, positive if counterclockwise.
+fn signed_area(ring: &[Point4326]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0].lon() * w[1].lat() - w[1].lon() * w[0].lat())
+        .sum()
+}
+
+/// Current date (year, month, day) in UTC for the dBase header.
+fn today() -> (i64, u8, u8) {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    civil_from_days((secs / 86400) as i64)
+}
+
+/// Convert days since 1970-01-01 to a date in the proleptic Gregorian calendar
+/// (see http://howardhinnant.github.io/date_algorithms.html).
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn read_u32_be(data: &[u8], pos: usize) -> u32 {
+        u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+    }
+
+    #[test]
+    fn test_field_names() {
+        let mut used = HashSet::new();
+        let mut add = |key: &str| {
+            let name = field_name(key, &used);
+            used.insert(name.to_uppercase());
+            name
+        };
+        assert_eq!(add("number"), "number");
+        assert_eq!(add("number_mean"), "number_mea");
+        assert_eq!(add("number_meandering"), "number_m_1");
+        assert_eq!(add("NUMBER"), "NUMBER_1");
+        assert_eq!(add("länge"), "l_nge");
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut shp = Cursor::new(vec![]);
+        let mut shx = Cursor::new(vec![]);
+        let mut dbf = Cursor::new(vec![]);
+        {
+            let mut writer = ShapefileWriter::new(&mut shp, &mut shx, &mut dbf).unwrap();
+            for i in 0..2 {
+                let coords = vec![Point4326::new(52.5, 13.4), Point4326::new(52.6, 13.5 + i as f64)];
+                let feature = Feature::new(Geometry::LineString(coords))
+                    .with_property("number_weighted_mean", 0.5 * i as f64)
+                    .with_property("name", if i == 0 { "a" } else { "abc" });
+                writer.write_feature(&feature).unwrap();
+            }
+            assert!(writer.write_feature(&Feature::new(Geometry::Point(Point4326::new(0.0, 0.0)))).is_err());
+            writer.finish().unwrap();
+        }
+        let shp = shp.into_inner();
+        let shx = shx.into_inner();
+        let dbf = dbf.into_inner();
+
+        // PolyLine record: shape type, box, number of parts and points, one part, two points
+        let record_length = 4 + 32 + 8 + 4 + 2 * 16;
+        assert_eq!(shp.len(), 100 + 2 * (8 + record_length));
+        assert_eq!(read_u32_be(&shp, 24) as usize * 2, shp.len());
+        assert_eq!(shp[32], ShapeType::PolyLine as u8);
+        assert_eq!(shx.len(), 100 + 2 * 8);
+        assert_eq!(read_u32_be(&shx, 108) as usize * 2, 100 + 8 + record_length);
+
+        // Header, two field descriptors, terminator, two records of 1 + 24 + 3 bytes, end of file
+        assert_eq!(dbf.len(), 32 + 2 * 32 + 1 + 2 * 28 + 1);
+        assert_eq!(&dbf[32..42], b"number_wei");
+        assert_eq!(&dbf[32 + 2 * 32 + 1 + 28..], b"        0.500000000000000abc\x1a");
+    }
+}
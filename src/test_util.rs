@@ -0,0 +1,53 @@
+//! Fixtures that are shared by the tests of several modules.
+
+use std::path::{Path, PathBuf};
+
+use crate::network::OsmNodeId;
+use crate::route::{LatLon32, Route};
+use crate::route_index::RouteIndex;
+
+
+/// A path in the temporary directory that is unique to the test process. The file and the index
+/// of a routes file at this path are removed when the value is dropped, also if the test fails.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// `name` needs to be unique among all tests and may include an extension, e.g.
+    /// "export.csv".
+    pub fn new(name: &str) -> Self {
+        TempFile {
+            path: std::env::temp_dir().join(format!("nori_test_{}_{}", std::process::id(), name)),
+        }
+    }
+
+    pub fn to_str(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+impl AsRef<Path> for TempFile {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Not every test creates the file or an index.
+        let _ = std::fs::remove_file(RouteIndex::path_for(&self.path));
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A route whose node ids, distance and duration depend on `i`.
+pub fn test_route(i: i64) -> Route {
+    Route {
+        start_coord: LatLon32::new(52.5, 13.4),
+        end_coord: LatLon32::new(52.6, 13.5),
+        node_ids: vec![OsmNodeId::new(i), OsmNodeId::new(i + 1)],
+        distance: 100.0 * i as f64,
+        duration: Some(10.0 * i as f64),
+    }
+}
@@ -0,0 +1,640 @@
+//! Target distributions of the distance between source and destination points.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::Context;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::route::Route;
+
+
+/// Quantile of the standard normal distribution at 0.999
+const Z_999: f64 = 3.090_232;
+
+/// A distribution of trip lengths in meters.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TripLength {
+    /// The logarithm of the distance is normally distributed with mean `mu` and standard
+    /// deviation `sigma`.
+    LogNormal { mu: f64, sigma: f64 },
+    /// Shares of distance bands. The distances are evenly distributed within each band.
+    Histogram {
+        /// Band limits in ascending order, one more than there are shares
+        edges: Vec<f64>,
+        /// Shares of all bands, adding up to 1
+        shares: Vec<f64>,
+    },
+}
+
+/// A row of a histogram CSV file
+#[derive(Deserialize)]
+struct Band {
+    lower: f64,
+    upper: f64,
+    share: f64,
+}
+
+/// Error function, with an absolute error below 1.5e-7 (Abramowitz and Stegun 7.1.26).
+fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let poly = t * (0.254_829_592
+        + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    if x < 0.0 { -y } else { y }
+}
+
+/// Quantile function of the standard normal distribution, with a relative error below 1.2e-9
+/// (P. J. Acklam's algorithm). Returns -inf for 0 and inf for 1.
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1, 2.209_460_984_245_205e2, -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2, -3.066_479_806_614_716e1, 2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1, 1.615_858_368_580_409e2, -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1, -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3, -3.223_964_580_411_365e-1, -2.400_758_277_161_838,
+        -2.549_732_539_343_734, 4.374_664_141_464_968, 2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3, 3.224_671_290_700_398e-1, 2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const P_LOW: f64 = 0.024_25;
+
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < P_LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - P_LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+impl TripLength {
+    /// A log-normal distribution with the given mean distance in meters and the standard
+    /// deviation `sigma` of the logarithm of the distance.
+    pub fn log_normal(mean: f64, sigma: f64) -> anyhow::Result<Self> {
+        if !(mean > 0.0 && sigma > 0.0) {
+            anyhow::bail!("The mean and sigma of a log-normal distribution need to be positive");
+        }
+        Ok(TripLength::LogNormal {
+            mu: mean.ln() - sigma * sigma / 2.0,
+            sigma,
+        })
+    }
+
+    /// Read distance bands from a CSV file with the columns `lower`, `upper` (in meters) and
+    /// `share`. The bands have to be adjacent and in ascending order. The shares are normalized.
+    pub fn from_csv<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mut reader = csv::Reader::from_path(&path)
+            .with_context(|| format!("Failed to open {:?}", path.as_ref()))?;
+        let mut bands: Vec<Band> = vec![];
+        for band in reader.deserialize() {
+            bands.push(band?);
+        }
+        let first = bands.first()
+            .ok_or_else(|| anyhow::anyhow!("The histogram has no distance bands"))?;
+        let mut edges = vec![first.lower];
+        for band in &bands {
+            if band.lower != *edges.last().unwrap() {
+                anyhow::bail!(
+                    "The distance band starting at {} does not follow the previous band", band.lower
+                );
+            }
+            if !(band.lower >= 0.0 && band.upper > band.lower && band.share >= 0.0) {
+                anyhow::bail!(
+                    "Invalid distance band {} to {} with share {}",
+                    band.lower,
+                    band.upper,
+                    band.share,
+                );
+            }
+            edges.push(band.upper);
+        }
+        Self::histogram(edges, bands.iter().map(|b| b.share).collect())
+    }
+
+    /// A histogram with the given band limits in ascending order and shares. The shares are
+    /// normalized.
+    pub fn histogram(edges: Vec<f64>, shares: Vec<f64>) -> anyhow::Result<Self> {
+        if edges.len() != shares.len() + 1 {
+            anyhow::bail!(
+                "A histogram with {} shares needs {} band limits, not {}",
+                shares.len(),
+                shares.len() + 1,
+                edges.len(),
+            );
+        }
+        if !edges.iter().all(|e| e.is_finite()) || edges[0] < 0.0 {
+            anyhow::bail!("The band limits of the histogram need to be finite and non-negative");
+        }
+        if let Some(w) = edges.windows(2).find(|w| w[1] <= w[0]) {
+            anyhow::bail!(
+                "The band limits of the histogram need to be ascending, but {} follows {}", w[1], w[0]
+            );
+        }
+        if shares.iter().any(|s| s.is_nan() || *s < 0.0) {
+            anyhow::bail!("The shares of the histogram need to be non-negative");
+        }
+        let total: f64 = shares.iter().sum();
+        if !total.is_finite() || total <= 0.0 {
+            anyhow::bail!("The shares of the histogram need to add up to a positive number");
+        }
+        Ok(TripLength::Histogram {
+            edges,
+            shares: shares.iter().map(|s| s / total).collect(),
+        })
+    }
+
+    /// Mean distance in meters
+    pub fn mean(&self) -> f64 {
+        match self {
+            TripLength::LogNormal { mu, sigma } => (mu + sigma * sigma / 2.0).exp(),
+            TripLength::Histogram { edges, shares } => shares.iter()
+                .enumerate()
+                .map(|(i, share)| share * (edges[i] + edges[i + 1]) / 2.0)
+                .sum(),
+        }
+    }
+
+    /// Probability density at the given distance.
+    pub fn pdf(&self, dist: f64) -> f64 {
+        match self {
+            TripLength::LogNormal { mu, sigma } => {
+                if dist <= 0.0 {
+                    return 0.0;
+                }
+                let z = (dist.ln() - mu) / sigma;
+                (-z * z / 2.0).exp() / (dist * sigma * (2.0 * std::f64::consts::PI).sqrt())
+            },
+            TripLength::Histogram { edges, shares } => match band_index(edges, dist) {
+                Some(i) => shares[i] / (edges[i + 1] - edges[i]),
+                None => 0.0,
+            },
+        }
+    }
+
+    /// Probability of a distance of at most `dist`.
+    pub fn cdf(&self, dist: f64) -> f64 {
+        match self {
+            TripLength::LogNormal { mu, sigma } => {
+                if dist <= 0.0 {
+                    return 0.0;
+                }
+                0.5 * (1.0 + erf((dist.ln() - mu) / (sigma * std::f64::consts::SQRT_2)))
+            },
+            TripLength::Histogram { edges, shares } => shares.iter()
+                .enumerate()
+                .map(|(i, share)| {
+                    let fraction = (dist - edges[i]) / (edges[i + 1] - edges[i]);
+                    share * fraction.clamp(0.0, 1.0)
+                })
+                .sum(),
+        }
+    }
+
+    /// The 99.9% quantile of a log-normal distribution or the end of the last band of a
+    /// histogram, used as maximum distance if none is given.
+    pub fn default_max_dist(&self) -> f64 {
+        match self {
+            TripLength::LogNormal { mu, sigma } => (mu + Z_999 * sigma).exp(),
+            TripLength::Histogram { edges, .. } => *edges.last().unwrap(),
+        }
+    }
+
+    /// Draw a random distance of at most `max_dist` by inverting the cumulative distribution
+    /// function of the distribution truncated at `max_dist`. Returns 0 if there are no trips
+    /// within `max_dist`.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R, max_dist: f64) -> f64 {
+        let u = rng.gen::<f64>() * self.cdf(max_dist);
+        let dist = match self {
+            TripLength::LogNormal { mu, sigma } => (mu + sigma * normal_quantile(u)).exp(),
+            TripLength::Histogram { edges, shares } => {
+                let mut u = u;
+                let mut dist = *edges.last().unwrap();
+                for (i, &share) in shares.iter().enumerate() {
+                    if u < share {
+                        dist = edges[i] + u / share * (edges[i + 1] - edges[i]);
+                        break;
+                    }
+                    u -= share;
+                }
+                dist
+            },
+        };
+        // The approximations of the cdf and its inverse do not match exactly.
+        dist.min(max_dist)
+    }
+
+    /// Weight of a candidate destination at the given distance. If the destinations were spread
+    /// evenly in the plane, their distance density would grow linearly with the distance and
+    /// weighting them by `pdf(dist) / dist` would yield the target distribution. Real densities
+    /// are clustered, so the achieved distribution differs. Check it with
+    /// `--trip-length-report` and adjust histograms with `--calibrate`.
+    pub fn area_weight(&self, dist: f64) -> f64 {
+        if dist > 0.0 {
+            self.pdf(dist) / dist
+        } else {
+            0.0
+        }
+    }
+
+    /// Compare the distances of sampled routes with this distribution, truncated at `max_dist`.
+    /// Histograms are compared band by band, other distributions in `bins` bands of equal width.
+    pub fn verify(&self, max_dist: f64, bee_line: &[f64], network: &[f64], bins: usize)
+        -> TripLengthReport
+    {
+        let truncated_cdf = |d: f64| (self.cdf(d.min(max_dist)) / self.cdf(max_dist)).min(1.0);
+        let share = |distances: &[f64], lower: f64, upper: f64| {
+            let n = distances.iter().filter(|&&d| d >= lower && d < upper).count();
+            if distances.is_empty() { 0.0 } else { n as f64 / distances.len() as f64 }
+        };
+
+        let edges = match self {
+            TripLength::Histogram { edges, .. } => {
+                let mut edges: Vec<f64> = edges.iter().copied().filter(|&e| e < max_dist).collect();
+                edges.push(max_dist);
+                if edges[0] > 0.0 {
+                    edges.insert(0, 0.0);
+                }
+                edges
+            },
+            _ => (0..=bins).map(|i| i as f64 * max_dist / bins as f64).collect(),
+        };
+        let mut rows: Vec<ReportRow> = edges.windows(2)
+            .map(|w| {
+                let (lower, upper) = (w[0], w[1]);
+                ReportRow {
+                    lower,
+                    upper,
+                    target: truncated_cdf(upper) - truncated_cdf(lower),
+                    bee_line: share(bee_line, lower, upper),
+                    network: share(network, lower, upper),
+                }
+            })
+            .collect();
+        // Bee-line distances end at max_dist, but network distances are longer.
+        rows.push(ReportRow {
+            lower: max_dist,
+            upper: f64::INFINITY,
+            target: 0.0,
+            bee_line: share(bee_line, max_dist, f64::INFINITY),
+            network: share(network, max_dist, f64::INFINITY),
+        });
+
+        TripLengthReport {
+            target: self.clone(),
+            rows,
+            bee_line_mean: mean(bee_line),
+            network_mean: mean(network),
+            ks_bee_line: ks_distance(bee_line, truncated_cdf),
+            ks_network: ks_distance(network, truncated_cdf),
+        }
+    }
+}
+
+impl fmt::Display for TripLength {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TripLength::LogNormal { sigma, .. } => {
+                write!(f, "log-normal trip lengths (mean {:.0} m, sigma {})", self.mean(), sigma)
+            },
+            TripLength::Histogram { shares, .. } => {
+                write!(
+                    f,
+                    "trip lengths from a histogram ({} bands, mean {:.0} m)",
+                    shares.len(),
+                    self.mean(),
+                )
+            },
+        }
+    }
+}
+
+/// Index of the band that contains the given distance
+fn band_index(edges: &[f64], dist: f64) -> Option<usize> {
+    edges.windows(2).position(|w| dist >= w[0] && dist < w[1])
+}
+
+/// Which distance of a route is compared with the target distribution
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DistanceMeasure {
+    BeeLine,
+    Network,
+}
+
+/// Adjusts the shares of a histogram that destinations are sampled from, so that the distances of
+/// the routes match the target histogram. Sampling only controls the bee-line distance, but
+/// routes are longer, destinations may be missing in some directions and points are snapped to
+/// the road network.
+pub struct Calibration {
+    edges: Vec<f64>,
+    target: Vec<f64>,
+    /// Correction factor of each band
+    factors: Vec<f64>,
+    measure: DistanceMeasure,
+    /// Number of routes between adjustments
+    every: usize,
+    /// Number of routes in each band since the last adjustment
+    counts: Vec<u64>,
+    routes: usize,
+    /// Largest difference between achieved and target shares before the last adjustment
+    pub last_difference: f64,
+}
+
+impl Calibration {
+    pub fn new(target: &TripLength, measure: DistanceMeasure, every: usize)
+        -> anyhow::Result<Self>
+    {
+        match target {
+            TripLength::Histogram { edges, shares } => Ok(Calibration {
+                edges: edges.clone(),
+                target: shares.clone(),
+                factors: vec![1.0; shares.len()],
+                measure,
+                every: every.max(1),
+                counts: vec![0; shares.len()],
+                routes: 0,
+                last_difference: f64::NAN,
+            }),
+            TripLength::LogNormal { .. } => anyhow::bail!("Only histograms can be calibrated"),
+        }
+    }
+
+    /// Add the distance of a sampled route. Returns the adjusted distribution to sample from
+    /// after every `every` routes.
+    pub fn add_route(&mut self, route: &Route) -> Option<TripLength> {
+        let dist = match self.measure {
+            DistanceMeasure::BeeLine => route.distance_bee_line(),
+            DistanceMeasure::Network => route.distance,
+        };
+        // Routes outside of all bands lower the achieved shares of all bands.
+        if let Some(i) = band_index(&self.edges, dist) {
+            self.counts[i] += 1;
+        }
+        self.routes += 1;
+        if self.routes < self.every {
+            return None;
+        }
+
+        self.last_difference = 0.0;
+        for i in 0..self.target.len() {
+            let achieved = self.counts[i] as f64 / self.routes as f64;
+            self.last_difference = self.last_difference.max((achieved - self.target[i]).abs());
+            if self.target[i] > 0.0 {
+                // Damped, because the shares of a single batch are noisy.
+                let ratio = if achieved > 0.0 { (self.target[i] / achieved).sqrt() } else { 2.0 };
+                self.factors[i] = (self.factors[i] * ratio).clamp(0.05, 20.0);
+            }
+        }
+        self.counts.iter_mut().for_each(|c| *c = 0);
+        self.routes = 0;
+
+        let shares = self.target.iter().zip(&self.factors).map(|(t, f)| t * f).collect();
+        Some(TripLength::histogram(self.edges.clone(), shares).unwrap())
+    }
+}
+
+/// Returns None if there are no values.
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.iter().sum::<f64>() / values.len() as f64)
+    }
+}
+
+/// Kolmogorov-Smirnov statistic: the largest difference between the empirical distribution of
+/// the values and the given cumulative distribution function. Returns None if there are no
+/// values.
+fn ks_distance<F: Fn(f64) -> f64>(values: &[f64], cdf: F) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let n = sorted.len() as f64;
+    Some(sorted.iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let c = cdf(x);
+            (c - i as f64 / n).abs().max(((i + 1) as f64 / n - c).abs())
+        })
+        .fold(0.0, f64::max))
+}
+
+/// Format an optional statistic, "n/a" if there were no routes.
+fn format_or_na(value: Option<f64>, precision: usize, unit: &str) -> String {
+    match value {
+        Some(value) => format!("{:.*}{}", precision, value, unit),
+        None => "n/a".to_string(),
+    }
+}
+
+/// Shares of trips per distance band
+#[derive(Serialize)]
+struct ReportRow {
+    lower: f64,
+    upper: f64,
+    target: f64,
+    bee_line: f64,
+    network: f64,
+}
+
+/// Achieved vs. target trip length distribution (see `TripLength::verify`).
+pub struct TripLengthReport {
+    target: TripLength,
+    rows: Vec<ReportRow>,
+    /// None if there are no routes
+    bee_line_mean: Option<f64>,
+    network_mean: Option<f64>,
+    /// Kolmogorov-Smirnov statistic of the bee-line distances
+    ks_bee_line: Option<f64>,
+    /// Kolmogorov-Smirnov statistic of the network distances
+    ks_network: Option<f64>,
+}
+
+impl TripLengthReport {
+    pub fn print_summary(&self) {
+        println!("Target: {}", self.target);
+        println!("Mean bee-line distance: {}", format_or_na(self.bee_line_mean, 0, " m"));
+        println!("Mean network distance:  {}", format_or_na(self.network_mean, 0, " m"));
+        println!("Largest difference of cumulative shares (Kolmogorov-Smirnov):");
+        println!("  bee-line: {}", format_or_na(self.ks_bee_line, 3, ""));
+        println!("  network:  {}", format_or_na(self.ks_network, 3, ""));
+    }
+
+    /// Write the shares of each distance band as CSV.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let mut writer = csv::Writer::from_path(&path)
+            .with_context(|| format!("Failed to create {:?}", path.as_ref()))?;
+        for row in &self.rows {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use crate::test_util::TempFile;
+
+    #[test]
+    fn test_log_normal() {
+        let dist = TripLength::log_normal(5000.0, 0.8).unwrap();
+        assert!((dist.mean() - 5000.0).abs() < 1e-6);
+        let median = 5000.0 * (-0.32f64).exp();
+        assert!((dist.cdf(median) - 0.5).abs() < 1e-6);
+        assert!((dist.cdf(dist.default_max_dist()) - 0.999).abs() < 1e-5);
+
+        // The density integrates to the cumulative distribution
+        let integral: f64 = (0..10000).map(|i| dist.pdf((i as f64 + 0.5) * 2.0) * 2.0).sum();
+        assert!((integral - dist.cdf(20000.0)).abs() < 1e-3);
+
+        assert!(TripLength::log_normal(-1.0, 0.8).is_err());
+        assert!(TripLength::log_normal(1000.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_sample_and_verify() {
+        let dist = TripLength::log_normal(3000.0, 0.6).unwrap();
+        let max_dist = dist.default_max_dist();
+        let mut rng = StdRng::seed_from_u64(7);
+        let samples: Vec<f64> = (0..20000).map(|_| dist.sample(&mut rng, max_dist)).collect();
+        assert!(samples.iter().all(|&d| d > 0.0 && d <= max_dist));
+        assert!((mean(&samples).unwrap() - 3000.0).abs() < 60.0);
+
+        let report = dist.verify(max_dist, &samples, &[], 10);
+        assert!(report.ks_bee_line.unwrap() < 0.02);
+        assert_eq!(report.rows.len(), 11);
+        let target: f64 = report.rows.iter().map(|r| r.target).sum();
+        assert!((target - 1.0).abs() < 1e-9);
+        for row in &report.rows {
+            assert!((row.target - row.bee_line).abs() < 0.02);
+        }
+
+        // Uniform distances do not match
+        let uniform: Vec<f64> = (0..1000).map(|i| i as f64 * max_dist / 1000.0).collect();
+        assert!(dist.verify(max_dist, &uniform, &[], 10).ks_bee_line.unwrap() > 0.3);
+
+        // No network distances, and a NaN does not panic
+        let report = dist.verify(max_dist, &[1000.0, f64::NAN], &[], 10);
+        assert_eq!((report.network_mean, report.ks_network), (None, None));
+        assert!(report.bee_line_mean.unwrap().is_nan());
+        assert!(report.ks_bee_line.is_some());
+    }
+
+    #[test]
+    fn test_normal_quantile() {
+        for &p in &[1e-6, 0.001, 0.02, 0.3, 0.5, 0.8, 0.99, 0.999_999] {
+            let z = normal_quantile(p);
+            assert!((0.5 * (1.0 + erf(z / std::f64::consts::SQRT_2)) - p).abs() < 2e-7);
+        }
+        assert!((normal_quantile(0.999) - Z_999).abs() < 1e-6);
+        assert_eq!(normal_quantile(0.5), 0.0);
+        assert_eq!(normal_quantile(0.0), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_sample_truncated() {
+        // Almost all of the distribution is beyond the maximum distance.
+        let dist = TripLength::log_normal(3000.0, 0.6).unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        let samples: Vec<f64> = (0..5000).map(|_| dist.sample(&mut rng, 500.0)).collect();
+        assert!(samples.iter().all(|&d| d > 0.0 && d <= 500.0));
+        assert!(dist.verify(500.0, &samples, &[], 10).ks_bee_line.unwrap() < 0.03);
+
+        // Truncated within the second band
+        let hist = TripLength::histogram(vec![0.0, 1000.0, 3000.0], vec![0.5, 0.5]).unwrap();
+        let samples: Vec<f64> = (0..5000).map(|_| hist.sample(&mut rng, 2000.0)).collect();
+        assert!(samples.iter().all(|&d| (0.0..=2000.0).contains(&d)));
+        let short = samples.iter().filter(|&&d| d < 1000.0).count() as f64 / 5000.0;
+        assert!((short - 2.0 / 3.0).abs() < 0.03);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let path = TempFile::new("histogram.csv");
+        std::fs::write(&path, "lower,upper,share\n0,1000,2\n1000,3000,4\n3000,10000,2\n").unwrap();
+        let dist = TripLength::from_csv(&path).unwrap();
+        assert_eq!(dist, TripLength::Histogram {
+            edges: vec![0.0, 1000.0, 3000.0, 10000.0],
+            shares: vec![0.25, 0.5, 0.25],
+        });
+        assert_eq!(dist.default_max_dist(), 10000.0);
+        assert!((dist.cdf(2000.0) - 0.5).abs() < 1e-9);
+        assert!((dist.pdf(500.0) - 0.25 / 1000.0).abs() < 1e-12);
+        assert!((dist.mean() - (0.25 * 500.0 + 0.5 * 2000.0 + 0.25 * 6500.0)).abs() < 1e-9);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let samples: Vec<f64> = (0..10000).map(|_| dist.sample(&mut rng, 10000.0)).collect();
+        let report = dist.verify(10000.0, &samples, &samples, 20);
+        // One row per band and one for longer distances
+        assert_eq!(report.rows.len(), 4);
+        assert!(report.ks_bee_line.unwrap() < 0.02);
+
+        std::fs::write(&path, "lower,upper,share\n0,1000,1\n2000,3000,1\n").unwrap();
+        assert!(TripLength::from_csv(&path).is_err());
+        assert!(TripLength::histogram(vec![0.0, 1000.0], vec![0.5, 0.5]).is_err());
+        assert!(TripLength::histogram(vec![], vec![]).is_err());
+        assert!(TripLength::histogram(vec![0.0, 2000.0, 1000.0], vec![0.5, 0.5]).is_err());
+        assert!(TripLength::histogram(vec![0.0, 1000.0, 1000.0], vec![0.5, 0.5]).is_err());
+        assert!(TripLength::histogram(vec![-10.0, 1000.0], vec![1.0]).is_err());
+        assert!(TripLength::histogram(vec![0.0, f64::INFINITY], vec![1.0]).is_err());
+        assert!(TripLength::histogram(vec![0.0, 1000.0, 2000.0], vec![2.0, -1.0]).is_err());
+    }
+
+    #[test]
+    fn test_calibration() {
+        let target = TripLength::histogram(vec![0.0, 2000.0, 4000.0, 8000.0], vec![0.3, 0.4, 0.3])
+            .unwrap();
+        let mut calibration = Calibration::new(&target, DistanceMeasure::Network, 2000).unwrap();
+        let mut sampler = target.clone();
+        let mut rng = StdRng::seed_from_u64(11);
+        let route = |distance: f64| Route {
+            start_coord: crate::route::LatLon32::new(52.5, 13.4),
+            end_coord: crate::route::LatLon32::new(52.5, 13.4),
+            node_ids: vec![],
+            distance,
+            duration: None,
+        };
+
+        // Routes are 30% longer than the bee-line distance that is sampled.
+        let mut differences = vec![];
+        for _ in 0..20 * 2000 {
+            let dist = sampler.sample(&mut rng, 8000.0) * 1.3;
+            if let Some(adjusted) = calibration.add_route(&route(dist)) {
+                sampler = adjusted;
+                differences.push(calibration.last_difference);
+            }
+        }
+        assert_eq!(differences.len(), 20);
+        assert!(differences[0] > 0.1);
+        assert!(*differences.last().unwrap() < 0.05);
+
+        let log_normal = TripLength::log_normal(1.0, 1.0).unwrap();
+        assert!(Calibration::new(&log_normal, DistanceMeasure::BeeLine, 1).is_err());
+    }
+}
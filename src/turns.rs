@@ -0,0 +1,218 @@
+//! Turning movement counts at intersections.
+//!
+//! Each movement is a triple of the previous node, the intersection and the next node of a route.
+//! Intersections are the nodes with more than two neighbors (see `PolylineCollection`).
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::Context;
+use geomatic::{laea, Point3035, Point4326};
+use serde::Serialize;
+
+use crate::geometry::Geometry;
+use crate::network::{Network, OsmNodeId};
+use crate::output::{self, Feature};
+use crate::route::RouteCollectionReader;
+use crate::route_filter::{Filter, RouteValues};
+
+
+/// Turns with an angle below this are straight ahead, above 180 - this they are U-turns.
+const STRAIGHT_DEGREES: f64 = 30.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Movement {
+    pub from: OsmNodeId,
+    pub via: OsmNodeId,
+    pub to: OsmNodeId,
+}
+
+#[derive(Serialize)]
+struct CsvRecord {
+    node: i64,
+    from_node: i64,
+    to_node: i64,
+    turn: &'static str,
+    angle: f64,
+    number: u64,
+    /// Share of all routes through the intersection
+    share: f64,
+}
+
+/// Signed angle of the turn from a-b to b-c in degrees, positive for left turns.
+fn turn_angle(a: Point3035, b: Point3035, c: Point3035) -> f64 {
+    let (x1, y1) = (b.coords.0 - a.coords.0, b.coords.1 - a.coords.1);
+    let (x2, y2) = (c.coords.0 - b.coords.0, c.coords.1 - b.coords.1);
+    (x1 * y2 - y1 * x2).atan2(x1 * x2 + y1 * y2).to_degrees()
+}
+
+fn turn_name(movement: &Movement, angle: f64) -> &'static str {
+    if movement.from == movement.to || angle.abs() > 180.0 - STRAIGHT_DEGREES {
+        "u-turn"
+    } else if angle.abs() < STRAIGHT_DEGREES {
+        "straight"
+    } else if angle > 0.0 {
+        "left"
+    } else {
+        "right"
+    }
+}
+
+fn midpoint(a: Point4326, b: Point4326) -> Point4326 {
+    Point4326::new((a.lat() + b.lat()) / 2.0, (a.lon() + b.lon()) / 2.0)
+}
+
+/// Counts of the movements at all intersections of a network.
+pub struct TurnCounts {
+    intersections: HashSet<OsmNodeId>,
+    counts: HashMap<(OsmNodeId, OsmNodeId, OsmNodeId), u64>,
+}
+
+impl TurnCounts {
+    pub fn new(intersections: HashSet<OsmNodeId>) -> Self {
+        TurnCounts {
+            intersections,
+            counts: HashMap::new(),
+        }
+    }
+
+    /// Count the movements at the intersections of the given network.
+    pub fn for_network(net: &Network) -> Self {
+        Self::new(net.build_polylines().intersections().clone())
+    }
+
+    /// Count the movements of a route at all intersections that it passes.
+    pub fn add_route(&mut self, node_ids: &[OsmNodeId]) {
+        for w in node_ids.windows(3) {
+            if self.intersections.contains(&w[1]) {
+                *self.counts.entry((w[0], w[1], w[2])).or_insert(0) += 1;
+            }
+        }
+    }
+
+    /// Count the movements of all routes of the given file that match the filter.
+    pub fn add_routes(&mut self, routes_path: &str, filter: Option<&Filter>) -> anyhow::Result<()> {
+        let reader = RouteCollectionReader::new(routes_path)
+            .with_context(|| format!("Failed to read the routes file {:?}", routes_path))?;
+        for (index, route) in reader.enumerate() {
+            let route = route?;
+            if let Some(filter) = filter {
+                if !filter.matches(&RouteValues::new(index as u64, &route)) {
+                    continue;
+                }
+            }
+            self.add_route(&route.node_ids);
+        }
+        Ok(())
+    }
+
+    /// All movements with their counts, sorted by intersection, incoming and outgoing node.
+    pub fn movements(&self) -> Vec<(Movement, u64)> {
+        let mut movements: Vec<_> = self.counts.iter()
+            .map(|(&(from, via, to), &number)| (Movement { from, via, to }, number))
+            .collect();
+        movements.sort_by_key(|(m, _)| (m.via.as_i64(), m.from.as_i64(), m.to.as_i64()));
+        movements
+    }
+
+    /// Number of routes through each intersection
+    fn totals(&self) -> HashMap<OsmNodeId, u64> {
+        let mut totals = HashMap::new();
+        for (&(_, via, _), &number) in &self.counts {
+            *totals.entry(via).or_insert(0) += number;
+        }
+        totals
+    }
+
+    /// Look up the coordinates of the three nodes of a movement.
+    fn points(net: &Network, m: &Movement) -> anyhow::Result<[Point4326; 3]> {
+        let point = |id: OsmNodeId| net.get_node(id)
+            .map(|n| n.as_point4326())
+            .ok_or_else(|| anyhow::anyhow!("Node {} is not part of the network", id.as_i64()));
+        Ok([point(m.from)?, point(m.via)?, point(m.to)?])
+    }
+
+    /// Write one row per movement with the turn direction and the share of all routes through
+    /// the intersection.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P, net: &Network) -> anyhow::Result<()> {
+        let mut writer = csv::Writer::from_path(&path)
+            .with_context(|| format!("Failed to create {:?}", path.as_ref()))?;
+        let totals = self.totals();
+        for (m, number) in self.movements() {
+            let [a, b, c] = Self::points(net, &m)?;
+            let angle = turn_angle(laea::forward(a), laea::forward(b), laea::forward(c));
+            writer.serialize(CsvRecord {
+                node: m.via.as_i64(),
+                from_node: m.from.as_i64(),
+                to_node: m.to.as_i64(),
+                turn: turn_name(&m, angle),
+                angle,
+                number,
+                share: number as f64 / totals[&m.via] as f64,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write each movement as a LineString from the middle of the incoming edge through the
+    /// intersection to the middle of the outgoing edge (see `output::create` for the formats).
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P, net: &Network, precision: usize)
+        -> anyhow::Result<()>
+    {
+        let mut writer = output::create(path)?;
+        writer.set_precision(precision);
+        let totals = self.totals();
+        for (m, number) in self.movements() {
+            let [a, b, c] = Self::points(net, &m)?;
+            let angle = turn_angle(laea::forward(a), laea::forward(b), laea::forward(c));
+            let line = vec![midpoint(a, b), b, midpoint(b, c)];
+            writer.write_feature(&Feature::new(Geometry::LineString(line))
+                .with_property("node", m.via.as_i64())
+                .with_property("from_node", m.from.as_i64())
+                .with_property("to_node", m.to.as_i64())
+                .with_property("turn", turn_name(&m, angle))
+                .with_property("angle", angle)
+                .with_property("number", number)
+                .with_property("share", number as f64 / totals[&m.via] as f64))?;
+        }
+        writer.finish()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_route() {
+        let id = OsmNodeId::new;
+        let mut turns = TurnCounts::new(vec![id(2), id(5)].into_iter().collect());
+        turns.add_route(&[id(1), id(2), id(3)]);
+        turns.add_route(&[id(4), id(2), id(3), id(5)]);
+        turns.add_route(&[id(1), id(2), id(3)]);
+        // Routes that start or end at an intersection have no movement there
+        turns.add_route(&[id(2), id(5)]);
+
+        let movements: Vec<_> = turns.movements().into_iter()
+            .map(|(m, n)| (m.from.as_i64(), m.via.as_i64(), m.to.as_i64(), n))
+            .collect();
+        assert_eq!(movements, vec![(1, 2, 3, 2), (4, 2, 3, 1)]);
+        assert_eq!(turns.totals()[&id(2)], 3);
+    }
+
+    #[test]
+    fn test_turn_name() {
+        let p = Point3035::new;
+        let m = Movement { from: OsmNodeId::new(1), via: OsmNodeId::new(2), to: OsmNodeId::new(3) };
+        let check = |c: Point3035| turn_name(&m, turn_angle(p(0.0, 0.0), p(0.0, 10.0), c));
+        // Heading north
+        assert_eq!(check(p(1.0, 20.0)), "straight");
+        assert_eq!(check(p(-10.0, 10.0)), "left");
+        assert_eq!(check(p(10.0, 12.0)), "right");
+        assert_eq!(check(p(1.0, 0.0)), "u-turn");
+        let back = Movement { to: OsmNodeId::new(1), ..m };
+        assert_eq!(turn_name(&back, 90.0), "u-turn");
+    }
+}